@@ -1,7 +1,8 @@
 use clap::ArgMatches;
 use snafu::Snafu;
 
-use crate::manifest::{AddedFile, State};
+use crate::deploy;
+use crate::manifest::{AddedFile, DeployMode, State};
 use crate::utils;
 use log::{debug, info, warn};
 use std::path::PathBuf;
@@ -36,14 +37,14 @@ pub fn add_files(
         add_file(&PathBuf::from(file), state_info, matches)?;
     }
 
-    state_info.data.populate_file(state_info);
+    state_info.data.populate_file(state_info)?;
 
     Ok(())
 }
 
 fn only_retain_correct_files(files: &mut Vec<PathBuf>, state_info: &mut State) {
     files.retain(|file| {
-        if !std::path::Path::new(file).is_file() {
+        if !std::path::Path::new(file).is_file() && !std::path::Path::new(file).is_dir() {
             info!(
                 "The file {} doesn't exist, and thus cannot become great (be added). Skipping....",
                 file.display()
@@ -64,9 +65,15 @@ fn only_retain_correct_files(files: &mut Vec<PathBuf>, state_info: &mut State) {
             return false;
         }
 
-        if file.symlink_metadata().unwrap().file_type().is_symlink() {
+        // Symlinks into the packed repository are files we've deployed
+        // before; anything else would be tracking someone else's file.
+        if file.symlink_metadata().unwrap().file_type().is_symlink()
+            && !std::fs::read_link(file)
+                .unwrap_or_default()
+                .starts_with(&state_info.greatness_git_pack_dir)
+        {
             warn!(
-                "The file {} is a symlink. Greatness cannot handle symlinks. Skipping....",
+                "The file {} is a symlink to outside of greatness. Greatness cannot handle those. Skipping....",
                 file.display()
             );
 
@@ -80,13 +87,52 @@ fn only_retain_correct_files(files: &mut Vec<PathBuf>, state_info: &mut State) {
 fn add_file(
     file: &PathBuf,
     state: &mut State,
-    _matches: &ArgMatches,
+    matches: &ArgMatches,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let special_file = utils::relative_to_special(&file)?;
+    let mut added = AddedFile::from(special_file.clone());
+
+    if let Some(mode) = matches.value_of("deploy") {
+        added.deploy = mode.parse()?;
+    } else if file.symlink_metadata()?.file_type().is_symlink() {
+        added.deploy = DeployMode::Symlink;
+    }
+
+    let include = matches
+        .values_of("include")
+        .map(|v| v.map(|g| g.to_owned()).collect::<Vec<String>>());
+    let exclude = matches
+        .values_of("exclude")
+        .map(|v| v.map(|g| g.to_owned()).collect::<Vec<String>>());
+
+    if file.is_dir() {
+        debug!("{} is a directory, tracking recursively....", file.display());
+
+        added.directory = true;
+        added.include = include;
+        added.exclude = exclude;
+    } else if include.is_some() || exclude.is_some() {
+        warn!(
+            "The file {} is not a directory, so --include and --exclude mean nothing to it....",
+            file.display()
+        );
+    }
+
+    // Record the metadata now, so that it survives even without a pack
+    let live = file.canonicalize()?;
+    for expanded_file in added.expand(&live)? {
+        let metadata = deploy::metadata_of(&utils::special_to_absolute(&expanded_file))?;
+        added.set_metadata_for(&expanded_file, metadata);
+    }
+
+    if added.directory {
+        added.metadata = Some(deploy::metadata_of(&live)?);
+    }
+
     if let Some(ref mut files) = state.data.files {
-        files.push(AddedFile::from(special_file.clone()));
+        files.push(added);
     } else {
-        state.data.files = Some(vec![AddedFile::from(special_file.clone())]);
+        state.data.files = Some(vec![added]);
     }
 
     Ok(())
@@ -0,0 +1,173 @@
+use crate::backup;
+use crate::manifest::{Manifest, State};
+use crate::package;
+use crate::plan::{self, Action};
+use crate::pull;
+use crate::pull::installed::Installed;
+use crate::pull::lock::Lock;
+use crate::script;
+use crate::tag::selector;
+use crate::utils;
+use clap::ArgMatches;
+use log::{debug, info};
+use snafu::{ResultExt, Snafu};
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+pub enum ApplyError {
+    #[snafu(display("Nothing has been packed into {} yet! Pack, or pull the git repository first.", dir.display()))]
+    NothingPacked {
+        dir: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Deploy the packed repository onto this machine. This is the
+/// reverse of `pack`, and is what to run after pulling the git
+/// repository on another machine.
+pub fn apply(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    backup::set_reason("apply");
+    let selector = selector::parse(matches.value_of("only-with-tag"))?;
+
+    let base = state.greatness_git_pack_dir.clone();
+    let mut packed_state = State::new(base.clone())?;
+
+    if !packed_state.greatness_state.exists() {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context(NothingPacked { dir: &base })?;
+    }
+
+    packed_state.data = Manifest::read_from_file(&packed_state)?;
+
+    adopt_manifest(state, &packed_state.data)?;
+    adopt_lock(state, &packed_state)?;
+    unpack_scripts(state, &base)?;
+
+    let mut files_dir = base.clone();
+    files_dir.push("files");
+    pull::add::install_files(
+        matches,
+        &files_dir,
+        state,
+        &packed_state.data,
+        "main",
+        selector.as_ref(),
+    )?;
+
+    // It's our own repository, so there is no need to be wary of scripts
+    debug!("Running the scripts of the packed repository....");
+    script::jog::jog(&mut packed_state, selector.as_ref())?;
+
+    if matches.is_present("packages") {
+        debug!("--packages specified, installing packages....");
+        let packages = package::jog::jog(matches, &packed_state)?;
+        Installed::record_packages(state, "main", &packages)?;
+    }
+
+    Ok(())
+}
+
+/// Make the packed manifest the main one, backing up the main one if
+/// it is any different.
+fn adopt_manifest(state: &mut State, packed: &Manifest) -> Result<(), Box<dyn std::error::Error>> {
+    if &state.data == packed {
+        return Ok(());
+    }
+
+    info!(
+        "The packed greatness state differs from {}, which is being replaced. A backup WILL be made!",
+        state.greatness_state.display()
+    );
+    backup::backup_file(&state.greatness_state)?;
+
+    state.data = packed.clone();
+    state.data.populate_file(state)?;
+
+    Ok(())
+}
+
+/// Make the packed lock file the main one, so that pulling the required
+/// states gets the same commits as on the machine that packed them.
+fn adopt_lock(state: &State, packed_state: &State) -> Result<(), Box<dyn std::error::Error>> {
+    let packed = Lock::read(packed_state)?;
+    if Lock::read(state)? == packed {
+        return Ok(());
+    }
+
+    packed.write(state)
+}
+
+/// Copy the packed scripts back into the scripts directory, which
+/// is where the manifest says they are.
+fn unpack_scripts(state: &State, base: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let mut from = base.clone();
+    from.push("scripts");
+
+    if !from.exists() {
+        return Ok(());
+    }
+
+    debug!(
+        "Unpacking script directory from {} -> {}....",
+        from.display(),
+        state.greatness_scripts_dir.display(),
+    );
+
+    if plan::record(Action::WriteFile {
+        path: state.greatness_scripts_dir.clone(),
+        from: Some(from.clone()),
+    }) {
+        return Ok(());
+    }
+
+    let mut options = fs_extra::dir::CopyOptions::new();
+    options.overwrite = true;
+    options.content_only = true;
+
+    std::fs::create_dir_all(&state.greatness_scripts_dir).context(utils::DirCreationError {
+        dir: &state.greatness_scripts_dir,
+    })?;
+    fs_extra::dir::copy(&from, &state.greatness_scripts_dir, &options)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pull::lock::Locked;
+
+    #[test]
+    fn the_packed_lock_is_adopted() {
+        let main = tempfile::tempdir().unwrap();
+        let packed = tempfile::tempdir().unwrap();
+        let state = State::new(main.path().to_path_buf()).unwrap();
+        let packed_state = State::new(packed.path().to_path_buf()).unwrap();
+
+        let mut lock = Lock::default();
+        lock.set(Locked {
+            name: "shared".to_owned(),
+            url: "https://example.com/shared.git".to_owned(),
+            commit: "abc".to_owned(),
+        });
+        lock.write(&packed_state).unwrap();
+        adopt_lock(&state, &packed_state).unwrap();
+
+        assert_eq!(Lock::read(&state).unwrap(), lock);
+    }
+
+    #[test]
+    fn packed_scripts_are_unpacked() {
+        let main = tempfile::tempdir().unwrap();
+        let packed = tempfile::tempdir().unwrap();
+        let state = State::new(main.path().to_path_buf()).unwrap();
+        let base = packed.path().to_path_buf();
+        std::fs::create_dir_all(base.join("scripts/sub")).unwrap();
+        std::fs::write(base.join("scripts/sub/fix.sh"), "echo fixed").unwrap();
+
+        unpack_scripts(&state, &base).unwrap();
+
+        let unpacked = state.greatness_scripts_dir.join("sub/fix.sh");
+        assert_eq!(std::fs::read_to_string(unpacked).unwrap(), "echo fixed");
+    }
+}
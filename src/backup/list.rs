@@ -0,0 +1,63 @@
+use crate::backup;
+use crate::utils;
+use clap::ArgMatches;
+use log::info;
+
+/// Lists every backup, oldest first.
+pub fn list(_matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let ids = backup::all();
+    if ids.is_empty() {
+        info!("\x1b[1mNo backups yet!\x1b[0m");
+        return Ok(());
+    }
+
+    for id in ids {
+        let index = backup::read_index(&id)?;
+
+        let mut reasons = index
+            .files
+            .iter()
+            .map(|f| f.reason.as_str())
+            .collect::<Vec<&str>>();
+        reasons.dedup();
+
+        info!(
+            "\x1b[1m{}\x1b[0m: {} file(s), from {}",
+            id,
+            index.files.len(),
+            if reasons.is_empty() {
+                "nothing".to_owned()
+            } else {
+                reasons.join(", ")
+            }
+        );
+    }
+
+    Ok(())
+}
+
+/// Shows what is in a backup.
+pub fn show(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let id = backup::find(matches.value_of("id").unwrap())?;
+    let index = backup::read_index(&id)?;
+
+    info!("Backup \x1b[1m{}\x1b[0m:", id);
+    if let Some(created) = backup::created(&id) {
+        info!("\tmade: {}", created);
+    }
+
+    for file in &index.files {
+        info!(
+            "\tpath: {}",
+            utils::special_to_absolute(&file.path).display()
+        );
+        info!("\t\treason: {}", file.reason);
+        info!("\t\thash: {}", file.hash);
+        info!(
+            "\t\tstored at: {}",
+            backup::stored_path(&id, &file.path).display()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,250 @@
+pub mod list;
+pub mod prune;
+pub mod restore;
+
+use crate::plan::{self, Action};
+use crate::utils;
+use chrono::{Local, NaiveDateTime};
+use lazy_static::lazy_static;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// How backup directories are named, which is also when they were made.
+const ID_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum BackupError {
+    #[snafu(display("Great backup {} doesn't exist! See `great backup list`.", id))]
+    NoSuchBackup { id: String, source: std::io::Error },
+
+    #[snafu(display("Failed to read great backup index {}: {}", file.display(), source))]
+    IndexReadError {
+        file: PathBuf,
+        source: serde_yaml::Error,
+    },
+}
+
+/// The index of a single backup. A backup holds every file that was
+/// replaced during one run of great.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct BackupIndex {
+    #[serde(default)]
+    pub files: Vec<BackedUpFile>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct BackedUpFile {
+    /// Where the file was, as a special path.
+    pub path: PathBuf,
+    /// What replaced it, such as `pull github.com/user/dotfiles`.
+    pub reason: String,
+    /// SHA-256 of the backed up contents.
+    pub hash: String,
+}
+
+/// Where backups go during this run of great.
+struct Session {
+    /// Usually ~/.greatness/backups.
+    store: Option<PathBuf>,
+    /// Backup being written to. Made on the first backup of the run.
+    current: Option<String>,
+    reason: String,
+}
+
+lazy_static! {
+    static ref SESSION: Mutex<Session> = Mutex::new(Session {
+        store: None,
+        current: None,
+        reason: "unknown".to_owned(),
+    });
+}
+
+/// Set where backups are kept. Until this is called, backups can't be made.
+pub fn use_store(store: PathBuf) {
+    SESSION.lock().unwrap().store = Some(store);
+}
+
+/// Set why files are being backed up from now on, such as `apply`.
+pub fn set_reason(reason: impl Into<String>) {
+    SESSION.lock().unwrap().reason = reason.into();
+}
+
+/// Gets where backups are kept.
+pub fn store() -> PathBuf {
+    SESSION
+        .lock()
+        .unwrap()
+        .store
+        .clone()
+        .expect("the backup store is set before anything is backed up")
+}
+
+/// Gets the directory of a backup.
+pub fn dir_of(id: &str) -> PathBuf {
+    store().join(id)
+}
+
+/// Gets where a backed up file is kept inside of its backup.
+pub fn stored_path(id: &str, special: &PathBuf) -> PathBuf {
+    let mut files_dir = dir_of(id);
+    files_dir.push("files");
+
+    utils::special_in_dir(&files_dir, special)
+}
+
+/// Gets the ids of every backup, oldest first.
+pub fn all() -> Vec<String> {
+    let mut ids = std::fs::read_dir(store())
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().is_dir())
+                .filter_map(|e| e.file_name().to_str().map(str::to_owned))
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+    ids.sort();
+
+    ids
+}
+
+/// Gets the id of a backup, resolving `latest` to the newest one.
+pub fn find(id: &str) -> Result<String, BackupError> {
+    let id = match id {
+        "latest" => all().pop().unwrap_or_default(),
+        id => id.to_owned(),
+    };
+
+    if id.is_empty() || !dir_of(&id).is_dir() {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context(NoSuchBackup { id });
+    }
+
+    Ok(id)
+}
+
+/// Gets when a backup was made, from its id.
+pub fn created(id: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(id.get(..15)?, ID_FORMAT).ok()
+}
+
+pub fn read_index(id: &str) -> Result<BackupIndex, Box<dyn std::error::Error>> {
+    let file = dir_of(id).join("index.yaml");
+    let contents = std::fs::read_to_string(&file).context(utils::FileReadError { file: &file })?;
+
+    Ok(serde_yaml::from_str(&contents).context(IndexReadError { file: &file })?)
+}
+
+fn write_index(id: &str, index: &BackupIndex) -> Result<(), Box<dyn std::error::Error>> {
+    let file = dir_of(id).join("index.yaml");
+    utils::write_atomically(&file, serde_yaml::to_string(index)?.as_bytes())
+        .context(utils::FileWriteError { file: &file })?;
+
+    Ok(())
+}
+
+/// Gets the backup of this run, making it if needed. Backups are named
+/// after when they were made, so two in the same second get a suffix.
+fn current() -> Result<String, Box<dyn std::error::Error>> {
+    if let Some(id) = &SESSION.lock().unwrap().current {
+        return Ok(id.clone());
+    }
+
+    let now = Local::now().format(ID_FORMAT).to_string();
+    let mut id = now.clone();
+    let mut n = 1;
+    while dir_of(&id).exists() {
+        id = format!("{}-{}", now, n);
+        n += 1;
+    }
+
+    if !plan::is_dry_run() {
+        let dir = dir_of(&id);
+        std::fs::create_dir_all(&dir).context(utils::DirCreationError { dir: &dir })?;
+        write_index(&id, &BackupIndex::default())?;
+    }
+
+    SESSION.lock().unwrap().current = Some(id.clone());
+
+    Ok(id)
+}
+
+/// Back up a file that is about to be replaced, into this run's backup.
+/// A file backed up twice in one run keeps its first backup, which is
+/// the file as it was before great touched it.
+pub fn backup_file(original: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let id = current()?;
+    let special = utils::absolute_to_special(original);
+    let to = stored_path(&id, &special);
+
+    if plan::record(Action::Backup {
+        path: original.clone(),
+        to: to.clone(),
+    }) {
+        return Ok(());
+    }
+
+    let mut index = read_index(&id)?;
+    if index.files.iter().any(|f| f.path == special) {
+        debug!("{} is already backed up in {}....", original.display(), id);
+        return Ok(());
+    }
+
+    debug!("Backing up {} -> {}....", original.display(), to.display());
+    std::fs::create_dir_all(to.parent().unwrap()).context(utils::DirCreationError {
+        dir: to.parent().unwrap(),
+    })?;
+    std::fs::copy(original, &to).context(utils::FileCopyError {
+        src: original,
+        dest: &to,
+    })?;
+
+    index.files.push(BackedUpFile {
+        path: special,
+        reason: SESSION.lock().unwrap().reason.clone(),
+        hash: utils::hash_file(&to)?,
+    });
+    write_index(&id, &index)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn created_is_read_from_the_id() {
+        let made = created("20200102-030405").unwrap();
+        assert_eq!(made.format(ID_FORMAT).to_string(), "20200102-030405");
+        assert_eq!(created("20200102-030405-1"), Some(made));
+        assert_eq!(created("not a backup"), None);
+    }
+
+    #[test]
+    fn a_file_keeps_its_first_backup() {
+        let store = tempfile::tempdir().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        use_store(store.path().to_path_buf());
+        set_reason("test");
+
+        let file = home.path().join(".bashrc");
+        std::fs::write(&file, "before").unwrap();
+        backup_file(&file).unwrap();
+        std::fs::write(&file, "after").unwrap();
+        backup_file(&file).unwrap();
+
+        let id = find("latest").unwrap();
+        let index = read_index(&id).unwrap();
+        assert_eq!(index.files.len(), 1);
+        assert_eq!(index.files[0].reason, "test");
+
+        let stored = stored_path(&id, &index.files[0].path);
+        assert_eq!(std::fs::read_to_string(stored).unwrap(), "before");
+        assert!(find("19990101-000000").is_err());
+    }
+}
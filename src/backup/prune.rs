@@ -0,0 +1,43 @@
+use crate::backup;
+use crate::plan::{self, Action};
+use crate::utils;
+use chrono::{Duration, Local};
+use clap::ArgMatches;
+use log::info;
+use snafu::ResultExt;
+
+/// Removes old backups. Backups are removed if they are older than
+/// `--older-than` days, or beyond the newest `--keep`.
+pub fn prune(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let keep = matches.value_of_t::<usize>("keep").ok();
+    let older_than = matches.value_of_t::<i64>("older-than").ok();
+
+    let ids = backup::all();
+    let keep_from = ids.len().saturating_sub(keep.unwrap_or(ids.len()));
+    let cutoff = older_than.map(|days| Local::now().naive_local() - Duration::days(days));
+
+    let mut pruned = 0;
+    for (n, id) in ids.iter().enumerate() {
+        let too_old = match (cutoff, backup::created(id)) {
+            (Some(cutoff), Some(created)) => created < cutoff,
+            _ => false,
+        };
+
+        if n >= keep_from && !too_old {
+            continue;
+        }
+
+        let dir = backup::dir_of(id);
+        info!("Pruning backup {}....", id);
+        pruned += 1;
+        if plan::record(Action::Remove { path: dir.clone() }) {
+            continue;
+        }
+
+        std::fs::remove_dir_all(&dir).context(utils::DirDeletionError { dir: &dir })?;
+    }
+
+    info!("Pruned {} backup(s)!", pruned);
+
+    Ok(())
+}
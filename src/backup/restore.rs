@@ -0,0 +1,123 @@
+use crate::backup;
+use crate::plan::{self, Action};
+use crate::utils;
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use snafu::ResultExt;
+use std::path::PathBuf;
+
+/// Puts the files of a backup back where they were. Whatever is there
+/// now is backed up first, so a restore can itself be undone.
+pub fn restore(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let id = backup::find(matches.value_of("id").unwrap())?;
+    let index = backup::read_index(&id)?;
+
+    let only = matches
+        .values_of("paths")
+        .into_iter()
+        .flatten()
+        .map(|p| utils::any_to_special(&PathBuf::from(p)))
+        .collect::<Result<Vec<PathBuf>, std::io::Error>>()?;
+
+    backup::set_reason(format!("restore {}", id));
+
+    for file in &index.files {
+        if !only.is_empty() && !only.iter().any(|o| file.path.starts_with(o)) {
+            continue;
+        }
+
+        let stored = backup::stored_path(&id, &file.path);
+        let to = utils::special_to_absolute(&file.path);
+
+        if utils::hash_file(&stored).ok().as_ref() != Some(&file.hash) {
+            warn!(
+                "The backup of {} in {} was changed or is missing, skipping....",
+                to.display(),
+                id
+            );
+            continue;
+        }
+
+        if utils::hash_file(&to).ok().as_ref() == Some(&file.hash) {
+            debug!("{} is already restored!", to.display());
+            continue;
+        }
+
+        info!("Restoring {} from {}....", to.display(), id);
+        restore_file(&stored, &to, true)?;
+    }
+
+    Ok(())
+}
+
+/// Copy a backed up file back into place. If `keep_current`, a file in
+/// the way is backed up. Links are replaced rather than written
+/// through, as they may point into the packed repository.
+pub fn restore_file(
+    stored: &PathBuf,
+    to: &PathBuf,
+    keep_current: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if to.symlink_metadata().is_ok() {
+        if keep_current && to.is_file() {
+            backup::backup_file(to)?;
+        }
+
+        if !plan::record(Action::Remove { path: to.clone() }) {
+            std::fs::remove_file(to).context(utils::FileDeletionError { file: to })?;
+        }
+    }
+
+    if plan::record(Action::WriteFile {
+        path: to.clone(),
+        from: Some(stored.clone()),
+    }) {
+        return Ok(());
+    }
+
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent).context(utils::DirCreationError { dir: parent })?;
+    }
+
+    std::fs::copy(stored, to).context(utils::FileCopyError {
+        src: stored,
+        dest: to,
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_replaced_not_written_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let stored = dir.path().join("stored");
+        let packed = dir.path().join("packed");
+        let to = dir.path().join("home").join(".bashrc");
+        std::fs::write(&stored, "backed up").unwrap();
+        std::fs::write(&packed, "packed").unwrap();
+        std::fs::create_dir_all(to.parent().unwrap()).unwrap();
+        std::os::unix::fs::symlink(&packed, &to).unwrap();
+
+        restore_file(&stored, &to, false).unwrap();
+
+        assert!(!to.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "backed up");
+        assert_eq!(std::fs::read_to_string(&packed).unwrap(), "packed");
+    }
+
+    #[test]
+    fn missing_parents_are_made() {
+        let dir = tempfile::tempdir().unwrap();
+        let stored = dir.path().join("stored");
+        let to = dir.path().join("a").join("b").join("file");
+        std::fs::write(&stored, "backed up").unwrap();
+
+        restore_file(&stored, &to, false).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "backed up");
+    }
+}
@@ -0,0 +1,213 @@
+pub mod set;
+
+use crate::manifest::Condition;
+use log::debug;
+use nix::unistd::{Uid, User};
+use std::collections::HashMap;
+
+lazy_static! {
+    /// Facts about the machine greatness is running on.
+    pub static ref FACTS: Facts = Facts::gather();
+}
+
+/// Facts about a machine, which conditions (and templates) are
+/// checked against.
+#[derive(Debug, Clone)]
+pub struct Facts {
+    /// Host name, as in `hostname`.
+    pub hostname: String,
+    /// Operating system, such as linux or macos.
+    pub os: String,
+    /// ID of the distribution in os-release, such as arch or ubuntu.
+    pub distro: Option<String>,
+    /// Distributions this one is like (ID_LIKE in os-release).
+    pub distro_like: Vec<String>,
+    /// CPU architecture, such as x86_64 or aarch64.
+    pub arch: String,
+    /// Name of the user running greatness.
+    pub user: String,
+}
+
+impl Facts {
+    /// Gathers the facts of the machine we are on.
+    pub fn gather() -> Self {
+        let os_release = read_os_release();
+
+        let facts = Self {
+            hostname: hostname(),
+            os: std::env::consts::OS.to_owned(),
+            distro: os_release.get("ID").cloned(),
+            distro_like: os_release
+                .get("ID_LIKE")
+                .map(|l| l.split_whitespace().map(|d| d.to_owned()).collect())
+                .unwrap_or_default(),
+            arch: std::env::consts::ARCH.to_owned(),
+            user: User::from_uid(Uid::current())
+                .ok()
+                .flatten()
+                .map(|u| u.name)
+                .or_else(|| std::env::var("USER").ok())
+                .unwrap_or_default(),
+        };
+
+        debug!("Gathered great facts about this machine: {:?}", facts);
+
+        facts
+    }
+}
+
+impl Condition {
+    /// Checks if the machine we are on matches the condition.
+    pub fn is_met(&self) -> bool {
+        self.is_met_by(&FACTS, |name| std::env::var(name).ok())
+    }
+
+    /// Checks the condition against some facts, and a way of looking
+    /// up environment variables.
+    pub fn is_met_by(&self, facts: &Facts, env: impl Fn(&str) -> Option<String>) -> bool {
+        let any = |wanted: &Option<Vec<String>>, actual: &[&str]| match wanted {
+            Some(wanted) => wanted.iter().any(|w| actual.contains(&w.as_str())),
+            None => true,
+        };
+
+        let mut distros: Vec<&str> = facts.distro.iter().map(|d| d.as_str()).collect();
+        distros.extend(facts.distro_like.iter().map(|d| d.as_str()));
+
+        let env_matches = self.env.iter().flatten().all(|(name, wanted)| {
+            match (env(name), wanted) {
+                (Some(value), Some(wanted)) => &value == wanted,
+                (Some(_), None) => true,
+                (None, _) => false,
+            }
+        });
+
+        any(&self.hostname, &[&facts.hostname])
+            && any(&self.os, &[&facts.os])
+            && any(&self.distro, &distros)
+            && any(&self.arch, &[&facts.arch])
+            && any(&self.user, &[&facts.user])
+            && env_matches
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+
+        for (name, values) in &[
+            ("hostname", &self.hostname),
+            ("os", &self.os),
+            ("distro", &self.distro),
+            ("arch", &self.arch),
+            ("user", &self.user),
+        ] {
+            if let Some(values) = values {
+                parts.push(format!("{} is {}", name, values.join(" or ")));
+            }
+        }
+
+        for (name, value) in self.env.iter().flatten() {
+            match value {
+                Some(value) => parts.push(format!("${} is {}", name, value)),
+                None => parts.push(format!("${} is set", name)),
+            }
+        }
+
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Checks if an entry with an (optional) condition is active on the
+/// machine we are on. Entries without conditions are everywhere.
+pub fn is_active(when: &Option<Condition>) -> bool {
+    when.as_ref().map_or(true, |w| w.is_met())
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+
+    match nix::unistd::gethostname(&mut buf) {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(_) => std::env::var("HOSTNAME").unwrap_or_default(),
+    }
+}
+
+/// Reads os-release, which has lines like ID=arch or NAME="Arch Linux".
+fn read_os_release() -> HashMap<String, String> {
+    let mut ret = HashMap::new();
+
+    for file in &["/etc/os-release", "/usr/lib/os-release"] {
+        if let Ok(contents) = std::fs::read_to_string(file) {
+            for line in contents.lines() {
+                if let Some((key, value)) = line.split_once('=') {
+                    ret.insert(
+                        key.trim().to_owned(),
+                        value.trim().trim_matches('"').trim_matches('\'').to_owned(),
+                    );
+                }
+            }
+
+            break;
+        }
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> Facts {
+        Facts {
+            hostname: "laptop".to_owned(),
+            os: "linux".to_owned(),
+            distro: Some("pop".to_owned()),
+            distro_like: vec!["ubuntu".to_owned(), "debian".to_owned()],
+            arch: "x86_64".to_owned(),
+            user: "milo".to_owned(),
+        }
+    }
+
+    fn is_met(condition: &str, env: &[(&str, &str)]) -> bool {
+        let condition: Condition = serde_yaml::from_str(condition).unwrap();
+        condition.is_met_by(&facts(), |name| {
+            env.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn distros_match_by_what_they_are_like() {
+        assert!(is_met("distro: pop", &[]));
+        assert!(is_met("distro: debian", &[]));
+        assert!(is_met("distro: [arch, ubuntu]", &[]));
+        assert!(!is_met("distro: arch", &[]));
+    }
+
+    #[test]
+    fn every_part_has_to_match() {
+        assert!(is_met("{}", &[]));
+        assert!(is_met("os: linux\nuser: [root, milo]", &[]));
+        assert!(!is_met("os: linux\nhostname: desktop", &[]));
+    }
+
+    #[test]
+    fn env_without_a_value_only_has_to_be_set() {
+        let condition = "env:\n  WORK: ~";
+
+        assert!(is_met(condition, &[("WORK", "")]));
+        assert!(is_met(condition, &[("WORK", "yes")]));
+        assert!(!is_met(condition, &[]));
+    }
+
+    #[test]
+    fn env_with_a_value_has_to_equal_it() {
+        let condition = "env:\n  SHELL: /bin/zsh";
+
+        assert!(is_met(condition, &[("SHELL", "/bin/zsh")]));
+        assert!(!is_met(condition, &[("SHELL", "/bin/bash")]));
+        assert!(!is_met(condition, &[]));
+    }
+}
@@ -0,0 +1,95 @@
+use crate::manifest::{Condition, State};
+use crate::utils;
+use clap::ArgMatches;
+use snafu::{ResultExt, Snafu};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+pub enum WhenError {
+    #[snafu(display("Great package {} is not added!", package))]
+    PackageNotAdded {
+        package: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Invalid environment condition {}. Please specify as NAME=value, or just NAME!",
+        input
+    ))]
+    InvalidEnvInput {
+        input: String,
+        source: std::io::Error,
+    },
+}
+
+/// Sets (or clears) the condition of (a) file(s), or package(s)
+/// if `--package` is given.
+pub fn set(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let when = if matches.is_present("clear") {
+        None
+    } else {
+        Some(condition_from(matches)?)
+    };
+
+    for entry in matches.values_of("entries").unwrap() {
+        if matches.is_present("package") {
+            match state.data.contains_package(entry.to_owned()) {
+                Some(package) => package.when = when.clone(),
+                None => {
+                    return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                        .context(PackageNotAdded { package: entry })?;
+                }
+            }
+        } else {
+            let file = PathBuf::from(entry);
+            let normalized_file = utils::relative_to_special(&file)?;
+            let mut contains = match state.data.contains(&normalized_file) {
+                Some(c) => c.0.clone(),
+                None => {
+                    return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                        .context(utils::FileNotTracked { file: &file })?;
+                }
+            };
+
+            contains.when = when.clone();
+
+            state.data.add_file(contains);
+        }
+    }
+
+    state.data.populate_file(state)?;
+
+    Ok(())
+}
+
+fn condition_from(matches: &ArgMatches) -> Result<Condition, Box<dyn std::error::Error>> {
+    let values = |name: &str| {
+        matches
+            .values_of(name)
+            .map(|v| v.map(|v| v.to_owned()).collect::<Vec<String>>())
+    };
+
+    let mut env = BTreeMap::new();
+    for input in matches.values_of("env").into_iter().flatten() {
+        match input.split_once('=') {
+            Some((name, value)) if !name.is_empty() => {
+                env.insert(name.to_owned(), Some(value.to_owned()))
+            }
+            None if !input.is_empty() => env.insert(input.to_owned(), None),
+            _ => {
+                return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+                    .context(InvalidEnvInput { input })?;
+            }
+        };
+    }
+
+    Ok(Condition {
+        hostname: values("hostname"),
+        os: values("os"),
+        distro: values("distro"),
+        arch: values("arch"),
+        user: values("user"),
+        env: if env.is_empty() { None } else { Some(env) },
+    })
+}
@@ -0,0 +1,98 @@
+use crate::crypt;
+use crate::deploy;
+use crate::manifest::{DeployMode, State};
+use crate::plan::{self, Action};
+use crate::utils;
+use clap::ArgMatches;
+use git2::Repository;
+use log::{info, warn};
+use snafu::ResultExt;
+use std::path::PathBuf;
+
+/// Marks (a) file(s) as encrypted. Only their ciphertext is packed,
+/// and they are decrypted whenever they are installed. Copies that
+/// are already packed are encrypted straight away.
+pub fn add(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files_dir = state.greatness_git_pack_dir.clone();
+    files_dir.push("files");
+
+    for file in matches.values_of("files").unwrap() {
+        let file = PathBuf::from(file);
+        let normalized_file = utils::relative_to_special(&file)?;
+        let mut contains = match state.data.contains(&normalized_file) {
+            Some(c) => c.0.clone(),
+            None => {
+                return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                    .context(utils::FileNotTracked { file: &file })?;
+            }
+        };
+
+        if contains.encrypted {
+            info!("Great file {} is already encrypted!", file.display());
+            continue;
+        }
+
+        // A link would point at the ciphertext
+        if contains.deploy != DeployMode::Copy {
+            warn!(
+                "Encrypted great file {} will be copied instead of {}ed!",
+                file.display(),
+                contains.deploy
+            );
+            contains.deploy = DeployMode::Copy;
+        }
+
+        let packed = utils::special_in_dir(&files_dir, &normalized_file);
+        if packed.exists() {
+            for expanded in contains.expand(&packed)? {
+                let packed = utils::special_in_dir(&files_dir, &expanded);
+                let live = utils::special_to_absolute(&expanded);
+                if plan::record(Action::WriteFile {
+                    path: packed.clone(),
+                    from: None,
+                }) {
+                    continue;
+                }
+
+                // Links would show the ciphertext, so they become copies
+                if deploy::same_file(&packed, &live) {
+                    let contents = std::fs::read(&live).context(utils::FileReadError { file: &live })?;
+                    let metadata = deploy::metadata_of(&live)?;
+                    std::fs::remove_file(&live).context(utils::FileDeletionError { file: &live })?;
+                    deploy::deploy_contents(&contents, &live, Some(&metadata))?;
+                }
+
+                crypt::convert_in_place(&packed, true)?;
+            }
+        }
+
+        if is_committed(state, &normalized_file) {
+            warn!(
+                "Great file {} was already committed, so its secrets stay in the git history! Change them, or rewrite the history.",
+                file.display()
+            );
+        }
+
+        contains.encrypted = true;
+        info!("Great file {} will be packed encrypted!", file.display());
+
+        state.data.add_file(contains);
+    }
+
+    state.data.populate_file(state)?;
+
+    Ok(())
+}
+
+/// Whether the packed copy of a (special) path is committed to the
+/// packed repository.
+fn is_committed(state: &State, special: &PathBuf) -> bool {
+    let committed = || -> Option<bool> {
+        let repo = Repository::open(&state.greatness_git_pack_dir).ok()?;
+        let tree = repo.head().ok()?.peel_to_tree().ok()?;
+
+        Some(tree.get_path(&utils::special_in_dir(&PathBuf::from("files"), special)).is_ok())
+    };
+
+    committed().unwrap_or(false)
+}
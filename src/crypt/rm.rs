@@ -0,0 +1,56 @@
+use crate::crypt;
+use crate::manifest::State;
+use crate::plan::{self, Action};
+use crate::utils;
+use clap::ArgMatches;
+use log::warn;
+use snafu::ResultExt;
+use std::path::PathBuf;
+
+/// Stops encrypting (a) file(s). Copies that are already packed are
+/// decrypted straight away.
+pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files_dir = state.greatness_git_pack_dir.clone();
+    files_dir.push("files");
+
+    for file in matches.values_of("files").unwrap() {
+        let file = PathBuf::from(file);
+        let normalized_file = utils::relative_to_special(&file)?;
+        let mut contains = match state.data.contains(&normalized_file) {
+            Some(c) => c.0.clone(),
+            None => {
+                return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                    .context(utils::FileNotTracked { file: &file })?;
+            }
+        };
+
+        if !contains.encrypted {
+            continue;
+        }
+
+        let packed = utils::special_in_dir(&files_dir, &normalized_file);
+        if packed.exists() {
+            for expanded in contains.expand(&packed)? {
+                let packed = utils::special_in_dir(&files_dir, &expanded);
+                if !plan::record(Action::WriteFile {
+                    path: packed.clone(),
+                    from: None,
+                }) {
+                    crypt::convert_in_place(&packed, false)?;
+                }
+            }
+        }
+
+        warn!(
+            "Great file {} is no longer encrypted, so its secrets will be in the git history once pushed!",
+            file.display()
+        );
+        contains.encrypted = false;
+
+        state.data.add_file(contains);
+    }
+
+    state.data.populate_file(state)?;
+
+    Ok(())
+}
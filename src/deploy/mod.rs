@@ -0,0 +1,306 @@
+pub mod set;
+
+use crate::manifest::{DeployMode, FileMetadata};
+use crate::plan::{self, Action};
+use crate::pull::transaction;
+use crate::utils;
+use log::{debug, warn};
+use nix::sys::time::{TimeVal, TimeValLike};
+use nix::unistd::{Uid, User};
+use snafu::{ResultExt, Snafu};
+use std::fs::Permissions;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+/// Errors pretaining to deploying files onto the system
+pub enum DeployError {
+    #[snafu(display("Failed to symlink great file {} -> {}: {}", link.display(), target.display(), source))]
+    SymlinkError {
+        link: PathBuf,
+        target: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to remove {} to make way for a great file: {}", file.display(), source))]
+    MakeWayError {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to save great file {} before replacing it: {}", file.display(), source))]
+    JournalError {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to set the permissions of great file {}: {}", file.display(), source))]
+    PermissionsError {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to set the modification time of great file {}: {}", file.display(), source))]
+    TimesError { file: PathBuf, source: nix::Error },
+
+    #[snafu(display("{}", source))]
+    CommonError { source: utils::CommonErrors },
+}
+
+/// Put a file from inside of a repository (`from`) at its place
+/// on the system (`to`), the way `mode` says to. Whatever is at
+/// `to` already is replaced; make a backup first if it matters.
+/// Recorded metadata is restored once the file is in place.
+pub fn deploy(
+    from: &PathBuf,
+    to: &PathBuf,
+    mode: DeployMode,
+    metadata: Option<&FileMetadata>,
+) -> Result<(), DeployError> {
+    debug!(
+        "Deploying great file {} to {} ({:?})....",
+        from.display(),
+        to.display(),
+        mode
+    );
+
+    let planned = match mode {
+        DeployMode::Copy => Action::WriteFile {
+            path: to.clone(),
+            from: Some(from.clone()),
+        },
+        _ => Action::Link {
+            path: to.clone(),
+            target: from.clone(),
+            hard: mode == DeployMode::Hardlink,
+        },
+    };
+    if plan::record(planned) {
+        return Ok(());
+    }
+    transaction::touch(to).context(JournalError { file: to })?;
+
+    // Never write through an old symlink into the repository,
+    // and links can't be made over the top of existing files.
+    let is_link = to
+        .symlink_metadata()
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    if is_link || same_file(from, to) || (mode != DeployMode::Copy && to.exists()) {
+        std::fs::remove_file(&to).context(MakeWayError { file: to })?;
+    }
+
+    match mode {
+        DeployMode::Copy => match metadata {
+            Some(metadata) => copy_with_mode(from, to, metadata.mode)?,
+            None => {
+                std::fs::copy(&from, &to)
+                    .context(utils::FileCopyError { src: from, dest: to })
+                    .context(CommonError {})?;
+            }
+        },
+
+        DeployMode::Symlink => {
+            let target = from
+                .canonicalize()
+                .context(utils::NoFileExistsError { file: from })
+                .context(CommonError {})?;
+
+            std::os::unix::fs::symlink(&target, &to).context(SymlinkError {
+                link: to,
+                target: &target,
+            })?;
+        }
+
+        DeployMode::Hardlink => {
+            if let Err(e) = std::fs::hard_link(&from, &to) {
+                // Most likely the repository is on another file system
+                warn!(
+                    "Could not hardlink {} to {} ({}), copying instead....",
+                    to.display(),
+                    from.display(),
+                    e
+                );
+
+                std::fs::copy(&from, &to)
+                    .context(utils::FileCopyError { src: from, dest: to })
+                    .context(CommonError {})?;
+            }
+        }
+    }
+
+    if let Some(metadata) = metadata {
+        restore_metadata(to, metadata)?;
+    }
+
+    Ok(())
+}
+
+/// Put generated contents, such as a rendered template, at `to`.
+/// Like `deploy`, whatever is at `to` already is replaced.
+pub fn deploy_contents(
+    contents: &[u8],
+    to: &PathBuf,
+    metadata: Option<&FileMetadata>,
+) -> Result<(), DeployError> {
+    debug!("Writing great contents to {}....", to.display());
+
+    if plan::record(Action::WriteFile {
+        path: to.clone(),
+        from: None,
+    }) {
+        return Ok(());
+    }
+    transaction::touch(to).context(JournalError { file: to })?;
+
+    let is_link = to
+        .symlink_metadata()
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    if is_link {
+        std::fs::remove_file(&to).context(MakeWayError { file: to })?;
+    }
+
+    let mode = metadata.map_or(0o644, |m| m.mode);
+    write_with_mode(&mut &contents[..], to, mode)
+        .context(utils::FileWriteError { file: to })
+        .context(CommonError {})?;
+
+    if let Some(metadata) = metadata {
+        restore_metadata(to, metadata)?;
+    }
+
+    Ok(())
+}
+
+/// Copy a file, making sure the copy never has looser permissions
+/// than `mode`, not even for a moment.
+fn copy_with_mode(from: &PathBuf, to: &PathBuf, mode: u32) -> Result<(), DeployError> {
+    let copy = || -> Result<(), std::io::Error> {
+        write_with_mode(&mut std::fs::File::open(&from)?, to, mode)
+    };
+
+    copy()
+        .context(utils::FileCopyError { src: from, dest: to })
+        .context(CommonError {})?;
+
+    Ok(())
+}
+
+fn write_with_mode(
+    src: &mut impl std::io::Read,
+    to: &PathBuf,
+    mode: u32,
+) -> Result<(), std::io::Error> {
+    if to.exists() {
+        std::fs::set_permissions(&to, Permissions::from_mode(mode))?;
+    }
+
+    let mut dest = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&to)?;
+
+    std::io::copy(src, &mut dest)?;
+
+    Ok(())
+}
+
+/// Read the metadata of a file, so that it can be recorded.
+pub fn metadata_of(file: &PathBuf) -> Result<FileMetadata, utils::CommonErrors> {
+    let metadata = file
+        .metadata()
+        .context(utils::NoFileExistsError { file })?;
+    let owner = User::from_uid(Uid::from_raw(metadata.uid()))
+        .ok()
+        .flatten()
+        .map(|u| u.name);
+
+    Ok(FileMetadata {
+        mode: metadata.mode() & 0o7777,
+        owner,
+        mtime: Some(metadata.mtime()),
+        hash: None,
+        packed_hash: None,
+    })
+}
+
+/// Restore recorded metadata onto a file. Only root can give files
+/// away, so failing to restore the owner is just a warning.
+pub fn restore_metadata(file: &PathBuf, metadata: &FileMetadata) -> Result<(), DeployError> {
+    if plan::is_dry_run() {
+        return Ok(());
+    }
+
+    std::fs::set_permissions(&file, Permissions::from_mode(metadata.mode))
+        .context(PermissionsError { file })?;
+
+    if let Some(mtime) = metadata.mtime {
+        let atime = file.metadata().map(|m| m.atime()).unwrap_or(mtime);
+
+        nix::sys::stat::utimes(
+            file.as_path(),
+            &TimeVal::seconds(atime),
+            &TimeVal::seconds(mtime),
+        )
+        .context(TimesError { file })?;
+    }
+
+    if let Some(owner) = &metadata.owner {
+        match User::from_name(owner) {
+            Ok(Some(user)) => {
+                let current = file.metadata().map(|m| m.uid()).unwrap_or(0);
+                if current != user.uid.as_raw() {
+                    if let Err(e) = nix::unistd::chown(file.as_path(), Some(user.uid), None) {
+                        warn!(
+                            "Could not give great file {} to {}: {}",
+                            file.display(),
+                            owner,
+                            e
+                        );
+                    }
+                }
+            }
+
+            _ => debug!(
+                "Great user {} doesn't exist here, leaving the owner of {} alone....",
+                owner,
+                file.display()
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks if `to` is already deployed from `from`, the way `mode`
+/// says to. Copies are never considered deployed, as they can drift.
+pub fn is_deployed(from: &PathBuf, to: &PathBuf, mode: DeployMode) -> bool {
+    match mode {
+        DeployMode::Copy => false,
+
+        DeployMode::Symlink => match (std::fs::read_link(to), from.canonicalize()) {
+            (Ok(target), Ok(from)) => target == from,
+            _ => false,
+        },
+
+        DeployMode::Hardlink => {
+            match (to.symlink_metadata(), from.symlink_metadata()) {
+                (Ok(to), Ok(from)) => to.dev() == from.dev() && to.ino() == from.ino(),
+                _ => false,
+            }
+        }
+    }
+}
+
+/// Checks if two paths are the very same file on disk, following
+/// symlinks. Copying one onto the other would empty it.
+pub fn same_file(a: &PathBuf, b: &PathBuf) -> bool {
+    match (a.metadata(), b.metadata()) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
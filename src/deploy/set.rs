@@ -0,0 +1,50 @@
+use crate::manifest::{DeployMode, State};
+use crate::utils;
+use clap::ArgMatches;
+use log::warn;
+use snafu::ResultExt;
+use std::path::PathBuf;
+
+/// Changes how (a) file(s) are deployed onto the system. Takes
+/// effect on the next pack, apply or pull.
+pub fn set(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let mode: DeployMode = matches.value_of("mode").unwrap().parse()?;
+
+    for file in matches.values_of("files").unwrap() {
+        set_file(PathBuf::from(file), mode, state)?;
+    }
+
+    state.data.populate_file(state)?;
+
+    Ok(())
+}
+
+fn set_file(
+    file: PathBuf,
+    mode: DeployMode,
+    state: &mut State,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let normalized_file = utils::relative_to_special(&file)?;
+    let mut contains = match state.data.contains(&normalized_file) {
+        Some(c) => c.0.clone(),
+        None => {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                .context(utils::FileNotTracked { file: &file })?;
+        }
+    };
+
+    // A link would point at the ciphertext
+    if contains.encrypted && mode != DeployMode::Copy {
+        warn!(
+            "Great file {} is encrypted, so it is always copied!",
+            file.display()
+        );
+        return Ok(());
+    }
+
+    contains.deploy = mode;
+
+    state.data.add_file(contains);
+
+    Ok(())
+}
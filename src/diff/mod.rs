@@ -0,0 +1,337 @@
+use crate::condition;
+use crate::crypt;
+use crate::manifest::{AddedFile, State};
+use crate::tag::selector;
+use crate::template;
+use crate::utils;
+use clap::ArgMatches;
+use git2::{Repository, Tree};
+use log::{debug, info};
+use serde_yaml::Value;
+use snafu::{ResultExt, Snafu};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+pub enum DiffError {
+    #[snafu(display("The greatness git repository doesn't exist yet! Please pack first."))]
+    NoRepository { source: std::io::Error },
+
+    #[snafu(display("Failed to find great revision {}: {}", rev, source))]
+    RevisionError { rev: String, source: git2::Error },
+
+    #[snafu(display("Failed to read {} at great revision {}: {}", file.display(), rev, source))]
+    BlobError {
+        file: PathBuf,
+        rev: String,
+        source: git2::Error,
+    },
+
+    #[snafu(display("Great external diff {} can't be split into words: {}", command, source))]
+    InvalidExternal {
+        command: String,
+        source: shell_words::ParseError,
+    },
+
+    #[snafu(display("Failed to run great external diff {}: {}", command, source))]
+    ExternalError {
+        command: String,
+        source: subprocess::PopenError,
+    },
+}
+
+/// One side of a diff.
+struct Side {
+    /// What to call it in the diff header.
+    label: String,
+    /// Its contents, if it exists at all.
+    contents: Option<Vec<u8>>,
+}
+
+/// Prints the differences between tracked files on the system and
+/// their packed copies (or their copies at a git revision). Templates
+/// are compared by what they render to.
+pub fn diff(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let filters = matches
+        .values_of("paths")
+        .into_iter()
+        .flatten()
+        .map(|p| utils::any_to_special(&PathBuf::from(p)))
+        .collect::<Result<Vec<PathBuf>, std::io::Error>>()?;
+    let selector = selector::parse(matches.value_of("tag"))?;
+    let rev = matches.value_of("rev");
+    let external = matches.value_of("external");
+
+    let tree = match rev {
+        Some(rev) => {
+            let repo = match &state.repository {
+                Some(r) => r,
+                None => {
+                    return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                        .context(NoRepository {})?
+                }
+            };
+
+            Some((repo, tree_at(repo, rev)?))
+        }
+        None => None,
+    };
+
+    let mut files_dir = state.greatness_git_pack_dir.clone();
+    files_dir.push("files");
+
+    let variables = template::variables(&state.data, state)?;
+    let mut differences = 0;
+
+    for file in state.data.files.iter().flatten() {
+        if !selector.as_ref().map_or(true, |s| s.matches(&file.tags)) {
+            continue;
+        }
+
+        if !condition::is_active(&file.when) {
+            continue;
+        }
+
+        for special in expand_both(file, &files_dir)? {
+            if !filters.is_empty() && !filters.iter().any(|f| special.starts_with(f)) {
+                continue;
+            }
+
+            let live = utils::special_to_absolute(&special);
+            let packed_contents = match &tree {
+                Some((repo, tree)) => blob_at(repo, tree, &special, rev.unwrap())?,
+                None => std::fs::read(utils::special_in_dir(&files_dir, &special)).ok(),
+            };
+            let packed_contents = match (file.encrypted, packed_contents) {
+                (true, Some(c)) => Some(crypt::decrypt(&c, &special)?),
+                (_, contents) => contents,
+            };
+
+            let packed = Side {
+                label: format!("a/{}", special.display()),
+                contents: match (file.template, packed_contents) {
+                    (true, Some(t)) => Some(
+                        render_template(&t, &special, &variables)?.into_bytes(),
+                    ),
+                    (_, contents) => contents,
+                },
+            };
+            let live = Side {
+                label: format!("b/{}", special.display()),
+                contents: std::fs::read(&live).ok(),
+            };
+
+            if packed.contents == live.contents {
+                continue;
+            }
+
+            differences += 1;
+            match external {
+                Some(command) => run_external(command, &special, &packed, &live)?,
+                None => print_diff(&packed, &live),
+            }
+        }
+    }
+
+    if differences == 0 {
+        info!("No differences, which is great!");
+    }
+
+    Ok(())
+}
+
+/// Gets every (special) path of an entry, whether it is on the system
+/// or in the packed repository, so that removed files show up too.
+fn expand_both(file: &AddedFile, files_dir: &PathBuf) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let live = utils::special_to_absolute(&file.path);
+    let packed = utils::special_in_dir(files_dir, &file.path);
+
+    let mut expanded = vec![];
+    for root in &[live, packed] {
+        if root.exists() {
+            for special in file.expand(root)? {
+                if !expanded.contains(&special) {
+                    expanded.push(special);
+                }
+            }
+        }
+    }
+
+    if expanded.is_empty() && !file.directory {
+        expanded.push(file.path.clone());
+    }
+
+    expanded.sort();
+
+    Ok(expanded)
+}
+
+fn tree_at<'a>(repo: &'a Repository, rev: &str) -> Result<Tree<'a>, DiffError> {
+    repo.revparse_single(rev)
+        .and_then(|o| o.peel_to_tree())
+        .context(RevisionError { rev })
+}
+
+/// Reads the packed copy of a file at a revision, if it was there.
+fn blob_at(
+    repo: &Repository,
+    tree: &Tree,
+    special: &PathBuf,
+    rev: &str,
+) -> Result<Option<Vec<u8>>, DiffError> {
+    let path = utils::special_in_dir(&PathBuf::from("files"), special);
+    let entry = match tree.get_path(&path) {
+        Ok(e) => e,
+        Err(_) => return Ok(None),
+    };
+
+    let blob = entry
+        .to_object(repo)
+        .and_then(|o| o.peel_to_blob())
+        .context(BlobError { file: &path, rev })?;
+
+    Ok(Some(blob.content().to_vec()))
+}
+
+fn render_template(
+    template: &[u8],
+    special: &PathBuf,
+    variables: &Value,
+) -> Result<String, Box<dyn std::error::Error>> {
+    Ok(template::render_str(
+        &String::from_utf8_lossy(template),
+        special,
+        variables,
+    )?)
+}
+
+fn print_diff(old: &Side, new: &Side) {
+    let (old_label, new_label) = (
+        old.contents.as_ref().map_or("/dev/null".to_owned(), |_| old.label.clone()),
+        new.contents.as_ref().map_or("/dev/null".to_owned(), |_| new.label.clone()),
+    );
+
+    let (old_text, new_text) = match (as_text(&old.contents), as_text(&new.contents)) {
+        (Some(old), Some(new)) => (old, new),
+        _ => {
+            println!("Binary files {} and {} differ", old_label, new_label);
+            return;
+        }
+    };
+
+    let patch = diffy::create_patch(old_text, new_text);
+    let formatter = if nix::unistd::isatty(1).unwrap_or(false) {
+        diffy::PatchFormatter::new().with_color()
+    } else {
+        diffy::PatchFormatter::new()
+    };
+
+    // Swap diffy's own header for one that names the files
+    let formatted = formatter.fmt_patch(&patch).to_string();
+    let hunks = formatted.splitn(3, '\n').nth(2).unwrap_or("");
+
+    println!("--- {}\n+++ {}", old_label, new_label);
+    print!("{}", hunks);
+}
+
+/// Gets the contents of a file as text, unless it's binary. Files that
+/// don't exist are empty.
+fn as_text(contents: &Option<Vec<u8>>) -> Option<&str> {
+    match contents {
+        None => Some(""),
+        Some(contents) => utils::as_text(contents),
+    }
+}
+
+/// Runs an external diff tool on the two sides, as `command old new`.
+/// Sides that aren't files on disk as they are are written out first.
+fn run_external(
+    command: &str,
+    special: &PathBuf,
+    old: &Side,
+    new: &Side,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Quoted words are kept together, like in a shell
+    let words = shell_words::split(command).context(InvalidExternal { command })?;
+    let (program, args) = match words.split_first() {
+        Some((program, args)) => (program.as_str(), args),
+        None => (command, &[][..]),
+    };
+
+    // The packed side may be the plaintext of an encrypted file
+    let tmp_dir = crypt::private_tmp_dir()?;
+
+    let name = special.file_name().unwrap_or_default().to_str().unwrap_or("file");
+    let mut paths = vec![];
+    for (prefix, side) in &[("a", old), ("b", new)] {
+        match &side.contents {
+            Some(contents) => {
+                let mut path = tmp_dir.clone();
+                path.push(format!("{}-{}", prefix, name));
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&path)
+                    .and_then(|mut f| f.write_all(contents))
+                    .context(utils::FileWriteError { file: &path })?;
+                paths.push(path);
+            }
+            None => paths.push(PathBuf::from("/dev/null")),
+        }
+    }
+
+    debug!("Running great external diff {} on {}....", command, special.display());
+
+    let result = subprocess::Exec::cmd(program)
+        .args(args)
+        .arg(&paths[0])
+        .arg(&paths[1])
+        .join()
+        .context(ExternalError { command });
+
+    let _ = std::fs::remove_dir_all(&tmp_dir);
+    result?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn side(contents: Option<&str>) -> Side {
+        Side {
+            label: "side".to_owned(),
+            contents: contents.map(|c| c.as_bytes().to_vec()),
+        }
+    }
+
+    #[test]
+    fn missing_files_are_empty_and_binary_files_are_not_text() {
+        assert_eq!(as_text(&None), Some(""));
+        assert_eq!(as_text(&Some(b"text".to_vec())), Some("text"));
+        assert_eq!(as_text(&Some(vec![0, 159, 146, 150])), None);
+    }
+
+    #[test]
+    fn external_diffs_get_both_sides() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let command = format!("sh -c 'cat \"$0\" \"$1\" > \"{}\"'", out.display());
+
+        let special = PathBuf::from("~/.bashrc");
+        run_external(&command, &special, &side(Some("old\n")), &side(None)).unwrap();
+        run_external(
+            &command,
+            &special,
+            &side(Some("old\n")),
+            &side(Some("new\n")),
+        )
+        .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "old\nnew\n");
+        assert!(run_external("sh -c 'unclosed", &special, &side(None), &side(None)).is_err());
+    }
+}
@@ -1,4 +1,9 @@
-use crate::manifest::{AddedFile, State};
+use crate::crypt;
+use crate::deploy;
+use crate::manifest::{AddedFile, DeployMode, Requirement, State};
+use crate::pull::lock::Lock;
+use crate::pull::source::Source;
+use crate::pack;
 use crate::utils;
 use clap::ArgMatches;
 use log::debug;
@@ -9,6 +14,11 @@ use std::path::PathBuf;
 ///     1. Simular tag names
 ///     2. Non-existant files
 ///     3. Non-existant scripts
+///     4. Files that should be linked into the packed repository, but aren't
+///     5. Files whose permissions differ from the recorded ones
+///     6. Files that changed since they were last packed
+///     7. Files that look like they hold secrets, but aren't encrypted
+///     8. Required states that aren't pulled, or aren't locked
 pub fn doctor(state: &State, _matches: &ArgMatches) -> Option<Vec<String>> {
     let mut warnings = vec![];
 
@@ -36,14 +46,19 @@ pub fn doctor(state: &State, _matches: &ArgMatches) -> Option<Vec<String>> {
             // Dotfile checks
             {
                 check_single_dotfile_existance(&mut warnings, &file);
+                check_deployment(&mut warnings, state, &file);
+                check_metadata(&mut warnings, &file);
+                check_drift(&mut warnings, &file);
+                check_secrets(&mut warnings, &file);
             }
         }
     } else {
         debug!("No files to check!");
     }
 
-    if let Some(_required) = &state.data.requires {
+    if let Some(required) = &state.data.requires {
         debug!("Checking requirments....");
+        check_requirements(&mut warnings, state, required);
     } else {
         debug!("No requirements to check!");
     }
@@ -93,3 +108,118 @@ fn check_single_dotfile_existance(warnings: &mut Vec<String>, file: &AddedFile)
         ));
     }
 }
+
+fn check_deployment(warnings: &mut Vec<String>, state: &State, file: &AddedFile) {
+    if file.deploy == DeployMode::Copy {
+        return;
+    }
+
+    let mut files_dir = state.greatness_git_pack_dir.clone();
+    files_dir.push("files");
+
+    // Missing files are already reported by the existance check
+    let expanded = match file.expand(&utils::special_to_absolute(&file.path)) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for expanded_file in expanded {
+        let packed = utils::special_in_dir(&files_dir, &expanded_file);
+        let live = utils::special_to_absolute(&expanded_file);
+
+        if !deploy::is_deployed(&packed, &live, file.deploy) {
+            warnings.push(format!(
+                "File {} should be a {} to {}, but isn't! Pack or apply to fix this.",
+                live.display(),
+                file.deploy,
+                packed.display()
+            ));
+        }
+    }
+}
+
+fn check_metadata(warnings: &mut Vec<String>, file: &AddedFile) {
+    let expanded = match file.expand(&utils::special_to_absolute(&file.path)) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for expanded_file in expanded {
+        let recorded = match file.metadata_for(&expanded_file) {
+            Some(r) => r,
+            None => continue,
+        };
+
+        let live = utils::special_to_absolute(&expanded_file);
+        if let Ok(metadata) = deploy::metadata_of(&live) {
+            if metadata.mode != recorded.mode {
+                warnings.push(format!(
+                    "File {} has mode {:o}, but was recorded with mode {:o}!",
+                    live.display(),
+                    metadata.mode,
+                    recorded.mode
+                ));
+            }
+
+            if recorded.owner.is_some() && metadata.owner != recorded.owner {
+                warnings.push(format!(
+                    "File {} is owned by {}, but was recorded as owned by {}!",
+                    live.display(),
+                    metadata.owner.unwrap_or("nobody".to_owned()),
+                    recorded.owner.clone().unwrap()
+                ));
+            }
+        }
+    }
+}
+
+fn check_drift(warnings: &mut Vec<String>, file: &AddedFile) {
+    for drifted in pack::drifted(file) {
+        warnings.push(format!(
+            "File {} changed since it was last packed! Pack to keep the changes.",
+            utils::special_to_absolute(&drifted).display()
+        ));
+    }
+}
+
+fn check_secrets(warnings: &mut Vec<String>, file: &AddedFile) {
+    if file.encrypted {
+        return;
+    }
+
+    let expanded = match file.expand(&utils::special_to_absolute(&file.path)) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for expanded_file in expanded {
+        let live = utils::special_to_absolute(&expanded_file);
+        if crypt::looks_secret(&live) {
+            warnings.push(format!(
+                "File {} looks like it holds secrets, but isn't encrypted! Encrypt it with `great encrypt add`.",
+                live.display()
+            ));
+        }
+    }
+}
+
+fn check_requirements(warnings: &mut Vec<String>, state: &State, required: &Vec<Requirement>) {
+    let lock = Lock::read(state).unwrap_or_default();
+
+    for requirement in required {
+        if !Source::dest(state, &requirement.name).exists() {
+            warnings.push(format!(
+                "Required state {} isn't pulled! Pull it with `great pull add {}`.",
+                requirement.name, requirement.url
+            ));
+        } else if let (Source::Git(_), None) = (
+            Source::parse(&requirement.url),
+            lock.commit_of(&requirement.name, &requirement.url),
+        ) {
+            warnings.push(format!(
+                "Required state {} isn't locked, so other machines may pull a different commit of it!",
+                requirement.name
+            ));
+        }
+    }
+}
@@ -0,0 +1,340 @@
+//! Archives of the whole greatness setup, for machines without
+//! internet, or to attach to bug reports. An archive holds the packed
+//! repository as it is, under `greatness/`, so the paths in it keep
+//! their placeholders, and it can be installed under any home.
+
+use crate::manifest::State;
+use crate::plan::{self, Action};
+use chrono::Local;
+use clap::ArgMatches;
+use log::{debug, info};
+use snafu::{ResultExt, Snafu};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The directory everything in an archive is under.
+pub const ARCHIVE_ROOT: &str = "greatness";
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum ExportError {
+    #[snafu(display("Invalid great archive format {}! Expected tar.gz, tar.zst or zip: {}", found, source))]
+    InvalidFormat {
+        found: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Nothing is packed yet, so there is nothing to export! Please pack first."))]
+    NotPacked { source: std::io::Error },
+
+    #[snafu(display("Failed to write great archive {}: {}", file.display(), source))]
+    ArchiveError {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to write great zip archive {}: {}", file.display(), source))]
+    ZipError {
+        file: PathBuf,
+        source: zip::result::ZipError,
+    },
+}
+
+/// Kinds of archive.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl FromStr for Format {
+    type Err = ExportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tar.gz" | "tgz" => Ok(Self::TarGz),
+            "tar.zst" | "tzst" => Ok(Self::TarZst),
+            "zip" => Ok(Self::Zip),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+                .context(InvalidFormat { found: s }),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TarGz => write!(f, "tar.gz"),
+            Self::TarZst => write!(f, "tar.zst"),
+            Self::Zip => write!(f, "zip"),
+        }
+    }
+}
+
+impl Format {
+    /// Guess the format of an archive from its name.
+    pub fn of(file: &PathBuf) -> Option<Self> {
+        let name = file.file_name()?.to_str()?;
+
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the name of an archive without its extension.
+    pub fn strip(&self, name: &str) -> String {
+        [".tar.gz", ".tgz", ".tar.zst", ".tzst", ".zip"]
+            .iter()
+            .find_map(|extension| name.strip_suffix(extension))
+            .unwrap_or(name)
+            .to_owned()
+    }
+}
+
+/// Something to put in an archive; where it is, and its path in the
+/// archive.
+struct Entry {
+    path: PathBuf,
+    name: PathBuf,
+}
+
+/// Export the packed repository, and optionally the pulled states, to
+/// an archive.
+pub fn export(matches: &ArgMatches, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+    let format = Format::from_str(matches.value_of("format").unwrap())?;
+
+    if !state.greatness_git_pack_dir.join("greatness.yaml").exists() {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotFound)).context(NotPacked {})?;
+    }
+
+    let output = match matches.value_of("output") {
+        Some(output) => PathBuf::from(output),
+        None => PathBuf::from(format!(
+            "greatness-{}.{}",
+            Local::now().format("%Y%m%d-%H%M%S"),
+            format
+        )),
+    };
+
+    let mut entries = entries_of(&state.greatness_git_pack_dir, &PathBuf::from(ARCHIVE_ROOT))?;
+    if matches.is_present("pulled") && state.greatness_pulled_dir.exists() {
+        entries.extend(entries_of(
+            &state.greatness_pulled_dir,
+            &PathBuf::from(ARCHIVE_ROOT).join("pulled"),
+        )?);
+    }
+
+    if plan::record(Action::WriteFile {
+        path: output.clone(),
+        from: None,
+    }) {
+        return Ok(());
+    }
+
+    // Written next to where it goes, so a failed export leaves nothing half written
+    let mut partial = output.clone().into_os_string();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+
+    let file = std::fs::File::create(&partial).context(ArchiveError { file: &output })?;
+    let written = match format {
+        Format::TarGz => {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            write_tar(encoder, &entries, &output)?
+                .finish()
+                .map(|_| ())
+                .context(ArchiveError { file: &output })
+        }
+        Format::TarZst => {
+            let encoder = zstd::Encoder::new(file, 0).context(ArchiveError { file: &output })?;
+            write_tar(encoder, &entries, &output)?
+                .finish()
+                .map(|_| ())
+                .context(ArchiveError { file: &output })
+        }
+        Format::Zip => write_zip(file, &entries, &output),
+    };
+
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e)?;
+    }
+
+    std::fs::rename(&partial, &output).context(ArchiveError { file: &output })?;
+    info!(
+        "Exported {} great files to {}!",
+        entries.iter().filter(|e| e.path.is_file()).count(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// Everything in a directory except for git's own files, named as if
+/// `dir` was `name`.
+fn entries_of(dir: &PathBuf, name: &PathBuf) -> Result<Vec<Entry>, Box<dyn std::error::Error>> {
+    let mut entries = vec![];
+    for entry in walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git")
+    {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(dir).unwrap();
+
+        entries.push(Entry {
+            path: entry.path().to_path_buf(),
+            name: name.join(relative),
+        });
+    }
+
+    Ok(entries)
+}
+
+fn write_tar<W: Write>(
+    writer: W,
+    entries: &Vec<Entry>,
+    output: &PathBuf,
+) -> Result<W, ExportError> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+
+    for entry in entries {
+        debug!("Archiving {} as {}....", entry.path.display(), entry.name.display());
+        builder
+            .append_path_with_name(&entry.path, &entry.name)
+            .context(ArchiveError { file: output })?;
+    }
+
+    builder.into_inner().context(ArchiveError { file: output })
+}
+
+fn write_zip(
+    file: std::fs::File,
+    entries: &Vec<Entry>,
+    output: &PathBuf,
+) -> Result<(), ExportError> {
+    let mut zip = zip::ZipWriter::new(file);
+
+    for entry in entries {
+        debug!("Archiving {} as {}....", entry.path.display(), entry.name.display());
+        let metadata = entry
+            .path
+            .symlink_metadata()
+            .context(ArchiveError { file: &entry.path })?;
+        let options = zip::write::FileOptions::default()
+            .unix_permissions(metadata.permissions().mode() & 0o777);
+        let name = entry.name.to_str().unwrap();
+
+        if metadata.is_dir() {
+            zip.add_directory(name, options)
+                .context(ZipError { file: output })?;
+            continue;
+        }
+
+        zip.start_file(name, options).context(ZipError { file: output })?;
+        let contents = std::fs::read(&entry.path).context(ArchiveError { file: &entry.path })?;
+        zip.write_all(&contents).context(ArchiveError { file: output })?;
+    }
+
+    zip.finish().context(ZipError { file: output })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A packed repository, with a file, a directory and git's own files.
+    fn packed() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join(".git/objects")).unwrap();
+        std::fs::create_dir_all(dir.path().join("files/{{HOME}}")).unwrap();
+        std::fs::write(dir.path().join("greatness.yaml"), "version: 1").unwrap();
+        std::fs::write(dir.path().join("files/{{HOME}}/.bashrc"), "alias ls=exa").unwrap();
+
+        dir
+    }
+
+    fn names(entries: &[Entry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|e| e.name.to_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn formats_are_known_by_name() {
+        assert_eq!(Format::from_str("tgz").unwrap(), Format::TarGz);
+        assert_eq!(Format::from_str("tar.zst").unwrap(), Format::TarZst);
+        assert!(Format::from_str("rar").is_err());
+
+        assert_eq!(
+            Format::of(&PathBuf::from("dots.tar.gz")),
+            Some(Format::TarGz)
+        );
+        assert_eq!(Format::of(&PathBuf::from("dots.zip")), Some(Format::Zip));
+        assert_eq!(Format::of(&PathBuf::from("dots")), None);
+        assert_eq!(Format::Zip.strip("dots.tar.zst"), "dots");
+    }
+
+    #[test]
+    fn git_is_not_exported() {
+        let dir = packed();
+        let entries = entries_of(&dir.path().to_path_buf(), &PathBuf::from(ARCHIVE_ROOT)).unwrap();
+
+        assert_eq!(
+            names(&entries),
+            vec![
+                format!("{}/files", ARCHIVE_ROOT),
+                format!("{}/files/{{{{HOME}}}}", ARCHIVE_ROOT),
+                format!("{}/files/{{{{HOME}}}}/.bashrc", ARCHIVE_ROOT),
+                format!("{}/greatness.yaml", ARCHIVE_ROOT),
+            ]
+        );
+    }
+
+    #[test]
+    fn tars_hold_every_entry() {
+        let dir = packed();
+        let entries = entries_of(&dir.path().to_path_buf(), &PathBuf::from(ARCHIVE_ROOT)).unwrap();
+        let tar = write_tar(vec![], &entries, &PathBuf::from("dots.tar")).unwrap();
+
+        let mut archive = tar::Archive::new(tar.as_slice());
+        let archived = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let path = e.unwrap().path().unwrap().to_path_buf();
+                path.to_str().unwrap().trim_end_matches('/').to_owned()
+            })
+            .collect::<Vec<String>>();
+        assert_eq!(archived, names(&entries));
+    }
+
+    #[test]
+    fn zips_keep_permissions() {
+        let dir = packed();
+        let bashrc = dir.path().join("files/{{HOME}}/.bashrc");
+        std::fs::set_permissions(&bashrc, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let entries = entries_of(&dir.path().to_path_buf(), &PathBuf::from(ARCHIVE_ROOT)).unwrap();
+        let output = dir.path().join("dots.zip");
+        write_zip(std::fs::File::create(&output).unwrap(), &entries, &output).unwrap();
+
+        let mut zip = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        let name = format!("{}/files/{{{{HOME}}}}/.bashrc", ARCHIVE_ROOT);
+        let file = zip.by_name(&name).unwrap();
+        assert_eq!(file.unix_mode().map(|m| m & 0o777), Some(0o600));
+    }
+}
@@ -18,6 +18,27 @@ pub enum CloneError {
         dir: PathBuf,
         source: std::io::Error,
     },
+
+    #[snafu(display("Failed to fetch great repository {}: {}", dir.display(), source))]
+    FetchFailure { dir: PathBuf, source: git2::Error },
+
+    #[snafu(display("Failed to compare commits {} and {} of great repository {}: {}", old, new, dir.display(), source))]
+    CompareFailure {
+        old: String,
+        new: String,
+        dir: PathBuf,
+        source: git2::Error,
+    },
+
+    #[snafu(display("Failed to get the status of great repository {}: {}", dir.display(), source))]
+    StatusFailure { dir: PathBuf, source: git2::Error },
+
+    #[snafu(display("Failed to check out {} in great repository {}: {}", revision, dir.display(), source))]
+    CheckoutFailure {
+        revision: String,
+        dir: PathBuf,
+        source: git2::Error,
+    },
 }
 
 struct State {
@@ -68,6 +89,144 @@ pub fn clone_repo(url: &String, clone_to: &PathBuf) -> Result<(), CloneError> {
     Ok(())
 }
 
+/// Check out a revision (a commit, or a reference) of a cloned
+/// repository. Returns the commit checked out.
+pub fn checkout(dir: &PathBuf, revision: &str) -> Result<String, CloneError> {
+    let checkout = || -> Result<String, git2::Error> {
+        let repo = git2::Repository::open(dir)?;
+        let commit = repo.revparse_single(revision)?.peel_to_commit()?;
+
+        repo.checkout_tree(
+            commit.as_object(),
+            Some(git2::build::CheckoutBuilder::new().force()),
+        )?;
+        repo.set_head_detached(commit.id())?;
+
+        Ok(commit.id().to_string())
+    };
+
+    checkout().context(CheckoutFailure { revision, dir })
+}
+
+/// Gets the commit checked out in a repository.
+pub fn head_commit(dir: &PathBuf) -> Result<String, CloneError> {
+    let head = || -> Result<String, git2::Error> {
+        let repo = git2::Repository::open(dir)?;
+        let commit = repo.head()?.peel_to_commit()?;
+
+        Ok(commit.id().to_string())
+    };
+
+    head().context(CheckoutFailure {
+        revision: "HEAD",
+        dir,
+    })
+}
+
+/// Fetch every branch and tag of a cloned repository from where it was
+/// cloned from. The checked out files are left alone.
+pub fn fetch(dir: &PathBuf) -> Result<(), CloneError> {
+    let fetch = || -> Result<(), git2::Error> {
+        let repo = git2::Repository::open(dir)?;
+        let mut remote = repo.find_remote("origin")?;
+
+        remote.fetch(
+            &[
+                "+refs/heads/*:refs/remotes/origin/*",
+                "+refs/tags/*:refs/tags/*",
+            ],
+            None,
+            None,
+        )
+    };
+
+    fetch().context(FetchFailure { dir })
+}
+
+/// Gets the commit a revision of a repository points at.
+pub fn resolve(dir: &PathBuf, revision: &str) -> Result<String, CloneError> {
+    let resolve = || -> Result<String, git2::Error> {
+        let repo = git2::Repository::open(dir)?;
+        let commit = repo.revparse_single(revision)?.peel_to_commit()?;
+
+        Ok(commit.id().to_string())
+    };
+
+    resolve().context(CheckoutFailure { revision, dir })
+}
+
+/// Gets the revision of the default branch of where a repository was
+/// cloned from.
+pub fn default_revision(dir: &PathBuf) -> String {
+    let repo = match git2::Repository::open(dir) {
+        Ok(r) => r,
+        Err(_) => return "HEAD".to_owned(),
+    };
+
+    ["refs/remotes/origin/HEAD", "refs/remotes/origin/main", "refs/remotes/origin/master"]
+        .iter()
+        .find(|r| repo.find_reference(r).is_ok())
+        .map_or("HEAD".to_owned(), |r| r.to_string())
+}
+
+/// Gets the paths that differ between two commits of a repository,
+/// relative to it.
+pub fn changed_between(dir: &PathBuf, old: &str, new: &str) -> Result<Vec<PathBuf>, CloneError> {
+    let changed = || -> Result<Vec<PathBuf>, git2::Error> {
+        let repo = git2::Repository::open(dir)?;
+        let tree_of = |commit: &str| -> Result<git2::Tree, git2::Error> {
+            repo.revparse_single(commit)?.peel_to_tree()
+        };
+
+        let diff = repo.diff_tree_to_tree(Some(&tree_of(old)?), Some(&tree_of(new)?), None)?;
+        let mut changed = vec![];
+        for delta in diff.deltas() {
+            for file in &[delta.old_file(), delta.new_file()] {
+                if let Some(path) = file.path() {
+                    if !changed.iter().any(|c| c == path) {
+                        changed.push(path.to_path_buf());
+                    }
+                }
+            }
+        }
+
+        Ok(changed)
+    };
+
+    changed().context(CompareFailure { old, new, dir })
+}
+
+/// Are any of the files checked out changed? Files git doesn't track
+/// are left out.
+pub fn is_dirty(dir: &PathBuf) -> Result<bool, CloneError> {
+    let dirty = || -> Result<bool, git2::Error> {
+        let repo = git2::Repository::open(dir)?;
+        let statuses = repo.statuses(Some(
+            git2::StatusOptions::new()
+                .include_untracked(false)
+                .include_ignored(false),
+        ))?;
+
+        Ok(!statuses.is_empty())
+    };
+
+    dirty().context(StatusFailure { dir })
+}
+
+/// Gets how many commits what is checked out is behind a revision, as
+/// of when the repository was last fetched.
+pub fn behind(dir: &PathBuf, revision: &str) -> Result<usize, CloneError> {
+    let behind = || -> Result<usize, git2::Error> {
+        let repo = git2::Repository::open(dir)?;
+        let head = repo.head()?.peel_to_commit()?.id();
+        let upstream = repo.revparse_single(revision)?.peel_to_commit()?.id();
+
+        Ok(repo.graph_ahead_behind(head, upstream)?.1)
+    };
+
+    behind().context(StatusFailure { dir })
+}
+
 fn clone_progress(state: &mut State) {
     let stats = state.progress.as_ref().unwrap();
     let network_pct = (100 * stats.received_objects()) / stats.total_objects();
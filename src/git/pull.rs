@@ -1,5 +1,6 @@
 use crate::manifest::State;
 use crate::pull;
+use crate::pull::installed::Installed;
 use clap::ArgMatches;
 use log::{info, warn};
 use snafu::{ResultExt, Snafu};
@@ -116,7 +117,8 @@ pub fn pull(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::
         }
     }
 
-    pull::add::install_mods(matches, state)?;
+    let packages = pull::add::install_mods(matches, state, None)?;
+    Installed::record_packages(state, "main", &packages)?;
 
     Ok(())
 }
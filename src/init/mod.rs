@@ -1,11 +1,10 @@
-use crate::manifest::State;
+use crate::manifest::{Manifest, State};
+use crate::plan::{self, Action};
 use crate::utils;
 use clap::ArgMatches;
 use git2::Repository;
 use snafu::{ResultExt, Snafu};
 use std::fs;
-use std::fs::File;
-use std::io::Write;
 use std::path::PathBuf;
 
 #[derive(Debug, Snafu)]
@@ -20,13 +19,19 @@ pub enum InitError {
 pub fn init(_matches: &ArgMatches, state: &State) -> Result<(), Box<dyn std::error::Error>> {
     init_no_damage(_matches, state)?;
 
-    let mut file;
-    file = File::create(&state.greatness_state).context(utils::FileCreationError {
-        file: &state.greatness_state,
-    })?;
+    if plan::record(Action::WriteFile {
+        path: state.greatness_state.clone(),
+        from: None,
+    }) {
+        return Ok(());
+    }
 
-    // For some reason, we need non-valid YAML to not get an error
-    file.write_all(b"{}").context(utils::FileWriteError {
+    // Start from an empty manifest, stamped with the current version
+    utils::write_atomically(
+        &state.greatness_state,
+        serde_yaml::to_string(&Manifest::default())?.as_bytes(),
+    )
+    .context(utils::FileWriteError {
         file: &state.greatness_state,
     })?;
 
@@ -42,25 +47,41 @@ pub fn init_no_damage(
     _matches: &ArgMatches,
     state: &State,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if !state.greatness_dir.as_path().exists() {
+    if !state.greatness_dir.as_path().exists()
+        && !plan::record(Action::CreateDir {
+            path: state.greatness_dir.clone(),
+        })
+    {
         fs::create_dir_all(&state.greatness_dir).context(utils::DirCreationError {
             dir: &state.greatness_dir,
         })?;
     }
 
-    if !state.greatness_pulled_dir.as_path().exists() {
+    if !state.greatness_pulled_dir.as_path().exists()
+        && !plan::record(Action::CreateDir {
+            path: state.greatness_pulled_dir.clone(),
+        })
+    {
         fs::create_dir_all(&state.greatness_pulled_dir).context(utils::DirCreationError {
             dir: &state.greatness_pulled_dir,
         })?;
     }
 
-    if !state.greatness_git_pack_dir.as_path().exists() {
+    if !state.greatness_git_pack_dir.as_path().exists()
+        && !plan::record(Action::CreateDir {
+            path: state.greatness_git_pack_dir.clone(),
+        })
+    {
         fs::create_dir_all(&state.greatness_git_pack_dir).context(utils::DirCreationError {
             dir: &state.greatness_git_pack_dir,
         })?;
     }
 
-    if !state.greatness_scripts_dir.as_path().exists() {
+    if !state.greatness_scripts_dir.as_path().exists()
+        && !plan::record(Action::CreateDir {
+            path: state.greatness_scripts_dir.clone(),
+        })
+    {
         fs::create_dir_all(&state.greatness_scripts_dir).context(utils::DirCreationError {
             dir: &state.greatness_scripts_dir,
         })?;
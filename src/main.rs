@@ -5,28 +5,42 @@ extern crate maplit;
 extern crate lazy_static;
 
 mod add;
+mod apply;
+mod backup;
+mod condition;
+mod crypt;
+mod deploy;
+mod diff;
 mod doctor;
+mod export;
 mod git;
 mod init;
 mod log_utils;
 mod manifest;
+mod merge;
 mod pack;
+mod plan;
 mod package;
+mod paths;
 mod progress;
 mod prompt;
 mod pull;
+mod purge;
 mod rm;
 mod script;
+mod secret;
 mod status;
 mod tag;
+mod template;
 mod utils;
 
 use isahc::ReadResponseExt;
-use clap::{App, AppSettings, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
 use env_logger::{Builder, Target};
 use log::LevelFilter;
 use log::{warn, error, info};
 use manifest::Manifest;
+use pull::installed::Installed;
 use nix::unistd::Uid;
 use std::io::Write;
 use std::path::PathBuf;
@@ -63,6 +77,20 @@ fn main() {
                 .required(false)
                 .takes_value(false)
         )
+        .arg(
+            Arg::from("<dry-run> -n, --dry-run 'Show what would be done, without touching anything.'")
+                .required(false)
+                .takes_value(false)
+                .global(true)
+        )
+        .arg(
+            Arg::from("<plan-format> --plan-format 'How to show what a dry run would do.'")
+                .required(false)
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .global(true)
+        )
         .subcommand(
             App::new("init")
                 .about("Initializes greatness!")
@@ -72,7 +100,20 @@ fn main() {
                         .takes_value(false),
                 ),
         )
-        // TODO: purge (removed all traces of greatness; in short, make your computer not great)
+        .subcommand(
+            App::new("purge")
+                .about("Removes all traces of greatness; in short, makes your computer not great. Replaced files are restored from the backups.")
+                .arg(
+                    Arg::from("-y, --yes 'Do not ask for confirmation.'")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::from("-p, --packages 'Uninstall the packages too.'")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
         .subcommand(
             App::new("doctor")
                 .about("Finds errors. Not that there are any, this software is great after all!")
@@ -83,9 +124,27 @@ fn main() {
         )
         .subcommand(
             App::new("add")
-                .about("Adds (a) file(s) to the state.")
+                .about("Adds (a) file(s) or directories to the state.")
                 .setting(AppSettings::TrailingVarArg)
-                .arg(Arg::from("<files>... 'File(s) to add.'").required(true)),
+                .arg(
+                    Arg::from("<include> -i, --include 'Only track files in the directory matching a glob. Can be given more than once.'")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                )
+                .arg(
+                    Arg::from("<exclude> -e, --exclude 'Do not track files in the directory matching a glob. Can be given more than once.'")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                )
+                .arg(
+                    Arg::from("<deploy> -l, --deploy 'How to put the file(s) in place on the system.'")
+                        .required(false)
+                        .takes_value(true)
+                        .possible_values(&["copy", "symlink", "hardlink"])
+                )
+                .arg(Arg::from("<files>... 'File(s) or directories to add.'").required(true)),
         )
         .subcommand(
             App::new("rm")
@@ -100,13 +159,14 @@ fn main() {
                     App::new("add")
                         .about("Fetches and merges an external state.")
                         .arg(
-                            Arg::from("<from> 'Where to fetch the external state.'")
+                            Arg::from("<from> 'Where to fetch the external state; a git repository, a local directory, a file:// URL, or an archive made by `great export`.'")
                                 .required(true)
                                 .index(1),
                         )
                         .arg(
-                            Arg::from("<only-with-tag> -t, --only-with-tag 'Only merge files with a specific tag.'")
+                            Arg::from("<only-with-tag> -t, --only-with-tag 'Only merge files picked by a tag selector such as \"shell,editor\", \"!work\" or \"desktop&linux\".'")
                                 .required(false)
+                                .takes_value(true)
                         )
                         .arg(
                             Arg::from("<allow-mods> -d, --allow-mods 'Allow scripts and package installation. Please do not use this argument without trusting the source.'")
@@ -118,23 +178,159 @@ fn main() {
                                 .required(false)
                                 .takes_value(false)
                         )
+                        .arg(
+                            Arg::from("<on-conflict> -c, --on-conflict 'What to do with files that already exist: overwrite, skip, merge or ask.'")
+                                .required(false)
+                                .takes_value(true)
+                                .possible_values(&["overwrite", "skip", "merge", "ask"])
+                        )
+                        .arg(
+                            Arg::from("<merge-tool> --merge-tool 'Resolve merge conflicts with a tool, such as \"meld $LOCAL $MERGED $REMOTE\".'")
+                                .required(false)
+                                .takes_value(true)
+                        )
+                        .arg(
+                            Arg::from("<name> -N, --name 'What to call the state. Defaults to where it is from, such as github.com/user/dotfiles.'")
+                                .required(false)
+                                .takes_value(true)
+                        )
+                        .arg(
+                            Arg::from("<branch> -b, --branch 'Pin the state to a branch.'")
+                                .required(false)
+                                .takes_value(true)
+                                .conflicts_with_all(&["git-tag", "commit"])
+                        )
+                        .arg(
+                            Arg::from("<git-tag> --git-tag 'Pin the state to a git tag.'")
+                                .required(false)
+                                .takes_value(true)
+                                .conflicts_with("commit")
+                        )
+                        .arg(
+                            Arg::from("<commit> --commit 'Pin the state to a commit.'")
+                                .required(false)
+                                .takes_value(true)
+                        )
                     )
                 .subcommand(
                     App::new("rm")
                         .about("Removes an external state.")
                         .arg(
                             Arg::from("<name> 'The name of the external state to remove.'")
+                                .required(true)
+                                .index(1)
+                        )
+                )
+                .subcommand(
+                    App::new("list")
+                        .about("Lists the external states, and where each of them is at.")
+                        .arg(
+                            Arg::from("<fetch> -f, --fetch 'Fetch the external states first, to see how far behind they are.'")
                                 .required(false)
+                                .takes_value(false)
+                        )
+                )
+                .subcommand(
+                    App::new("graph")
+                        .about("Prints which external states require which, as a tree.")
+                        .arg(
+                            Arg::from("<dot> --dot 'Print the graph in DOT, for graphviz.'")
+                                .required(false)
+                                .takes_value(false)
+                        )
+                )
+                .subcommand(
+                    App::new("show")
+                        .about("Shows every file, script, and package an external state has.")
+                        .arg(
+                            Arg::from("<name> 'The name of the external state to show.'")
+                                .required(true)
                                 .index(1)
                         )
                 )
+                .subcommand(
+                    App::new("update")
+                        .about("Fetches the external states again, installing the files that changed.")
+                        .arg(
+                            Arg::from("[names]... 'Only update these external states.'")
+                                .required(false)
+                        )
+                        .arg(
+                            Arg::from("<allow-mods> -d, --allow-mods 'Allow scripts and package installation. Please do not use this argument without trusting the source.'")
+                                .required(false)
+                                .takes_value(false)
+                        )
+                        .arg(
+                            Arg::from("<on-conflict> -c, --on-conflict 'What to do with files that already exist: overwrite, skip, merge or ask.'")
+                                .required(false)
+                                .takes_value(true)
+                                .possible_values(&["overwrite", "skip", "merge", "ask"])
+                        )
+                        .arg(
+                            Arg::from("<merge-tool> --merge-tool 'Resolve merge conflicts with a tool, such as \"meld $LOCAL $MERGED $REMOTE\".'")
+                                .required(false)
+                                .takes_value(true)
+                        )
+                )
+                .subcommand(
+                    App::new("abort")
+                        .about("Rolls back a pull that was interrupted.")
+                )
+        )
+        .subcommand(
+            App::new("apply")
+                .about("Deploys the packed repository onto this machine, replacing the greatness state with the packed one. Run this after pulling the git repository on another machine.")
+                .arg(
+                    Arg::from("<only-with-tag> -t, --only-with-tag 'Only apply files picked by a tag selector such as \"shell,editor\", \"!work\" or \"desktop&linux\".'")
+                        .required(false)
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::from("-p, --packages 'Install the packages too.'")
+                )
+                .arg(
+                    Arg::from("<on-conflict> -c, --on-conflict 'What to do with files that already exist: overwrite, skip, merge or ask.'")
+                        .required(false)
+                        .takes_value(true)
+                        .possible_values(&["overwrite", "skip", "merge", "ask"])
+                )
+                .arg(
+                    Arg::from("<merge-tool> --merge-tool 'Resolve merge conflicts with a tool, such as \"meld $LOCAL $MERGED $REMOTE\".'")
+                        .required(false)
+                        .takes_value(true)
+                ),
+        )
+        .subcommand(
+            App::new("diff")
+                .about("Shows what changed between files on the system and their packed copies.")
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::from("<tag> -t, --tag 'Only diff files picked by a tag selector such as \"shell,editor\", \"!work\" or \"desktop&linux\".'")
+                        .required(false)
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::from("<rev> -r, --rev 'Diff against the packed copies at a git revision, such as HEAD~2.'")
+                        .required(false)
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::from("<external> -x, --external 'Run a difftool on each changed file instead, as `<external> <packed> <live>`.'")
+                        .required(false)
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::from("[paths]... 'Only diff these files, or files in these directories.'")
+                        .required(false)
+                        .index(1),
+                ),
         )
         .subcommand(
             App::new("tag")
                 .about("Tag(s) (a) file(s).")
                 .setting(AppSettings::TrailingVarArg)
                 .arg(
-                    Arg::from("<tag> 'What to tag the file(s) as.'")
+                    Arg::from("<tag> 'What to tag the file(s) as. Files can have many tags.'")
                         .required(true)
                         .index(1),
                 )
@@ -142,6 +338,211 @@ fn main() {
                     Arg::from("<files>... 'File(s) to add.'")
                         .required(true)
                         .index(2),
+                )
+                .arg(
+                    Arg::from("<remove> -r, --remove 'Remove the tag from the file(s) instead.'")
+                        .required(false)
+                        .takes_value(false)
+                ),
+        )
+        .subcommand(
+            App::new("when")
+                .about("Only install (a) file(s) or package(s) on machines matching a condition. Conditions of different kinds must all match.")
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::from("<hostname> -H, --hostname 'Only on hosts with this name. Can be given more than once.'")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .use_delimiter(true)
+                )
+                .arg(
+                    Arg::from("<os> -o, --os 'Only on this operating system, such as linux or macos. Can be given more than once.'")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .use_delimiter(true)
+                )
+                .arg(
+                    Arg::from("<distro> -D, --distro 'Only on this distribution, by its os-release ID, such as arch or debian. Can be given more than once.'")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .use_delimiter(true)
+                )
+                .arg(
+                    Arg::from("<arch> -a, --arch 'Only on this CPU architecture, such as x86_64 or aarch64. Can be given more than once.'")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .use_delimiter(true)
+                )
+                .arg(
+                    Arg::from("<user> -u, --user 'Only for this user. Can be given more than once.'")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .use_delimiter(true)
+                )
+                .arg(
+                    Arg::from("<env> -E, --env 'Only if an environment variable is set, as NAME, or has a value, as NAME=value. Can be given more than once.'")
+                        .required(false)
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                )
+                .arg(
+                    Arg::from("-p, --package 'Treat the entries as packages instead of files.'")
+                )
+                .arg(
+                    Arg::from("-c, --clear 'Remove the condition, installing everywhere.'")
+                )
+                .arg(
+                    Arg::from("<entries>... 'File(s) or package(s) to change.'")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            App::new("template")
+                .about("Templates. The packed copy of a template is rendered with variables and facts about the machine whenever it is installed. Variables are set under `variables` in greatness.yaml, and can be overridden per machine in variables.yaml.")
+                .setting(AppSettings::SubcommandRequired)
+                .subcommand(
+                    App::new("add")
+                        .about("Make (a) file(s) templates.")
+                        .arg(
+                            Arg::from("<files>... 'File(s) to make templates.'")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    App::new("rm")
+                        .about("Stop (a) file(s) being templates.")
+                        .arg(
+                            Arg::from("<files>... 'File(s) to stop being templates.'")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    App::new("render")
+                        .about("Print what a template renders to on this machine.")
+                        .arg(
+                            Arg::from("<file> 'Template to render.'")
+                                .required(true)
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("encrypt")
+                .about("Encrypted files. Only the ciphertext of an encrypted file is packed, and it is decrypted whenever it is installed. The passphrase is taken from GREAT_PASSPHRASE, the key file in the greatness directory, or asked for.")
+                .setting(AppSettings::SubcommandRequired)
+                .subcommand(
+                    App::new("add")
+                        .about("Encrypt (a) file(s).")
+                        .arg(
+                            Arg::from("<files>... 'File(s) to encrypt.'")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    App::new("rm")
+                        .about("Stop encrypting (a) file(s).")
+                        .arg(
+                            Arg::from("<files>... 'File(s) to stop encrypting.'")
+                                .required(true)
+                                .index(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("deploy")
+                .about("Change how (a) file(s) are put in place on the system. Symlinks and hardlinks point into the packed repository.")
+                .setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::from("<mode> 'How to put the file(s) in place.'")
+                        .required(true)
+                        .possible_values(&["copy", "symlink", "hardlink"])
+                        .index(1),
+                )
+                .arg(
+                    Arg::from("<files>... 'File(s) to change.'")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            App::new("backup")
+                .about("Files that great replaced, kept in a backup for every run.")
+                .setting(AppSettings::SubcommandRequired)
+                .subcommand(App::new("list").about("List the backups."))
+                .subcommand(
+                    App::new("show")
+                        .about("Show what is in a backup.")
+                        .arg(
+                            Arg::from("<id> 'The backup, as listed, or latest.'")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    App::new("restore")
+                        .about("Put the files of a backup back. Whatever is there now is backed up first.")
+                        .setting(AppSettings::TrailingVarArg)
+                        .arg(
+                            Arg::from("<id> 'The backup, as listed, or latest.'")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::from("[paths]... 'Only restore these files, or files in these directories.'")
+                                .required(false)
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    App::new("prune")
+                        .about("Remove old backups.")
+                        .arg(
+                            Arg::from("<keep> -k, --keep 'Keep this many of the newest backups.'")
+                                .required(false)
+                                .takes_value(true)
+                        )
+                        .arg(
+                            Arg::from("<older-than> -o, --older-than 'Remove backups older than this many days.'")
+                                .required(false)
+                                .takes_value(true)
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("path")
+                .about("Path variables, such as {{CODE}} for ~/src. Tracked files inside of them are stored relative to them, so they can live elsewhere on other machines.")
+                .setting(AppSettings::SubcommandRequired)
+                .subcommand(
+                    App::new("add")
+                        .about("Define a path variable.")
+                        .arg(
+                            Arg::from("<name> 'Name of the variable, such as CODE.'")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::from("<dir> 'Directory the variable stands for on this machine.'")
+                                .required(true)
+                                .index(2),
+                        ),
+                )
+                .subcommand(
+                    App::new("rm")
+                        .about("Remove (a) path variable(s).")
+                        .arg(
+                            Arg::from("<names>... 'Name(s) of the variable(s).'")
+                                .required(true)
+                                .index(1),
+                        ),
                 ),
         )
         .subcommand(
@@ -207,9 +608,35 @@ fn main() {
 
                 )
         )
+        .subcommand(
+            App::new("export")
+                .about("Export everything that is packed to an archive, for machines without internet, or to attach to bug reports. Paths keep their placeholders, so the archive can be installed under any home directory.")
+                .arg(
+                    Arg::from("<format> -f, --format 'Kind of archive to make.'")
+                        .required(false)
+                        .takes_value(true)
+                        .possible_values(&["tar.gz", "tar.zst", "zip"])
+                        .default_value("tar.gz"),
+                )
+                .arg(
+                    Arg::from("<output> -o, --output 'Where to write the archive. Defaults to greatness-<time>.<format> in the current directory.'")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::from("-p, --pulled 'Include the pulled states too.'")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
         .subcommand(
             App::new("pack")
                 .about("Pack all your dotfiles into a git repository.")
+                .arg(
+                    Arg::from("<only-with-tag> -t, --only-with-tag 'Only pack files picked by a tag selector such as \"shell,editor\", \"!work\" or \"desktop&linux\".'")
+                        .required(false)
+                        .takes_value(true)
+                )
         )
         .subcommand(
             App::new("package")
@@ -309,6 +736,11 @@ fn main() {
                 .subcommand(
                     App::new("jog")
                         .about("Run script associated with a file.")
+                        .arg(
+                            Arg::from("<only-with-tag> -t, --only-with-tag 'Only run the scripts of files picked by a tag selector such as \"shell,editor\", \"!work\" or \"desktop&linux\".'")
+                                .required(false)
+                                .takes_value(true)
+                        )
                 )
         )
         .get_matches(); // TODO: Push and pull commands?
@@ -317,6 +749,10 @@ fn main() {
         builder.filter_level(LevelFilter::Debug);
     }
 
+    if matches.is_present("dry-run") {
+        plan::enable();
+    }
+
     builder.init();
 
     if Uid::effective().is_root() {
@@ -370,6 +806,14 @@ on the directory."
         }
     }
 
+    // Make sure no other great process touches the state whilst we do
+    if default_greatness_dir.as_path().exists() {
+        if let Err(e) = state.lock() {
+            error!("An error occured whilst locking the greatness state: {}", e);
+            std::process::exit(1);
+        }
+    }
+
     if matches.subcommand_name().unwrap_or("") != "init" {
         let state_data: Manifest = match Manifest::populate_from_file(&state) {
             Ok(m) => m,
@@ -380,8 +824,13 @@ on the directory."
         };
 
         state.data = state_data;
+        paths::define_from(&state.data, true);
     }
 
+    backup::use_store(state.greatness_backups_dir.clone());
+    crypt::use_key_file(state.greatness_key.clone());
+    secret::use_provider(state.data.secrets.clone());
+
     match matches.subcommand() {
         Some(("status", _status_matches)) => {
             status::print_status(&mut state);
@@ -400,12 +849,12 @@ on the directory."
                 error!("\t{}", err);
             }
 
-            std::process::exit(1);
+            fail(&matches);
         }
 
         Some(("add", add_matches)) => {
             match add::add_files(
-                &matches,
+                add_matches,
                 add_matches
                     .values_of("files")
                     .unwrap()
@@ -418,7 +867,7 @@ on the directory."
                 Err(e) => {
                     error!("An error occured whilst tracking great file(s): {}", e);
 
-                    std::process::exit(1);
+                    fail(&matches);
                 }
             }
         }
@@ -431,7 +880,7 @@ on the directory."
                     e
                 );
 
-                std::process::exit(1);
+                fail(&matches);
             }
         },
 
@@ -439,7 +888,7 @@ on the directory."
             if default_greatness_dir.as_path().exists() && !init_matches.is_present("force") {
                 error!("It looks like you've already initialized. \x1b[5m\x1b[1m\x1b[3m\x1b[4mReinitializing would overwrite your current configuration.\x1b[0m\x1b[31m\nPlease pass the --force flag to reinitialize.");
 
-                std::process::exit(1);
+                fail(&matches);
             }
 
             match init::init(init_matches, &state) {
@@ -447,7 +896,7 @@ on the directory."
                 Err(e) => {
                     error!("An error occured whilst initialising your local great greatness environment: {}", e);
 
-                    std::process::exit(1);
+                    fail(&matches);
                 }
             }
         }
@@ -455,12 +904,7 @@ on the directory."
         Some(("pull", get_matches)) => {
             match get_matches.subcommand() {
                 Some(("add", add_matches)) => {
-                    match pull::add::clone_and_install_repo(
-                        add_matches.value_of("from").unwrap().to_string(),
-                        add_matches,
-                        &mut state,
-                        false, // This is the original project
-                    ) {
+                    match pull::add::add(add_matches, &mut state) {
                         Ok(()) => (),
                         Err(e) => {
                             error!(
@@ -468,7 +912,7 @@ on the directory."
                                 e
                             );
 
-                            std::process::exit(1);
+                            fail(&matches);
                         }
                     }
                 }
@@ -478,10 +922,59 @@ on the directory."
                     Err(e) => {
                         error!("An error occured whilst removing an external state: {}", e);
 
-                        std::process::exit(1);
+                        fail(&matches);
+                    }
+                },
+
+                Some(("list", list_matches)) => match pull::list::list(list_matches, &state) {
+                    Ok(()) => (),
+                    Err(e) => {
+                        error!("An error occured whilst listing the external states: {}", e);
+
+                        fail(&matches);
+                    }
+                },
+
+                Some(("graph", graph_matches)) => match pull::graph::graph(graph_matches, &state) {
+                    Ok(()) => (),
+                    Err(e) => {
+                        error!("An error occured whilst graphing the external states: {}", e);
+
+                        fail(&matches);
+                    }
+                },
+
+                Some(("show", show_matches)) => match pull::show::show(show_matches, &state) {
+                    Ok(()) => (),
+                    Err(e) => {
+                        error!("An error occured whilst showing an external state: {}", e);
+
+                        fail(&matches);
                     }
                 },
 
+                Some(("update", update_matches)) => {
+                    match pull::update::update(update_matches, &mut state) {
+                        Ok(()) => (),
+                        Err(e) => {
+                            error!("An error occured whilst updating the external state(s): {}", e);
+
+                            fail(&matches);
+                        }
+                    }
+                }
+
+                Some(("abort", abort_matches)) => {
+                    match pull::transaction::abort(abort_matches, &state) {
+                        Ok(()) => (),
+                        Err(e) => {
+                            error!("An error occured whilst rolling back the interrupted pull: {}", e);
+
+                            fail(&matches);
+                        }
+                    }
+                }
+
                 None => {
                     unreachable!();
                 }
@@ -500,7 +993,7 @@ on the directory."
                         Err(e) => {
                             error!("An error occured whilst setting the remote: {}", e);
 
-                            std::process::exit(1);
+                            fail(&matches);
                         }
                     }
                 }
@@ -513,7 +1006,7 @@ on the directory."
                             e
                         );
 
-                        std::process::exit(1);
+                        fail(&matches);
                     }
                 },
 
@@ -522,7 +1015,7 @@ on the directory."
                     Err(e) => {
                         error!("An error occured whilst pushing files to the remote: {}", e);
 
-                        std::process::exit(1);
+                        fail(&matches);
                     }
                 },
 
@@ -531,7 +1024,7 @@ on the directory."
                     Err(e) => {
                         error!("An error occured whilst pulling files to local: {}", e);
 
-                        std::process::exit(1);
+                        fail(&matches);
                     }
                 },
 
@@ -540,7 +1033,7 @@ on the directory."
                         "Commiting is not yet supported. Please use the prompt subcommand instead!"
                     );
 
-                    std::process::exit(1);
+                    fail(&matches);
                 }
 
                 _ => {
@@ -549,6 +1042,33 @@ on the directory."
             }
         }
 
+        Some(("apply", apply_matches)) => match apply::apply(apply_matches, &mut state) {
+            Ok(()) => (),
+            Err(e) => {
+                error!("An error occured whilst applying the packed repository: {}", e);
+
+                fail(&matches);
+            }
+        },
+
+        Some(("diff", diff_matches)) => match diff::diff(diff_matches, &mut state) {
+            Ok(()) => (),
+            Err(e) => {
+                error!("An error occured whilst diffing: {}", e);
+
+                fail(&matches);
+            }
+        },
+
+        Some(("export", export_matches)) => match export::export(export_matches, &state) {
+            Ok(()) => (),
+            Err(e) => {
+                error!("An error occured whilst exporting greatness: {}", e);
+
+                fail(&matches);
+            }
+        },
+
         Some(("pack", pack_matches)) => match pack::pack(&mut state, pack_matches) {
             Ok(()) => (),
             Err(e) => {
@@ -557,17 +1077,19 @@ on the directory."
                     e
                 );
 
-                std::process::exit(1);
+                fail(&matches);
             }
         },
 
         Some(("package", package_matches)) => match package_matches.subcommand() {
-            Some(("jog", jog_matches)) => match package::jog::jog(jog_matches, &mut state) {
+            Some(("jog", jog_matches)) => match package::jog::jog(jog_matches, &state)
+                .and_then(|packages| Installed::record_packages(&state, "main", &packages))
+            {
                 Ok(()) => (),
                 Err(e) => {
                     error!("An error occured whilst installing all packages: {}", e);
 
-                    std::process::exit(1);
+                    fail(&matches);
                 }
             },
 
@@ -576,7 +1098,7 @@ on the directory."
                 Err(e) => {
                     error!("An error occured whilst adding a package to install: {}", e);
 
-                    std::process::exit(1);
+                    fail(&matches);
                 }
             },
 
@@ -588,7 +1110,7 @@ on the directory."
                         e
                     );
 
-                    std::process::exit(1);
+                    fail(&matches);
                 }
             },
 
@@ -599,7 +1121,7 @@ on the directory."
                         Err(e) => {
                             error!("An error occured whilst adding an overload: {}", e);
 
-                            std::process::exit(1);
+                            fail(&matches);
                         }
                     }
                 }
@@ -610,7 +1132,7 @@ on the directory."
                         Err(e) => {
                             error!("An error occured whilst removing an overload: {}", e);
 
-                            std::process::exit(1);
+                            fail(&matches);
                         }
                     }
                 }
@@ -628,10 +1150,154 @@ on the directory."
             Err(e) => {
                 error!("An error occured whilst tagging the file(s): {}", e);
 
-                std::process::exit(1);
+                fail(&matches);
+            }
+        },
+
+        Some(("when", when_matches)) => match condition::set::set(when_matches, &mut state) {
+            Ok(()) => (),
+            Err(e) => {
+                error!("An error occured whilst changing where the entries are installed: {}", e);
+
+                fail(&matches);
+            }
+        },
+
+        Some(("template", template_matches)) => match template_matches.subcommand() {
+            Some(("add", add_matches)) => match template::add::add(add_matches, &mut state) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst making the template(s): {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            Some(("rm", rm_matches)) => match template::rm::rm(rm_matches, &mut state) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst removing the template(s): {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            Some(("render", render_matches)) => {
+                match template::render::render(render_matches, &mut state) {
+                    Ok(()) => (),
+                    Err(e) => {
+                        error!("An error occured whilst rendering the template: {}", e);
+
+                        fail(&matches);
+                    }
+                }
+            }
+
+            _ => unreachable!(),
+        },
+
+        Some(("encrypt", encrypt_matches)) => match encrypt_matches.subcommand() {
+            Some(("add", add_matches)) => match crypt::add::add(add_matches, &mut state) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst encrypting the file(s): {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            Some(("rm", rm_matches)) => match crypt::rm::rm(rm_matches, &mut state) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst decrypting the file(s): {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            _ => unreachable!(),
+        },
+
+        Some(("deploy", deploy_matches)) => match deploy::set::set(deploy_matches, &mut state) {
+            Ok(()) => (),
+            Err(e) => {
+                error!("An error occured whilst changing how the file(s) are deployed: {}", e);
+
+                fail(&matches);
+            }
+        },
+
+        Some(("backup", backup_matches)) => match backup_matches.subcommand() {
+            Some(("list", list_matches)) => match backup::list::list(list_matches) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst listing the backups: {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            Some(("show", show_matches)) => match backup::list::show(show_matches) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst showing the backup: {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            Some(("restore", restore_matches)) => match backup::restore::restore(restore_matches) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst restoring the backup: {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            Some(("prune", prune_matches)) => match backup::prune::prune(prune_matches) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst pruning the backups: {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            _ => unreachable!(),
+        },
+
+        Some(("purge", purge_matches)) => match purge::purge(purge_matches, &mut state) {
+            Ok(()) => (),
+            Err(e) => {
+                error!("An error occured whilst purging greatness: {}", e);
+
+                fail(&matches);
             }
         },
 
+        Some(("path", path_matches)) => match path_matches.subcommand() {
+            Some(("add", add_matches)) => match paths::add::add(add_matches, &mut state) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst adding the path variable: {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            Some(("rm", rm_matches)) => match paths::rm::rm(rm_matches, &mut state) {
+                Ok(()) => (),
+                Err(e) => {
+                    error!("An error occured whilst removing the path variable(s): {}", e);
+
+                    fail(&matches);
+                }
+            },
+
+            _ => unreachable!(),
+        },
+
         Some(("prompt", prompt_matches)) => match prompt::prompt(prompt_matches, &mut state) {
             Ok(()) => (),
             Err(e) => {
@@ -640,7 +1306,7 @@ on the directory."
                     e
                 );
 
-                std::process::exit(1);
+                fail(&matches);
             }
         },
 
@@ -654,7 +1320,7 @@ on the directory."
                             e
                         );
 
-                        std::process::exit(1);
+                        fail(&matches);
                     }
                 }
             }
@@ -667,16 +1333,16 @@ on the directory."
                         e
                     );
 
-                    std::process::exit(1);
+                    fail(&matches);
                 }
             },
 
-            Some(("jog", _jog_matches)) => match script::jog::jog(&mut state) {
+            Some(("jog", jog_matches)) => match script::jog::jog_selected(jog_matches, &mut state) {
                 Ok(()) => (),
                 Err(e) => {
                     error!("An error occured whilst going jogging: {}", e);
 
-                    std::process::exit(1);
+                    fail(&matches);
                 }
             },
 
@@ -689,4 +1355,25 @@ on the directory."
         None => eprintln!("Please use the --help flag to get great knowlage!"),
         _ => unreachable!(),
     }
+
+    print_plan(&matches);
+}
+
+/// Prints what a dry run would have done, if this is one.
+fn print_plan(matches: &ArgMatches) {
+    if plan::is_dry_run() {
+        if let Err(e) = plan::print(matches.value_of("plan-format").unwrap_or("text")) {
+            error!("An error occured whilst printing the plan: {}", e);
+
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Exits after an error. A dry run still shows what it would have done
+/// up to the error.
+fn fail(matches: &ArgMatches) -> ! {
+    print_plan(matches);
+
+    std::process::exit(1);
 }
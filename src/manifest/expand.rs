@@ -0,0 +1,136 @@
+use super::AddedFile;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use snafu::{ResultExt, Snafu};
+use std::path::{Path, PathBuf};
+
+/// Name of the file, inside a tracked directory, that lists globs
+/// of files not to track. One glob per line, `#` starts a comment.
+pub const IGNORE_FILE: &str = ".greatignore";
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+/// Errors pretaining to expanding tracked directories
+pub enum ExpandError {
+    #[snafu(display("Invalid great glob {}: {}", glob, source))]
+    InvalidGlob {
+        glob: String,
+        source: globset::Error,
+    },
+
+    #[snafu(display("Failed to read great ignore file {}: {}", file.display(), source))]
+    IgnoreReadError {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to walk great directory {}: {}", dir.display(), source))]
+    WalkError {
+        dir: PathBuf,
+        source: walkdir::Error,
+    },
+}
+
+impl AddedFile {
+    /// Gets the (special) paths of every file this entry stands for.
+    /// A plain file is just itself. A directory is walked recursively
+    /// from `root`, which is wherever the directory currently lives
+    /// (on the system, or inside a packed/pulled repository), and
+    /// filtered by the include and exclude globs and `.greatignore`,
+    /// which is left out itself.
+    pub fn expand(&self, root: &PathBuf) -> Result<Vec<PathBuf>, ExpandError> {
+        if !self.directory {
+            return Ok(vec![self.path.clone()]);
+        }
+
+        let include = build_glob_set(self.include.as_ref().unwrap_or(&vec![]))?;
+        let mut exclude_globs = self.exclude.clone().unwrap_or(vec![]);
+        exclude_globs.append(&mut read_ignore_file(root)?);
+        let exclude = build_glob_set(&exclude_globs)?;
+        let include_everything = self.include.as_ref().map_or(true, |i| i.len() == 0);
+
+        let mut expanded = vec![];
+        let walker = walkdir::WalkDir::new(root)
+            .follow_links(false)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            // Never descend into excluded directories, or git internals.
+            // The ignore file itself is never tracked either
+            .filter_entry(|e| {
+                let relative = e.path().strip_prefix(root).unwrap_or(e.path());
+                e.file_name() != ".git"
+                    && relative != Path::new(IGNORE_FILE)
+                    && !exclude.is_match(relative)
+            });
+
+        for entry in walker {
+            let entry = entry.context(WalkError { dir: root })?;
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            let relative = entry.path().strip_prefix(root).unwrap().to_path_buf();
+            if !include_everything && !include.is_match(&relative) {
+                continue;
+            }
+
+            let mut special = self.path.clone();
+            special.push(relative);
+            expanded.push(special);
+        }
+
+        Ok(expanded)
+    }
+}
+
+fn build_glob_set(globs: &Vec<String>) -> Result<GlobSet, ExpandError> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).context(InvalidGlob { glob })?);
+    }
+
+    Ok(builder.build().context(InvalidGlob {
+        glob: globs.join(", "),
+    })?)
+}
+
+fn read_ignore_file(root: &Path) -> Result<Vec<String>, ExpandError> {
+    let mut ignore_file = root.to_path_buf();
+    ignore_file.push(IGNORE_FILE);
+
+    if !ignore_file.exists() {
+        return Ok(vec![]);
+    }
+
+    Ok(std::fs::read_to_string(&ignore_file)
+        .context(IgnoreReadError { file: &ignore_file })?
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.trim_end_matches('/').to_owned())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_ignore_file_is_never_expanded() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        std::fs::write(root.join(IGNORE_FILE), "*.log\n").unwrap();
+        std::fs::write(root.join("init.vim"), "").unwrap();
+        std::fs::write(root.join("debug.log"), "").unwrap();
+
+        let file = AddedFile {
+            path: PathBuf::from("{{HOME}}/.config/nvim"),
+            directory: true,
+            ..AddedFile::default()
+        };
+
+        assert_eq!(
+            file.expand(&root).unwrap(),
+            vec![PathBuf::from("{{HOME}}/.config/nvim/init.vim")]
+        );
+    }
+}
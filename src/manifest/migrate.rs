@@ -0,0 +1,254 @@
+use super::{MigrationFailed, StateError, UnknownVersion};
+use crate::paths;
+use serde_yaml::{Mapping, Value};
+use snafu::ResultExt;
+use std::path::Path;
+
+/// A single migration, upgrading a raw manifest by exactly one version.
+type Migration = fn(&mut Mapping) -> Result<(), String>;
+
+/// Every migration, in order. The migration at index `n` upgrades
+/// a manifest from version `n` to version `n + 1`. To change the
+/// schema, append a migration here; never edit an old one.
+const MIGRATIONS: &[Migration] = &[v0_to_v1, v1_to_v2, v2_to_v3, v3_to_v4];
+
+/// The manifest version this build of greatness reads and writes.
+pub const CURRENT_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// Gets the version of a raw manifest. Manifests written before
+/// versioning existed have no version field, and are version 0.
+pub fn version_of(raw: &Value) -> Result<u64, StateError> {
+    match raw.get("version") {
+        None => Ok(0),
+        Some(v) => match v.as_u64() {
+            Some(version) => Ok(version),
+            None => Err(std::io::Error::from(std::io::ErrorKind::InvalidData)).context(
+                UnknownVersion {
+                    found: format!("{:?}", v),
+                },
+            ),
+        },
+    }
+}
+
+/// Upgrade a raw manifest from version `from` to the current version.
+pub fn migrate(raw: &mut Value, from: u64) -> Result<(), StateError> {
+    if raw.is_null() {
+        *raw = Value::Mapping(Mapping::new());
+    }
+
+    let mapping = match raw.as_mapping_mut() {
+        Some(m) => m,
+        None => {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidData)).context(
+                MigrationFailed {
+                    from,
+                    reason: "the manifest is not a mapping".to_owned(),
+                },
+            )
+        }
+    };
+
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        if let Err(reason) = migration(mapping) {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidData)).context(
+                MigrationFailed {
+                    from: version as u64,
+                    reason,
+                },
+            );
+        }
+
+        mapping.insert(
+            Value::String("version".to_owned()),
+            Value::Number((version as u64 + 1).into()),
+        );
+    }
+
+    Ok(())
+}
+
+/// Version 1 only introduces the version field itself.
+fn v0_to_v1(_raw: &mut Mapping) -> Result<(), String> {
+    Ok(())
+}
+
+/// Version 2 stores paths with placeholders, such as {{HOME}}. Older
+/// versions meant to, but stored absolute paths instead.
+fn v1_to_v2(raw: &mut Mapping) -> Result<(), String> {
+    let specialize = |value: &mut Value| {
+        if let Some(path) = value.as_str() {
+            let special = paths::to_special_foreign(Path::new(path));
+            *value = Value::String(special.to_str().unwrap().to_owned());
+        }
+    };
+
+    if let Some(Value::Sequence(files)) = raw.get_mut(&Value::String("files".to_owned())) {
+        for file in files.iter_mut().filter_map(|f| f.as_mapping_mut()) {
+            if let Some(path) = file.get_mut(&Value::String("path".to_owned())) {
+                specialize(path);
+            }
+
+            if let Some(Value::Sequence(scripts)) =
+                file.get_mut(&Value::String("scripts".to_owned()))
+            {
+                scripts.iter_mut().for_each(specialize);
+            }
+        }
+    }
+
+    if let Some(Value::Sequence(requires)) = raw.get_mut(&Value::String("requires".to_owned())) {
+        for required in requires.iter_mut().filter_map(|r| r.as_sequence_mut()) {
+            if let Some(path) = required.get_mut(1) {
+                specialize(path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Version 3 makes requirements mappings with a name, instead of a
+/// pair of an optional URL and where they were pulled to. The name is
+/// where they were pulled to, under the pulled directory, so nothing
+/// has to move. Requirements without a URL are pulled from that path.
+fn v2_to_v3(raw: &mut Mapping) -> Result<(), String> {
+    let requires = match raw.get_mut(&Value::String("requires".to_owned())) {
+        Some(Value::Sequence(requires)) => requires,
+        _ => return Ok(()),
+    };
+
+    for required in requires.iter_mut() {
+        let pair = match required.as_sequence() {
+            Some(pair) => pair,
+            None => continue,
+        };
+
+        let path = match pair.get(1).and_then(Value::as_str) {
+            Some(path) => path.trim_end_matches('/'),
+            None => return Err(format!("requirement {:?} has no path", required)),
+        };
+        let dir = path.strip_suffix("/files").unwrap_or(path);
+        let name = match dir.find("/pulled/") {
+            Some(i) => &dir[i + "/pulled/".len()..],
+            None => dir.rsplit('/').next().unwrap_or(dir),
+        };
+        let url = match pair.get(0).and_then(Value::as_str) {
+            Some(url) => url.to_owned(),
+            None => paths::to_absolute(Path::new(dir))
+                .to_str()
+                .unwrap()
+                .to_owned(),
+        };
+
+        let mut requirement = Mapping::new();
+        requirement.insert(Value::String("name".to_owned()), Value::String(name.to_owned()));
+        requirement.insert(Value::String("url".to_owned()), Value::String(url));
+        *required = Value::Mapping(requirement);
+    }
+
+    Ok(())
+}
+
+/// Version 4 lets files have many tags. A file's tag becomes its only
+/// tag, and files tagged as nothing become untagged.
+fn v3_to_v4(raw: &mut Mapping) -> Result<(), String> {
+    if let Some(Value::Sequence(files)) = raw.get_mut(&Value::String("files".to_owned())) {
+        for file in files.iter_mut().filter_map(|f| f.as_mapping_mut()) {
+            let tags = match file.remove(&Value::String("tag".to_owned())) {
+                Some(Value::String(tag)) if !tag.is_empty() => vec![Value::String(tag)],
+                _ => vec![],
+            };
+
+            file.insert(Value::String("tags".to_owned()), Value::Sequence(tags));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrated(yaml: &str, from: u64) -> Value {
+        let mut raw: Value = serde_yaml::from_str(yaml).unwrap();
+        migrate(&mut raw, from).unwrap();
+
+        raw
+    }
+
+    #[test]
+    fn migrations_chain_from_version_0() {
+        let raw = migrated(
+            r#"
+files:
+  - path: /home/milo/.zshrc
+    tag: work
+requires:
+  - [~, /home/milo/.greatness/pulled/dots/files]
+"#,
+            0,
+        );
+
+        assert_eq!(version_of(&raw).unwrap(), CURRENT_VERSION);
+        assert_eq!(raw["files"][0]["path"], Value::from("{{HOME}}/.zshrc"));
+        assert_eq!(raw["files"][0]["tags"], Value::from(vec!["work"]));
+        assert_eq!(raw["files"][0].get("tag"), None);
+        assert_eq!(raw["requires"][0]["name"], Value::from("dots"));
+        assert!(raw["requires"][0]["url"]
+            .as_str()
+            .unwrap()
+            .ends_with("/.greatness/pulled/dots"));
+    }
+
+    #[test]
+    fn null_manifests_migrate_to_empty_ones() {
+        let raw = migrated("~", 0);
+
+        assert_eq!(version_of(&raw).unwrap(), CURRENT_VERSION);
+        assert_eq!(raw.as_mapping().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn v2_to_v3_names_requirements_by_where_they_were_pulled() {
+        let raw = migrated(
+            r#"
+requires:
+  - [https://example.com/dots.git, "{{HOME}}/.greatness/pulled/work/dots/"]
+  - [https://example.com/other.git, "{{HOME}}/elsewhere/other/files"]
+"#,
+            2,
+        );
+
+        assert_eq!(raw["requires"][0]["name"], Value::from("work/dots"));
+        assert_eq!(
+            raw["requires"][0]["url"],
+            Value::from("https://example.com/dots.git")
+        );
+        assert_eq!(raw["requires"][1]["name"], Value::from("other"));
+    }
+
+    #[test]
+    fn v2_to_v3_needs_a_path() {
+        let mut raw: Value = serde_yaml::from_str("requires:\n  - [https://example.com]").unwrap();
+
+        assert!(migrate(&mut raw, 2).is_err());
+    }
+
+    #[test]
+    fn v3_to_v4_drops_empty_tags() {
+        let raw = migrated(
+            r#"
+files:
+  - path: "{{HOME}}/a"
+    tag: ""
+  - path: "{{HOME}}/b"
+"#,
+            3,
+        );
+
+        assert_eq!(raw["files"][0]["tags"], Value::Sequence(vec![]));
+        assert_eq!(raw["files"][1]["tags"], Value::Sequence(vec![]));
+    }
+}
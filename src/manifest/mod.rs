@@ -1,16 +1,23 @@
+pub mod expand;
+pub mod migrate;
+
+use crate::plan::{self, Action};
+use crate::pull::transaction;
 use crate::script::ScriptsState;
+use crate::secret::SecretProvider;
 use crate::utils;
 use git2::Repository;
 use snafu::{ResultExt, Snafu};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::From;
 use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
 use std::result::Result;
 
-use log::debug;
+use log::{debug, info};
+use nix::fcntl::{flock, FlockArg};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Snafu)]
@@ -22,6 +29,48 @@ pub enum StateError {
         filename: PathBuf,
         source: serde_yaml::Error,
     },
+
+    #[snafu(display("Could not serialize great configuration: {}", source))]
+    SerializeError { source: serde_yaml::Error },
+
+    #[snafu(display("Could not write great configuration file ({}): {}", filename.display(), source))]
+    WriteError {
+        filename: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Could not open great lock file ({}): {}", filename.display(), source))]
+    LockOpenError {
+        filename: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Could not lock great directory ({}): {}", filename.display(), source))]
+    LockError {
+        filename: PathBuf,
+        source: nix::Error,
+    },
+
+    #[snafu(display("Great configuration file {} is version {}, but this greatness only understands up to version {}. Please update greatness!", filename.display(), found, supported))]
+    ManifestTooNew {
+        filename: PathBuf,
+        found: u64,
+        supported: u64,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Great configuration file has an unreadable version ({})!", found))]
+    UnknownVersion {
+        found: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to upgrade great configuration file from version {}: {}", from, reason))]
+    MigrationFailed {
+        from: u64,
+        reason: String,
+        source: std::io::Error,
+    },
 }
 
 /// Contains local data on disk and paths got dynamically.
@@ -32,10 +81,31 @@ pub struct State {
     pub greatness_state: PathBuf,
     pub greatness_git_pack_dir: PathBuf,
     pub greatness_scripts_dir: PathBuf,
+    /// Template variables for this machine only. Never packed.
+    pub greatness_variables: PathBuf,
+    /// What was last installed from each source, used as the base
+    /// when merging. See `merge::Conflicts`.
+    pub greatness_merge_bases_dir: PathBuf,
+    /// Files great replaced, one directory per run. See `backup`.
+    pub greatness_backups_dir: PathBuf,
+    /// Passphrase of encrypted files on this machine. Never packed.
+    pub greatness_key: PathBuf,
+    /// The exact commits of the required states. See `pull::lock`.
+    pub greatness_lock: PathBuf,
+    /// The files each pulled state wrote on this machine. See
+    /// `pull::installed`.
+    pub greatness_installed: PathBuf,
+    /// Journal of the pull in progress, next to the greatness directory
+    /// as pulling as main replaces it. See `pull::transaction`.
+    pub greatness_transaction_dir: PathBuf,
     pub repository: Option<Repository>,
     pub script_state: ScriptsState,
     pub package_context: PackageContext,
 
+    /// Advisory lock on the state directory, held until the state is
+    /// dropped. See `State::lock`.
+    lock: Option<File>,
+
     pub data: Manifest,
 }
 
@@ -45,10 +115,108 @@ pub struct State {
 pub struct AddedFile {
     #[serde(default)]
     pub path: PathBuf,
+    /// Tags to pick the file by. See `tag::selector`.
     #[serde(default)]
-    pub tag: Option<String>,
+    pub tags: Vec<String>,
     #[serde(default)]
     pub scripts: Option<Vec<PathBuf>>,
+    /// If set, the path is a directory, and everything inside of
+    /// it is tracked. See `AddedFile::expand`.
+    #[serde(default)]
+    pub directory: bool,
+    /// Globs of files inside a directory to track. Tracks
+    /// everything if none are given.
+    #[serde(default)]
+    pub include: Option<Vec<String>>,
+    /// Globs of files inside a directory not to track.
+    #[serde(default)]
+    pub exclude: Option<Vec<String>>,
+    /// How the file is put into place on the system.
+    #[serde(default)]
+    pub deploy: DeployMode,
+    /// Metadata of the file (or directory) itself, recorded so it
+    /// can be restored on install.
+    #[serde(default)]
+    pub metadata: Option<FileMetadata>,
+    /// Metadata of the files inside of a directory, keyed by their
+    /// path relative to it.
+    #[serde(default)]
+    pub contents: Option<BTreeMap<PathBuf, FileMetadata>>,
+    /// Only install the file on machines matching this. See
+    /// `Condition::is_met`.
+    #[serde(default)]
+    pub when: Option<Condition>,
+    /// If set, the packed copy is a template, rendered when the file
+    /// is installed. See `template::render_file`.
+    #[serde(default)]
+    pub template: bool,
+    /// If set, the packed copy is encrypted, and decrypted when the
+    /// file is installed. See `crypt`.
+    #[serde(default)]
+    pub encrypted: bool,
+}
+
+/// Which machines an entry is for. Every field that is set has to
+/// match. Lists match if any one of their values does, and may be
+/// written as a single value, such as `os: linux`.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct Condition {
+    /// Host names, as in `hostname`.
+    #[serde(default, with = "one_or_many", skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<Vec<String>>,
+    /// Operating systems, such as linux or macos.
+    #[serde(default, with = "one_or_many", skip_serializing_if = "Option::is_none")]
+    pub os: Option<Vec<String>>,
+    /// Distributions, by the ID (or ID_LIKE) in os-release, such as
+    /// arch or debian.
+    #[serde(default, with = "one_or_many", skip_serializing_if = "Option::is_none")]
+    pub distro: Option<Vec<String>>,
+    /// CPU architectures, such as x86_64 or aarch64.
+    #[serde(default, with = "one_or_many", skip_serializing_if = "Option::is_none")]
+    pub arch: Option<Vec<String>>,
+    /// Names of the user running greatness.
+    #[serde(default, with = "one_or_many", skip_serializing_if = "Option::is_none")]
+    pub user: Option<Vec<String>>,
+    /// Environment variables, and the value each must have. A
+    /// variable without a value (`~`) only has to be set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<BTreeMap<String, Option<String>>>,
+}
+
+/// Metadata of a file, which git would otherwise lose.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct FileMetadata {
+    /// Permission bits, written in octal, such as "600".
+    #[serde(default, with = "octal")]
+    pub mode: u32,
+    /// Name of the user owning the file.
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Last modification time, in seconds since the epoch.
+    #[serde(default)]
+    pub mtime: Option<i64>,
+    /// SHA-256 of the contents when the file was last packed. Lets
+    /// packing skip unchanged files, and tells when a file drifted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// SHA-256 of the packed ciphertext of an encrypted file, so that
+    /// packing can tell it changed without decrypting it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packed_hash: Option<String>,
+}
+
+/// How an added file is put into place on the system.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployMode {
+    /// Copy the file out of the repository.
+    Copy,
+    /// Symlink the file to its copy in the repository, so edits
+    /// land in the repository straight away.
+    Symlink,
+    /// Hardlink the file to its copy in the repository. Falls back
+    /// to copying if the two are on different file systems.
+    Hardlink,
 }
 
 /// Contains information about software that needs
@@ -60,6 +228,9 @@ pub struct AddedPackage {
     pub package: String,
     #[serde(default)]
     pub package_overloads: HashMap<String, String>,
+    /// Only install the package on machines matching this.
+    #[serde(default)]
+    pub when: Option<Condition>,
 }
 
 /// Contains information pretaining to how to install
@@ -71,11 +242,25 @@ pub struct PackageContext {
     /// importance, and then prefix itself in third.
     #[serde(default)]
     pub package_install_prefix: HashMap<String, (bool, u8, Vec<String>)>,
+    /// The prefix to uninstall a package, based on the name of the
+    /// package manager. Whether to run as root is the same as above.
+    #[serde(default)]
+    pub package_uninstall_prefix: HashMap<String, Vec<String>>,
+    /// The command that succeeds if a package is installed, based on
+    /// the name of the package manager. Packages that were installed
+    /// before are never uninstalled, and neither is anything installed
+    /// with a manager that has no command here.
+    #[serde(default)]
+    pub package_query: HashMap<String, (String, Vec<String>)>,
 }
 
 /// Data stored in the state that is stored locally on the computer
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Manifest {
+    /// Version of the manifest schema. See `migrate`.
+    #[serde(default)]
+    pub version: u64,
+
     /// Software that needs to be installed
     #[serde(default)]
     pub packages: Option<Vec<AddedPackage>>,
@@ -84,18 +269,82 @@ pub struct Manifest {
     #[serde(default)]
     pub files: Option<Vec<AddedFile>>,
 
-    /// Required repositories of dotfiles. First element is an optional URL
-    /// in which to update, and the second is a required path on the local disk.
+    /// Path variables defined by the user, such as CODE for
+    /// {{HOME}}/src. Used like the built in {{HOME}}.
+    #[serde(default)]
+    pub path_variables: Option<BTreeMap<String, PathBuf>>,
+
+    /// Variables for templates. Can be overridden on each machine,
+    /// see `template::variables`.
     #[serde(default)]
-    pub requires: Option<Vec<(Option<String>, PathBuf)>>,
+    pub variables: Option<BTreeMap<String, serde_yaml::Value>>,
+
+    /// Where templates and scripts get secrets from. See `secret`.
+    #[serde(default)]
+    pub secrets: Option<SecretProvider>,
+
+    /// Required states, which are pulled along with this one.
+    #[serde(default)]
+    pub requires: Option<Vec<Requirement>>,
+}
+
+/// A state required by another. The exact commit installed is kept in
+/// greatness.lock, see `pull::lock`.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Requirement {
+    /// What the state is called. It is pulled to this, under the
+    /// pulled directory.
+    pub name: String,
+    /// Where the state is pulled from, and updated from by `pull update`.
+    /// Anything `pull add` takes.
+    pub url: String,
+    /// What to check out. Without a pin, the default branch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<Pin>,
+    /// Only install files this tag selector picks, such as `shell,editor`.
+    /// See `tag::selector`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+/// What of a git repository to check out.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Pin {
+    /// The latest commit of a branch.
+    Branch(String),
+    /// A tag, which is expected to never move.
+    Tag(String),
+    /// An exact commit.
+    Commit(String),
+}
+
+impl std::fmt::Display for Pin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Branch(branch) => write!(f, "branch {}", branch),
+            Self::Tag(tag) => write!(f, "tag {}", tag),
+            Self::Commit(commit) => write!(f, "commit {}", commit),
+        }
+    }
+}
+
+impl Pin {
+    /// Gets the revision of a cloned repository the pin points at.
+    pub fn revision(&self) -> String {
+        match self {
+            Self::Branch(branch) => format!("refs/remotes/origin/{}", branch),
+            Self::Tag(tag) => format!("refs/tags/{}", tag),
+            Self::Commit(commit) => commit.clone(),
+        }
+    }
 }
 
 impl From<PathBuf> for AddedFile {
     fn from(path: PathBuf) -> Self {
         Self {
             path,
-            tag: Some("".to_owned()),
-            scripts: None,
+            ..Default::default()
         }
     }
 }
@@ -104,8 +353,8 @@ impl From<(PathBuf, String)> for AddedFile {
     fn from((path, tag): (PathBuf, String)) -> Self {
         Self {
             path,
-            tag: Some(tag),
-            scripts: None,
+            tags: vec![tag],
+            ..Default::default()
         }
     }
 }
@@ -114,8 +363,129 @@ impl Default for AddedFile {
     fn default() -> Self {
         Self {
             path: PathBuf::from(""),
-            tag: None,
+            tags: vec![],
             scripts: None,
+            directory: false,
+            include: None,
+            exclude: None,
+            deploy: DeployMode::Copy,
+            metadata: None,
+            contents: None,
+            when: None,
+            template: false,
+            encrypted: false,
+        }
+    }
+}
+
+impl AddedFile {
+    /// Gets the recorded metadata of a (special) path this entry
+    /// stands for. See `AddedFile::expand`.
+    pub fn metadata_for(&self, special: &PathBuf) -> Option<&FileMetadata> {
+        if !self.directory {
+            return self.metadata.as_ref();
+        }
+
+        let relative = special.strip_prefix(&self.path).ok()?;
+        self.contents.as_ref()?.get(relative)
+    }
+
+    /// Records the metadata of a (special) path this entry stands for.
+    pub fn set_metadata_for(&mut self, special: &PathBuf, metadata: FileMetadata) {
+        if !self.directory {
+            self.metadata = Some(metadata);
+            return;
+        }
+
+        if let Ok(relative) = special.strip_prefix(&self.path) {
+            self.contents
+                .get_or_insert_with(BTreeMap::new)
+                .insert(relative.to_path_buf(), metadata);
+        }
+    }
+}
+
+/// (De)serializes permission bits as an octal string, the way
+/// people are used to reading them. Plain numbers, like `mode: 600`,
+/// are read as octal too.
+mod octal {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mode: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:o}", mode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+        match serde_yaml::Value::deserialize(deserializer)? {
+            serde_yaml::Value::Number(n) => {
+                u32::from_str_radix(&n.to_string(), 8).map_err(serde::de::Error::custom)
+            }
+            serde_yaml::Value::String(s) => {
+                u32::from_str_radix(&s, 8).map_err(serde::de::Error::custom)
+            }
+            _ => Err(serde::de::Error::custom("expected an octal file mode")),
+        }
+    }
+}
+
+/// (De)serializes a list that may also be written as a single value.
+mod one_or_many {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    pub fn serialize<S: Serializer>(
+        values: &Option<Vec<String>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match values {
+            Some(values) if values.len() == 1 => serializer.serialize_str(&values[0]),
+            Some(values) => serializer.collect_seq(values),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<String>>, D::Error> {
+        Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+            Some(OneOrMany::One(value)) => Some(vec![value]),
+            Some(OneOrMany::Many(values)) => Some(values),
+            None => None,
+        })
+    }
+}
+
+impl Default for DeployMode {
+    fn default() -> Self {
+        Self::Copy
+    }
+}
+
+impl std::fmt::Display for DeployMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Copy => write!(f, "copy"),
+            Self::Symlink => write!(f, "symlink"),
+            Self::Hardlink => write!(f, "hardlink"),
+        }
+    }
+}
+
+impl std::str::FromStr for DeployMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "copy" => Ok(Self::Copy),
+            "symlink" => Ok(Self::Symlink),
+            "hardlink" => Ok(Self::Hardlink),
+            _ => Err(format!("unknown deploy mode {}", s)),
         }
     }
 }
@@ -136,6 +506,26 @@ impl PackageContext {
                 "brew".into() =>   (false, 1, vec!["install".into()]),
                 "port".into() =>   (false, 0, vec!["install".into()]),
             },
+            package_uninstall_prefix: hashmap! {
+                "pacman".into() => vec!["-Rs".into()],
+                "paru".into() =>   vec!["--noconfirm".into(), "-Rs".into()],
+                "yay".into() =>    vec!["--noconfirm".into(), "-Rs".into()],
+                "emerge".into() => vec!["--unmerge".into()],
+                "apt".into() =>    vec!["remove".into()],
+                "rpm".into() =>    vec!["-e".into()],
+                "dnf".into() =>    vec!["remove".into()],
+                "brew".into() =>   vec!["uninstall".into()],
+                "port".into() =>   vec!["uninstall".into()],
+            },
+            package_query: hashmap! {
+                "pacman".into() => ("pacman".into(), vec!["-Q".into()]),
+                "paru".into() =>   ("pacman".into(), vec!["-Q".into()]),
+                "yay".into() =>    ("pacman".into(), vec!["-Q".into()]),
+                "apt".into() =>    ("dpkg".into(), vec!["-s".into()]),
+                "rpm".into() =>    ("rpm".into(), vec!["-q".into()]),
+                "dnf".into() =>    ("rpm".into(), vec!["-q".into()]),
+                "brew".into() =>   ("brew".into(), vec!["list".into()]),
+            },
         }
     }
 }
@@ -152,33 +542,120 @@ impl AddedPackage {
         Self {
             package: "".into(),
             package_overloads: hashmap! {},
+            when: None,
         }
     }
 }
 
 impl Manifest {
     /// Load on file data into the stateData struct.
+    /// Older manifests are upgraded to the current version, after
+    /// backing up the original next to it. Only for the main state;
+    /// other states are read with `read_from_file`.
     pub fn populate_from_file(state_info: &State) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load(state_info, true)
+    }
+
+    /// Load the manifest of a state that isn't ours to change, such as
+    /// a pulled or packed one. Older manifests are upgraded in memory
+    /// only.
+    pub fn read_from_file(state_info: &State) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load(state_info, false)
+    }
+
+    fn load(state_info: &State, write_back: bool) -> Result<Self, Box<dyn std::error::Error>> {
         let state_file = &state_info.greatness_state;
-        let x = serde_yaml::from_str(
+        let mut raw: serde_yaml::Value = serde_yaml::from_str(
             &fs::read_to_string(&state_file).context(utils::FileReadError { file: &state_file })?,
         )
         .context(ParseError {
             filename: state_file,
         })?;
 
+        let version = migrate::version_of(&raw)?;
+        if version > migrate::CURRENT_VERSION {
+            Err(std::io::Error::from(std::io::ErrorKind::InvalidData)).context(
+                ManifestTooNew {
+                    filename: state_file,
+                    found: version,
+                    supported: migrate::CURRENT_VERSION,
+                },
+            )?;
+        }
+
+        let migrating = version < migrate::CURRENT_VERSION;
+        if migrating && write_back {
+            let mut backup = state_file.clone();
+            backup.set_extension(format!("yaml.v{}.bak", version));
+
+            info!(
+                "Upgrading great configuration file {} from version {} to {} (backup at {})....",
+                state_file.display(),
+                version,
+                migrate::CURRENT_VERSION,
+                backup.display()
+            );
+
+            if !plan::record(Action::Backup {
+                path: state_file.clone(),
+                to: backup.clone(),
+            }) {
+                fs::copy(&state_file, &backup).context(utils::FileCopyError {
+                    src: state_file,
+                    dest: &backup,
+                })?;
+            }
+        } else if migrating {
+            debug!(
+                "Reading great configuration file {} of version {} as version {}....",
+                state_file.display(),
+                version,
+                migrate::CURRENT_VERSION
+            );
+        }
+
+        if migrating {
+            migrate::migrate(&mut raw, version)?;
+        }
+
+        let x: Self = serde_yaml::from_value(raw).context(ParseError {
+            filename: state_file,
+        })?;
+
+        if migrating && write_back {
+            x.populate_file(state_info)?;
+        }
+
         Ok(x)
     }
 
-    /// Serialize data back into the local file on disk.
-    pub fn populate_file(&self, state: &State) {
-        let mut s = serde_yaml::to_string(self).unwrap(); // TODO: Figure out if we actually have to worry about this
-        let mut f = File::create(&state.greatness_state).unwrap();
+    /// Serialize data back into the local file on disk. The file is
+    /// replaced atomically, so a crash never leaves it half written.
+    pub fn populate_file(&self, state: &State) -> Result<(), StateError> {
+        let s = serde_yaml::to_string(self).context(SerializeError {})?;
 
         debug!("Writing to file:\n{}", s);
 
-        f.set_len(0).unwrap(); // Erase the file;
-        f.write_all(unsafe { s.as_mut_vec() }).unwrap();
+        if plan::is_dry_run() {
+            let old = fs::read_to_string(&state.greatness_state).unwrap_or_default();
+            if old != s {
+                plan::record(Action::ChangeManifest {
+                    path: state.greatness_state.clone(),
+                    diff: diffy::create_patch(&old, &s).to_string(),
+                });
+            }
+
+            return Ok(());
+        }
+
+        transaction::touch(&state.greatness_state).context(WriteError {
+            filename: &state.greatness_state,
+        })?;
+        utils::write_atomically(&state.greatness_state, s.as_bytes()).context(WriteError {
+            filename: &state.greatness_state,
+        })?;
+
+        Ok(())
     }
 
     /// Detects if we already contain a file
@@ -249,11 +726,11 @@ impl Manifest {
         }
 
         for file in self.files.clone().unwrap() {
-            if file.tag.is_none() {
-                continue;
+            for tag in file.tags {
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
             }
-
-            tags.push(file.tag.unwrap());
         }
 
         Some(tags)
@@ -289,8 +766,12 @@ impl Manifest {
 impl Default for Manifest {
     fn default() -> Self {
         Self {
+            version: migrate::CURRENT_VERSION,
             packages: None,
             files: None,
+            path_variables: None,
+            variables: None,
+            secrets: None,
             requires: None,
         }
     }
@@ -308,6 +789,21 @@ impl State {
         greatness_git_pack_dir.push("git");
         let mut greatness_scripts_dir = PathBuf::from(state_dir.clone());
         greatness_scripts_dir.push("scripts");
+        let mut greatness_variables = PathBuf::from(state_dir.clone());
+        greatness_variables.push("variables.yaml");
+        let mut greatness_merge_bases_dir = PathBuf::from(state_dir.clone());
+        greatness_merge_bases_dir.push("bases");
+        let mut greatness_backups_dir = PathBuf::from(state_dir.clone());
+        greatness_backups_dir.push("backups");
+        let mut greatness_key = PathBuf::from(state_dir.clone());
+        greatness_key.push("key");
+        let mut greatness_lock = PathBuf::from(state_dir.clone());
+        greatness_lock.push("greatness.lock");
+        let mut greatness_installed = PathBuf::from(state_dir.clone());
+        greatness_installed.push("installed.yaml");
+        let mut greatness_transaction_dir = state_dir.clone().into_os_string();
+        greatness_transaction_dir.push("-pull");
+        let greatness_transaction_dir = PathBuf::from(greatness_transaction_dir);
 
         let mut script_state = ScriptsState::new();
         script_state.register_all();
@@ -332,10 +828,53 @@ impl State {
             greatness_pulled_dir,
             greatness_git_pack_dir,
             greatness_scripts_dir,
+            greatness_variables,
+            greatness_merge_bases_dir,
+            greatness_backups_dir,
+            greatness_key,
+            greatness_lock,
+            greatness_installed,
+            greatness_transaction_dir,
             repository,
             script_state,
             package_context: PackageContext::new(),
+            lock: None,
             data: Manifest::default(),
         })
     }
+
+    /// Take an exclusive advisory lock on the state directory, so that
+    /// two great processes can't clobber each others changes. Waits
+    /// for the other process if the lock is already taken. The lock is
+    /// released when the state is dropped.
+    pub fn lock(&mut self) -> Result<(), StateError> {
+        let mut lock_file = self.greatness_dir.clone();
+        lock_file.push(".lock");
+
+        let f = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&lock_file)
+            .context(LockOpenError {
+                filename: &lock_file,
+            })?;
+
+        match flock(f.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => (),
+            Err(nix::errno::Errno::EWOULDBLOCK) => {
+                info!("Another great process is running, waiting for it to finish....");
+
+                flock(f.as_raw_fd(), FlockArg::LockExclusive).context(LockError {
+                    filename: &lock_file,
+                })?;
+            }
+            Err(e) => Err(e).context(LockError {
+                filename: &lock_file,
+            })?,
+        }
+
+        self.lock = Some(f);
+
+        Ok(())
+    }
 }
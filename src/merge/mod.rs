@@ -0,0 +1,413 @@
+use crate::crypt;
+use crate::manifest::State;
+use crate::plan;
+use crate::pull::transaction;
+use crate::utils;
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use question::{Answer, Question};
+use snafu::{ResultExt, Snafu};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Snafu)]
+pub enum MergeError {
+    #[snafu(display("Invalid great conflict resolution {}! Expected overwrite, skip, merge or ask: {}", found, source))]
+    InvalidOnConflict {
+        found: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Great merge tool {} can't be split into words: {}", command, source))]
+    InvalidTool {
+        command: String,
+        source: shell_words::ParseError,
+    },
+
+    #[snafu(display("Failed to run great merge tool {}: {}", command, source))]
+    ToolError {
+        command: String,
+        source: subprocess::PopenError,
+    },
+
+    #[snafu(display("Great merge tool {} failed to merge {}! It was left alone: {}", command, file.display(), source))]
+    ToolFailed {
+        command: String,
+        file: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// What to do when a file being installed already exists, and is
+/// different.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OnConflict {
+    /// Back up the existing file, and install over it.
+    Overwrite,
+    /// Keep the existing file.
+    Skip,
+    /// Three-way merge the two. See `Conflicts::merge`.
+    Merge,
+    /// Ask which of the above to do, for every file.
+    Ask,
+}
+
+impl FromStr for OnConflict {
+    type Err = MergeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overwrite" | "o" => Ok(Self::Overwrite),
+            "skip" | "s" => Ok(Self::Skip),
+            "merge" | "m" => Ok(Self::Merge),
+            "ask" => Ok(Self::Ask),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+                .context(InvalidOnConflict { found: s }),
+        }
+    }
+}
+
+/// How to resolve collisions with files that already exist, and
+/// where the merge bases of the source being installed are kept.
+/// The base of a file is what was last installed to it from that
+/// source, so a merge knows which side changed what.
+pub struct Conflicts {
+    pub on_conflict: OnConflict,
+    /// Command to resolve conflicting merges with, instead of leaving
+    /// conflict markers. See `Conflicts::run_tool`.
+    pub tool: Option<String>,
+    bases: PathBuf,
+}
+
+impl Conflicts {
+    /// * `source` - Name of what is being installed, see `source_name`.
+    pub fn from_matches(
+        matches: &ArgMatches,
+        state: &State,
+        source: &str,
+    ) -> Result<Self, MergeError> {
+        let on_conflict = match matches.value_of("on-conflict") {
+            Some(s) => OnConflict::from_str(s)?,
+            None => OnConflict::Ask,
+        };
+
+        Ok(Self {
+            on_conflict,
+            tool: matches.value_of("merge-tool").map(str::to_owned),
+            bases: state.greatness_merge_bases_dir.join(source),
+        })
+    }
+
+    /// Decide what to do with a collision. A dry run plans for the
+    /// default answer, overwriting.
+    pub fn choose(&self, file: &PathBuf) -> OnConflict {
+        if self.on_conflict != OnConflict::Ask {
+            return self.on_conflict;
+        }
+
+        if plan::is_dry_run() {
+            return OnConflict::Overwrite;
+        }
+
+        info!("Note that skipping doing this could cause the dotfiles you are pulling and merging to not work. A backup WILL be made!");
+
+        let answer = Question::new(&format!(
+            "Do you want to (o)verwrite, (s)kip or (m)erge {}?",
+            file.display()
+        ))
+        .acceptable(vec!["o", "s", "m", "overwrite", "skip", "merge"])
+        .until_acceptable()
+        .default(Answer::RESPONSE("o".to_owned()))
+        .show_defaults()
+        .ask();
+
+        match answer {
+            Some(Answer::RESPONSE(r)) => OnConflict::from_str(&r).unwrap_or(OnConflict::Overwrite),
+            _ => OnConflict::Overwrite,
+        }
+    }
+
+    fn base_of(&self, special: &PathBuf) -> PathBuf {
+        utils::special_in_dir(&self.bases, special)
+    }
+
+    /// Remember what was installed to a (special) path, as the base
+    /// of the next merge. The bases of encrypted files are kept
+    /// encrypted.
+    pub fn record_base(
+        &self,
+        special: &PathBuf,
+        contents: &[u8],
+        encrypted: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if plan::is_dry_run() {
+            return Ok(());
+        }
+
+        let base = self.base_of(special);
+        let contents = match encrypted {
+            true => crypt::encrypt(contents, &base)?,
+            false => contents.to_vec(),
+        };
+
+        transaction::touch(&base).context(utils::FileWriteError { file: &base })?;
+        if let Some(parent) = base.parent() {
+            std::fs::create_dir_all(parent).context(utils::DirCreationError { dir: parent })?;
+        }
+
+        Ok(utils::write_atomically(&base, &contents).context(utils::FileWriteError { file: &base })?)
+    }
+
+    /// Three-way merge incoming contents (theirs) into the file at
+    /// `live` (ours). Returns the merged contents, or nothing if the
+    /// files can't be merged, because they are binary.
+    pub fn merge(
+        &self,
+        special: &PathBuf,
+        live: &PathBuf,
+        theirs: &[u8],
+        encrypted: bool,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        let base_file = self.base_of(special);
+        let base = match std::fs::read(&base_file) {
+            Ok(base) if encrypted => Some(crypt::decrypt(&base, &base_file)?),
+            Ok(base) => Some(base),
+            Err(_) => None,
+        };
+        let ours = std::fs::read(live).context(utils::FileReadError { file: live })?;
+
+        let (base, ours, theirs) = match (
+            utils::as_text(base.as_deref().unwrap_or(&[])),
+            utils::as_text(&ours),
+            utils::as_text(theirs),
+        ) {
+            (Some(base), Some(ours), Some(theirs)) => (base, ours, theirs),
+            _ => {
+                warn!("{} is binary, and can't be merged!", live.display());
+                return Ok(None);
+            }
+        };
+
+        if base.is_empty() {
+            warn!(
+                "Nothing was installed to {} from here before, so everything that differs conflicts!",
+                live.display()
+            );
+        }
+
+        match diffy::merge(base, ours, theirs) {
+            Ok(merged) => {
+                info!("Merged {} without conflicts, which is great!", live.display());
+                Ok(Some(merged.into_bytes()))
+            }
+            Err(conflicted) => Ok(Some(self.resolve(live, base, ours, theirs, conflicted)?)),
+        }
+    }
+
+    /// Resolve a merge with conflicts, with the merge tool, `$EDITOR`
+    /// when asking, or by leaving the conflict markers in.
+    fn resolve(
+        &self,
+        live: &PathBuf,
+        base: &str,
+        ours: &str,
+        theirs: &str,
+        conflicted: String,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if plan::is_dry_run() {
+            warn!("Merging {} would conflict!", live.display());
+            return Ok(conflicted.into_bytes());
+        }
+
+        let editor = std::env::var("EDITOR").ok().filter(|e| !e.is_empty());
+        let command = match (&self.tool, editor) {
+            (Some(tool), _) => tool.clone(),
+            (None, Some(editor)) if self.on_conflict == OnConflict::Ask => {
+                let answer = Question::new(&format!(
+                    "Merging {} conflicts. Do you want to resolve the conflicts in {}?",
+                    live.display(),
+                    editor
+                ))
+                .default(Answer::YES)
+                .show_defaults()
+                .confirm();
+
+                if answer != Answer::YES {
+                    return Ok(leave_markers(live, conflicted));
+                }
+
+                format!("{} $MERGED", editor)
+            }
+            _ => return Ok(leave_markers(live, conflicted)),
+        };
+
+        // The sides may be the plaintext of encrypted files
+        let tmp_dir = crypt::private_tmp_dir()?;
+
+        let result = self.run_tool(&command, &tmp_dir, live, base, ours, theirs, &conflicted);
+        let _ = std::fs::remove_dir_all(&tmp_dir);
+
+        result
+    }
+
+    /// Runs a merge tool, git mergetool style. `$BASE`, `$LOCAL`,
+    /// `$REMOTE` and `$MERGED` in the command are replaced with the
+    /// paths of each side, and of the file to write the result to,
+    /// which starts out with conflict markers. If none of them are
+    /// used, `$LOCAL $BASE $REMOTE $MERGED` is appended.
+    #[allow(clippy::too_many_arguments)]
+    fn run_tool(
+        &self,
+        command: &str,
+        tmp_dir: &PathBuf,
+        live: &PathBuf,
+        base: &str,
+        ours: &str,
+        theirs: &str,
+        conflicted: &str,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        // Quoted words are kept together, like in a shell
+        let mut words = shell_words::split(command).context(InvalidTool { command })?;
+
+        let name = live.file_name().unwrap_or_default().to_str().unwrap_or("file");
+        let mut sides = vec![];
+        for (placeholder, prefix, contents) in &[
+            ("$LOCAL", "LOCAL.", ours),
+            ("$BASE", "BASE.", base),
+            ("$REMOTE", "REMOTE.", theirs),
+            ("$MERGED", "", conflicted),
+        ] {
+            let path = tmp_dir.join(format!("{}{}", prefix, name));
+            std::fs::write(&path, contents).context(utils::FileWriteError { file: &path })?;
+            sides.push((*placeholder, path));
+        }
+
+        if !words.iter().any(|w| sides.iter().any(|(p, _)| w.contains(p))) {
+            words.extend(sides.iter().map(|(p, _)| p.to_string()));
+        }
+
+        let words = words
+            .iter()
+            .map(|w| {
+                sides.iter().fold(w.clone(), |w, (p, path)| {
+                    w.replace(p, path.to_str().unwrap())
+                })
+            })
+            .collect::<Vec<String>>();
+
+        debug!("Resolving conflicts in {} with {}....", live.display(), command);
+        let status = subprocess::Exec::cmd(&words[0])
+            .args(&words[1..])
+            .join()
+            .context(ToolError { command })?;
+
+        if !status.success() {
+            return Err(std::io::Error::from(std::io::ErrorKind::Other))
+                .context(ToolFailed { command, file: live })?;
+        }
+
+        let merged = &sides[3].1;
+        let merged = std::fs::read(merged).context(utils::FileReadError { file: merged })?;
+        if utils::as_text(&merged).map_or(false, |m| m.contains("<<<<<<<")) {
+            warn!("{} still has conflict markers in it!", live.display());
+        }
+
+        Ok(merged)
+    }
+}
+
+fn leave_markers(live: &PathBuf, conflicted: String) -> Vec<u8> {
+    warn!(
+        "Merging {} conflicts! The conflicts are marked with <<<<<<< and >>>>>>>, so please resolve them by hand.",
+        live.display()
+    );
+
+    conflicted.into_bytes()
+}
+
+/// Gets the name merge bases are kept under for something being
+/// installed from `from`. Pulled repositories are named after where
+/// they were pulled to, and everything else is the main repository.
+pub fn source_name(state: &State, from: &PathBuf) -> String {
+    match from.strip_prefix(&state.greatness_pulled_dir) {
+        Ok(relative) if relative.components().count() != 0 => {
+            relative.to_str().unwrap().to_owned()
+        }
+        _ => "main".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflicts(dir: &tempfile::TempDir, tool: Option<&str>) -> Conflicts {
+        Conflicts {
+            on_conflict: OnConflict::Merge,
+            tool: tool.map(str::to_owned),
+            bases: dir.path().join("bases"),
+        }
+    }
+
+    #[test]
+    fn on_conflict_has_short_names() {
+        assert_eq!(OnConflict::from_str("m").unwrap(), OnConflict::Merge);
+        assert_eq!(
+            OnConflict::from_str("overwrite").unwrap(),
+            OnConflict::Overwrite
+        );
+        assert!(OnConflict::from_str("x").is_err());
+    }
+
+    #[test]
+    fn changes_on_both_sides_are_merged() {
+        let dir = tempfile::tempdir().unwrap();
+        let conflicts = conflicts(&dir, None);
+        let special = PathBuf::from("~/.bashrc");
+        let live = dir.path().join(".bashrc");
+
+        conflicts
+            .record_base(&special, b"a\nb\nc\n", false)
+            .unwrap();
+        std::fs::write(&live, "ours\nb\nc\n").unwrap();
+        let merged = conflicts
+            .merge(&special, &live, b"a\nb\ntheirs\n", false)
+            .unwrap();
+
+        assert_eq!(merged.unwrap(), b"ours\nb\ntheirs\n");
+    }
+
+    #[test]
+    fn conflicts_without_a_tool_are_marked() {
+        let dir = tempfile::tempdir().unwrap();
+        let conflicts = conflicts(&dir, None);
+        let special = PathBuf::from("~/.bashrc");
+        let live = dir.path().join(".bashrc");
+
+        conflicts.record_base(&special, b"a\n", false).unwrap();
+        std::fs::write(&live, "ours\n").unwrap();
+        let merged = conflicts
+            .merge(&special, &live, b"theirs\n", false)
+            .unwrap();
+
+        assert!(String::from_utf8(merged.unwrap())
+            .unwrap()
+            .contains("<<<<<<<"));
+    }
+
+    #[test]
+    fn tools_are_split_like_a_shell() {
+        let dir = tempfile::tempdir().unwrap();
+        let conflicts = conflicts(&dir, Some("sh -c 'cp \"$0\" \"$1\"' $REMOTE $MERGED"));
+        let special = PathBuf::from("~/.bashrc");
+        let live = dir.path().join(".bashrc");
+
+        conflicts.record_base(&special, b"a\n", false).unwrap();
+        std::fs::write(&live, "ours\n").unwrap();
+        let merged = conflicts
+            .merge(&special, &live, b"theirs\n", false)
+            .unwrap();
+
+        assert_eq!(merged.unwrap(), b"theirs\n");
+    }
+}
@@ -1,7 +1,12 @@
-use crate::manifest::State;
+use crate::condition;
+use crate::crypt;
+use crate::deploy;
+use crate::manifest::{AddedFile, DeployMode, FileMetadata, State};
+use crate::plan::{self, Action};
+use crate::tag::selector::{self, Selector};
 use crate::utils;
 use clap::ArgMatches;
-use log::debug;
+use log::{debug, info, warn};
 use snafu::{ResultExt, Snafu};
 use std::path::PathBuf;
 
@@ -14,56 +19,114 @@ pub enum PackError {
     },
 }
 
+/// What packing did to a single file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Packed {
+    Added,
+    Changed,
+    Unchanged,
+}
+
+/// How many files a pack touched.
+#[derive(Debug, Default)]
+pub struct PackSummary {
+    pub added: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+}
+
+impl PackSummary {
+    fn count(&mut self, packed: Packed) {
+        match packed {
+            Packed::Added => self.added += 1,
+            Packed::Changed => self.changed += 1,
+            Packed::Unchanged => self.unchanged += 1,
+        }
+    }
+}
+
+impl std::fmt::Display for PackSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} unchanged",
+            self.added, self.changed, self.unchanged
+        )?;
+
+        if self.removed != 0 {
+            write!(f, ", {} removed", self.removed)?;
+        }
+
+        Ok(())
+    }
+}
+
 /// Pack, and automatically call a packing backend
-pub fn pack(state: &mut State, _matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
-    let original_state_location = state.greatness_state.clone();
+pub fn pack(state: &mut State, matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let selector = selector::parse(matches.value_of("only-with-tag"))?;
+
     let base = PathBuf::from(&state.greatness_git_pack_dir);
-    state.greatness_state = base.clone();
-    if !base.as_path().exists() {
+    if !base.as_path().exists() && !plan::record(Action::CreateDir { path: base.clone() }) {
         std::fs::create_dir(&base).context(utils::DirCreationError { dir: &base })?;
     }
 
-    state.greatness_state.push("greatness.yaml");
-    if !state.greatness_state.as_path().exists() {
-        std::fs::File::create(&state.greatness_state).context(utils::FileCreationError {
-            file: &state.greatness_state,
-        })?;
-    }
+    let summary = pack_files(state, &base, selector.as_ref())?;
+    info!("Packed great files: {}.", summary);
 
-    pack_state(state, &original_state_location)?;
-    pack_files(state, &base)?;
+    // Packing records metadata, so save that before packing the state
+    state.data.populate_file(state)?;
+
+    pack_state(state, &base)?;
     pack_scripts(state, &base)?;
 
     Ok(())
 }
 
 /// Pack the state. Thats it.
-fn pack_state(
-    state: &mut State,
-    original_state_location: &PathBuf,
-) -> Result<(), Box<dyn std::error::Error>> {
+fn pack_state(state: &State, base: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    let mut to = base.clone();
+    to.push("greatness.yaml");
+
     // Pack the state
     debug!(
         "Packing state from {} -> {}....",
-        original_state_location.display(),
-        &state.greatness_state.display()
+        state.greatness_state.display(),
+        &to.display()
     );
-    std::fs::copy(&original_state_location, &state.greatness_state).context(
-        utils::FileCopyError {
-            src: &original_state_location,
-            dest: &state.greatness_state,
-        },
-    )?;
+    if plan::record(Action::WriteFile {
+        path: to.clone(),
+        from: Some(state.greatness_state.clone()),
+    }) {
+        return Ok(());
+    }
+
+    std::fs::copy(&state.greatness_state, &to).context(utils::FileCopyError {
+        src: &state.greatness_state,
+        dest: &to,
+    })?;
+
+    // The lock goes with it, so other machines pull the same commits
+    let lock_to = base.join("greatness.lock");
+    if state.greatness_lock.exists() {
+        std::fs::copy(&state.greatness_lock, &lock_to).context(utils::FileCopyError {
+            src: &state.greatness_lock,
+            dest: &lock_to,
+        })?;
+    } else if lock_to.exists() {
+        std::fs::remove_file(&lock_to).context(utils::FileDeletionError { file: &lock_to })?;
+    }
 
     Ok(())
 }
 
-/// Packs the scripts
+/// Packs the scripts. Only scripts that differ from their packed
+/// copy are copied, and packed scripts that were removed are removed.
 fn pack_scripts(state: &mut State, base: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
     let mut to = base.clone();
     to.push("scripts");
 
-    if !to.as_path().exists() {
+    if !to.as_path().exists() && !plan::record(Action::CreateDir { path: to.clone() }) {
         debug!(
             "Scripts dir doesn't exist! Creating at {}....",
             &to.display()
@@ -77,50 +140,379 @@ fn pack_scripts(state: &mut State, base: &PathBuf) -> Result<(), Box<dyn std::er
         to.display(),
     );
 
-    // Copy the directory recursively
-    fs_extra::dir::copy(
-        &state.greatness_scripts_dir,
-        to.parent().unwrap(),
-        &fs_extra::dir::CopyOptions::new(),
-    )?;
+    let scripts = relative_files_in(&state.greatness_scripts_dir)?;
+    for script in &scripts {
+        let from = state.greatness_scripts_dir.join(script);
+        let packed = to.join(script);
+
+        if packed.exists() && utils::hash_file(&packed)? == utils::hash_file(&from)? {
+            debug!("Script {} is unchanged, not packing....", from.display());
+            continue;
+        }
+
+        debug!("Packing script {} -> {}....", from.display(), packed.display());
+        if plan::record(Action::WriteFile {
+            path: packed.clone(),
+            from: Some(from.clone()),
+        }) {
+            continue;
+        }
+
+        std::fs::create_dir_all(packed.parent().unwrap()).context(utils::DirCreationError {
+            dir: packed.parent().unwrap(),
+        })?;
+        std::fs::copy(&from, &packed).context(utils::FileCopyError {
+            src: &from,
+            dest: &packed,
+        })?;
+    }
+
+    if to.exists() {
+        for stale in relative_files_in(&to)?.iter().filter(|s| !scripts.contains(s)) {
+            let stale = to.join(stale);
+            debug!("Removing stale packed script {}....", stale.display());
+            if plan::record(Action::Remove {
+                path: stale.clone(),
+            }) {
+                continue;
+            }
+
+            std::fs::remove_file(&stale).context(utils::FileDeletionError { file: &stale })?;
+        }
+    }
 
     Ok(())
 }
 
-/// Packs all the files.
-pub fn pack_files(state: &State, base: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(files) = &state.data.files {
+/// Every file in a directory, recursively, relative to it.
+fn relative_files_in(dir: &PathBuf) -> Result<Vec<PathBuf>, walkdir::Error> {
+    let mut files = vec![];
+    if !dir.exists() {
+        return Ok(files);
+    }
+
+    for entry in walkdir::WalkDir::new(dir) {
+        let entry = entry?;
+        if !entry.file_type().is_dir() {
+            files.push(entry.path().strip_prefix(dir).unwrap().to_path_buf());
+        }
+    }
+
+    Ok(files)
+}
+
+/// Packs all the files, recording their metadata. Directories are
+/// expanded, so that files added to them since the last pack are
+/// picked up, and files removed from them are removed from the pack.
+/// Files whose contents didn't change since the last pack are skipped,
+/// and so are files the selector doesn't pick, keeping what is packed.
+pub fn pack_files(
+    state: &mut State,
+    base: &PathBuf,
+    selector: Option<&Selector>,
+) -> Result<PackSummary, Box<dyn std::error::Error>> {
+    let mut summary = PackSummary::default();
+
+    if let Some(files) = &mut state.data.files {
         for file in files {
-            pack_file(&base, &file.path)?;
+            if !selector.map_or(true, |s| s.matches(&file.tags)) {
+                debug!(
+                    "Great file {} isn't picked by the tag selector, not packing....",
+                    file.path.display()
+                );
+                continue;
+            }
+
+            // Files for other machines may not exist here, keep what is packed
+            if !condition::is_active(&file.when) {
+                debug!(
+                    "Great file {} is not for this machine, not packing....",
+                    file.path.display()
+                );
+                continue;
+            }
+
+            let live = utils::special_to_absolute(&file.path);
+            let expanded = file.expand(&live)?;
+            for expanded_file in &expanded {
+                let last = file.metadata_for(expanded_file).cloned();
+                let (metadata, packed) = pack_file(
+                    &base,
+                    expanded_file,
+                    file.deploy,
+                    file.template,
+                    file.encrypted,
+                    last.as_ref(),
+                )?;
+                file.set_metadata_for(expanded_file, metadata);
+                summary.count(packed);
+            }
+
+            if file.directory {
+                let path = file.path.clone();
+                if let Some(contents) = &mut file.contents {
+                    contents.retain(|relative, _| expanded.contains(&path.join(relative)));
+                }
+
+                file.metadata = Some(deploy::metadata_of(&live)?);
+                summary.removed += remove_stale_files(base, file, &expanded)?;
+            }
         }
     }
 
-    Ok(())
+    Ok(summary)
 }
 
-/// Pack a file, git style
-fn pack_file(base: &PathBuf, file: &PathBuf) -> Result<(), utils::CommonErrors> {
+/// Remove files from a packed directory that are no longer
+/// in the live directory. Returns how many were removed.
+fn remove_stale_files(
+    base: &PathBuf,
+    file: &AddedFile,
+    expanded: &Vec<PathBuf>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut files_dir = base.clone();
+    files_dir.push("files");
+    let packed_dir = utils::special_in_dir(&files_dir, &file.path);
+    let mut removed = 0;
+
+    if !packed_dir.exists() {
+        return Ok(removed);
+    }
+
+    for packed in file.expand(&packed_dir)? {
+        if !expanded.contains(&packed) {
+            let stale = utils::special_in_dir(&files_dir, &packed);
+            debug!("Removing stale packed file {}....", stale.display());
+            removed += 1;
+            if plan::record(Action::Remove {
+                path: stale.clone(),
+            }) {
+                continue;
+            }
+
+            std::fs::remove_file(&stale).context(utils::FileDeletionError { file: &stale })?;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Pack a file, git style, and return its metadata. Files that are
+/// not copied onto the system are turned into links to their packed
+/// copy, once packed. The live copy of a template is what it rendered
+/// to, so once a template is packed, it is never packed over.
+/// Encrypted files are packed as ciphertext, and always copied.
+/// `last` is the metadata recorded when the file was last packed.
+fn pack_file(
+    base: &PathBuf,
+    file: &PathBuf,
+    mode: DeployMode,
+    template: bool,
+    encrypted: bool,
+    last: Option<&FileMetadata>,
+) -> Result<(FileMetadata, Packed), Box<dyn std::error::Error>> {
+    let last_hash = last.and_then(|m| m.hash.as_deref());
     let absolute_file = utils::special_to_absolute(file);
-    let mut to = base.clone();
-    to.push("files");
-    to.push(utils::absolute_to_special(&absolute_file));
+    let mut files_dir = base.clone();
+    files_dir.push("files");
+    let to = utils::special_in_dir(&files_dir, &utils::absolute_to_special(&absolute_file));
+    let mut metadata = deploy::metadata_of(&absolute_file)?;
+    let hash = utils::hash_file(&absolute_file)?;
+    metadata.hash = Some(hash.clone());
+
+    if !encrypted && deploy::same_file(&to, &absolute_file) {
+        debug!(
+            "{} is already linked to {}, nothing to pack....",
+            &absolute_file.display(),
+            &to.display()
+        );
+
+        // The deploy mode may have changed since the file was linked
+        if !deploy::is_deployed(&to, &absolute_file, mode) {
+            deploy::deploy(&to, &absolute_file, mode, Some(&metadata))?;
+        }
+
+        // Edits to a linked file already landed in the packed copy
+        let packed = match last_hash {
+            Some(last) if last != hash => Packed::Changed,
+            _ => Packed::Unchanged,
+        };
+
+        return Ok((metadata, packed));
+    }
+
+    if template && to.exists() {
+        // What it rendered to can't be packed over the template
+        match last_hash {
+            Some(last) if last != hash => warn!(
+                "{} was changed, but it is rendered from the template {}, so edit that instead!",
+                &absolute_file.display(),
+                &to.display()
+            ),
+            _ => debug!(
+                "{} is a template, keeping the packed template {}....",
+                &absolute_file.display(),
+                &to.display()
+            ),
+        }
+
+        return Ok((metadata, Packed::Unchanged));
+    }
+
+    // The packed copy may have changed underneath since the last pack,
+    // such as by git, so it is compared to as well. Ciphertext is
+    // compared to what it was when it was packed.
+    let packed_hash = utils::hash_file(&to).ok();
+    let expected = match encrypted {
+        true => last.and_then(|m| m.packed_hash.clone()),
+        false => Some(hash.clone()),
+    };
+    let unchanged = packed_hash.is_some()
+        && packed_hash == expected
+        && last_hash.map_or(true, |last| last == hash);
+    if unchanged {
+        debug!(
+            "{} is unchanged since it was last packed, not packing....",
+            &absolute_file.display()
+        );
+
+        // The live copy of an encrypted file is its plaintext, which is
+        // left be
+        if encrypted {
+            metadata.packed_hash = packed_hash;
+        } else if !deploy::is_deployed(&to, &absolute_file, mode) {
+            deploy::deploy(&to, &absolute_file, mode, Some(&metadata))?;
+        }
+
+        return Ok((metadata, Packed::Unchanged));
+    }
+
+    let packed = if to.exists() {
+        Packed::Changed
+    } else {
+        Packed::Added
+    };
 
     debug!(
         "Packing file from {} -> {}....",
         &absolute_file.display(),
         &to.display()
     );
-    if !to.as_path().exists() {
-        debug!("Files dir doesn't exist! Creating at {}....", &to.display());
-        std::fs::create_dir_all(to.parent().unwrap()).context(utils::DirCreationError {
-            dir: to.parent().unwrap(),
-        })?;
+    if !plan::record(Action::WriteFile {
+        path: to.clone(),
+        from: Some(absolute_file.clone()),
+    }) {
+        if !to.as_path().exists() {
+            debug!("Files dir doesn't exist! Creating at {}....", &to.display());
+            std::fs::create_dir_all(to.parent().unwrap()).context(utils::DirCreationError {
+                dir: to.parent().unwrap(),
+            })?;
+        }
+
+        if encrypted {
+            crypt::encrypt_file(&absolute_file, &to)?;
+            metadata.packed_hash = Some(utils::hash_file(&to)?);
+        } else {
+            std::fs::copy(&absolute_file, &to).context(utils::FileCopyError {
+                src: &absolute_file,
+                dest: &to,
+            })?;
+        }
+        deploy::restore_metadata(&to, &metadata)?;
     }
 
-    std::fs::copy(&absolute_file, &to).context(utils::FileCopyError {
-        src: &absolute_file,
-        dest: &to,
-    })?;
+    if mode != DeployMode::Copy && !encrypted {
+        deploy::deploy(&to, &absolute_file, mode, Some(&metadata))?;
+    }
 
-    Ok(())
+    Ok((metadata, packed))
+}
+
+/// Gets the (special) paths of an entry whose contents changed since
+/// it was last packed. Files packed before hashes were recorded are
+/// left out, as there is nothing to compare them to. For directories,
+/// files added to or removed from it count too.
+pub fn drifted(file: &AddedFile) -> Vec<PathBuf> {
+    let live = utils::special_to_absolute(&file.path);
+    let expanded = match file.expand(&live) {
+        Ok(e) => e,
+        Err(_) => return vec![],
+    };
+
+    let mut drifted = vec![];
+    for expanded_file in &expanded {
+        let recorded = match file.metadata_for(expanded_file) {
+            Some(r) => r,
+            None => {
+                if file.directory {
+                    drifted.push(expanded_file.clone());
+                }
+                continue;
+            }
+        };
+
+        if let Some(hash) = &recorded.hash {
+            let live = utils::special_to_absolute(expanded_file);
+            if utils::hash_file(&live).map_or(true, |h| &h != hash) {
+                drifted.push(expanded_file.clone());
+            }
+        }
+    }
+
+    if file.directory {
+        for relative in file.contents.iter().flat_map(|c| c.keys()) {
+            let removed = file.path.join(relative);
+            if !expanded.contains(&removed) {
+                drifted.push(removed);
+            }
+        }
+    }
+
+    drifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_files_are_packed() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("packed");
+        let live = dir.path().join("live");
+        std::fs::write(&live, "one").unwrap();
+        let pack = |last: Option<&FileMetadata>| {
+            pack_file(&base, &live, DeployMode::Copy, false, false, last).unwrap()
+        };
+
+        let (metadata, packed) = pack(None);
+        assert_eq!(packed, Packed::Added);
+        let (metadata, packed) = pack(Some(&metadata));
+        assert_eq!(packed, Packed::Unchanged);
+
+        std::fs::write(&live, "two").unwrap();
+        let (metadata, packed) = pack(Some(&metadata));
+        assert_eq!(packed, Packed::Changed);
+
+        // Such as by a git checkout
+        let to = utils::special_in_dir(&base.join("files"), &utils::absolute_to_special(&live));
+        std::fs::write(&to, "three").unwrap();
+        let (_, packed) = pack(Some(&metadata));
+        assert_eq!(packed, Packed::Changed);
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "two");
+    }
+
+    #[test]
+    fn removed_files_are_only_shown_when_there_are_some() {
+        let mut summary = PackSummary::default();
+        summary.count(Packed::Added);
+        summary.count(Packed::Unchanged);
+        assert_eq!(summary.to_string(), "1 added, 0 changed, 1 unchanged");
+
+        summary.removed = 2;
+        assert_eq!(
+            summary.to_string(),
+            "1 added, 0 changed, 1 unchanged, 2 removed"
+        );
+    }
 }
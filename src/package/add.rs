@@ -42,7 +42,7 @@ pub fn add(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::e
         }
     }
 
-    state.data.populate_file(&state);
+    state.data.populate_file(&state)?;
 
     Ok(())
 }
@@ -1,4 +1,7 @@
-use crate::manifest::State;
+use crate::condition;
+use crate::manifest::{AddedPackage, State};
+use crate::plan::{self, Action};
+use crate::pull::installed::InstalledPackage;
 use clap::ArgMatches;
 use log::{debug, info};
 use snafu::{ResultExt, Snafu};
@@ -30,9 +33,26 @@ pub enum PackageJogError {
         manager: String,
         source: subprocess::PopenError,
     },
+
+    #[snafu(display(
+        "The great package {} failed to uninstall with manager {}: {}",
+        package,
+        manager,
+        source
+    ))]
+    PackageUninstallFail {
+        package: String,
+        manager: String,
+        source: subprocess::PopenError,
+    },
 }
 
-pub fn jog(_matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+/// Install the packages of a state, returning those that weren't
+/// installed before.
+pub fn jog(
+    _matches: &ArgMatches,
+    state: &State,
+) -> Result<Vec<InstalledPackage>, Box<dyn std::error::Error>> {
     let manager = match super::get_manager(state) {
         Some(m) => m,
         None => {
@@ -40,73 +60,182 @@ pub fn jog(_matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::
         }
     };
 
+    let mut installed = vec![];
+
     // TODO: Option to install all packages at once
     if let Some(packages) = &state.data.packages {
         for package in packages {
-            let mut command = state
-                .package_context
-                .package_install_prefix
-                .get_key_value(&manager)
-                .unwrap()
-                .0
-                .clone();
-            let package_name = package.package.clone();
-            let mut args = state
-                .package_context
-                .package_install_prefix
-                .get(&manager)
-                .unwrap()
-                .2
-                .clone();
-
-            // Runs if we have overloads to deal with
-            if package.package_overloads.len() != 0 {
-                let mut to_use: (u8, String) = (0, "".into());
-                for overload in &package.package_overloads {
-                    let x = state
-                        .package_context
-                        .package_install_prefix
-                        .get_key_value(overload.0)
-                        .unwrap();
-
-                    if x.1 .1 > to_use.0 {
-                        to_use = (x.1 .1, x.0.clone());
-                    }
-                }
-
-                command = to_use.1;
+            if !condition::is_active(&package.when) {
+                debug!(
+                    "Great package {} is not for this machine, skipping....",
+                    package.package
+                );
+                continue;
             }
 
-            // Runs if we need to run the command as root.
-            if state
-                .package_context
-                .package_install_prefix
-                .get(&command)
-                .unwrap()
-                .0
-            {
-                args.insert(0, command);
-                command = "sudo".into(); // TODO: Support doas.
-            };
-
-            args.push(package_name.clone());
+            let package_name = package.package.clone();
+            let used = manager_for(state, &manager, package);
+            let (command, args) = package_command(state, &used, &package_name, false);
 
             info!(
                 "Installing package great {} with manager {}....",
                 &package_name, &manager
             );
             debug!("{} {:?}", &command, &args);
+            if plan::record(Action::RunCommand {
+                command: command.clone(),
+                args: args.clone(),
+            }) {
+                continue;
+            }
+
+            let before = is_installed(state, &used, &package_name);
+
             subprocess::Exec::cmd(command)
                 .args(&args)
-                .popen()
+                .join()
+                .and_then(exited)
                 .context(PackageInstallFail {
                     package: &package_name,
                     manager: &manager,
                 })?;
+
+            match before {
+                Some(false) => installed.push(InstalledPackage {
+                    package: package_name,
+                    manager: used,
+                }),
+                _ => debug!("Great package {} was already installed....", &package_name),
+            }
         }
     } else {
         info!("No work to do!");
     }
 
+    Ok(installed)
+}
+
+/// Uninstall packages with the managers that installed them.
+pub fn unjog(state: &State, packages: &[InstalledPackage]) -> Result<(), Box<dyn std::error::Error>> {
+    for package in packages {
+        let (command, args) = package_command(state, &package.manager, &package.package, true);
+
+        info!(
+            "Uninstalling great package {} with manager {}....",
+            &package.package, &package.manager
+        );
+        debug!("{} {:?}", &command, &args);
+        if plan::record(Action::RunCommand {
+            command: command.clone(),
+            args: args.clone(),
+        }) {
+            continue;
+        }
+
+        subprocess::Exec::cmd(command)
+            .args(&args)
+            .join()
+            .and_then(exited)
+            .context(PackageUninstallFail {
+                package: &package.package,
+                manager: &package.manager,
+            })?;
+    }
+
     Ok(())
 }
+
+/// Whether a package is installed, or nothing if the manager can't
+/// tell.
+fn is_installed(state: &State, manager: &str, package: &str) -> Option<bool> {
+    let (command, args) = state.package_context.package_query.get(manager)?;
+
+    subprocess::Exec::cmd(command)
+        .args(args)
+        .arg(package)
+        .stdout(subprocess::NullFile)
+        .stderr(subprocess::NullFile)
+        .join()
+        .ok()
+        .map(|status| status.success())
+}
+
+/// Turn a package manager exiting unsuccessfully into an error.
+fn exited(status: subprocess::ExitStatus) -> Result<(), subprocess::PopenError> {
+    match status.success() {
+        true => Ok(()),
+        false => Err(subprocess::PopenError::from(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("{:?}", status),
+        ))),
+    }
+}
+
+/// Get the manager to install a package with; the most important
+/// one it has an install name for, if it has any.
+fn manager_for(state: &State, manager: &String, package: &AddedPackage) -> String {
+    let mut command = manager.clone();
+
+    // Runs if we have overloads to deal with
+    if package.package_overloads.len() != 0 {
+        let mut to_use: (u8, String) = (0, "".into());
+        for overload in &package.package_overloads {
+            let x = state
+                .package_context
+                .package_install_prefix
+                .get_key_value(overload.0)
+                .unwrap();
+
+            if x.1 .1 > to_use.0 {
+                to_use = (x.1 .1, x.0.clone());
+            }
+        }
+
+        command = to_use.1;
+    }
+
+    command
+}
+
+/// Get the command (and its arguments) to install or uninstall a
+/// package with a manager.
+fn package_command(
+    state: &State,
+    manager: &str,
+    package: &str,
+    uninstall: bool,
+) -> (String, Vec<String>) {
+    let mut command = manager.to_owned();
+    let mut args = if uninstall {
+        state
+            .package_context
+            .package_uninstall_prefix
+            .get(manager)
+            .cloned()
+            .unwrap_or_default()
+    } else {
+        state
+            .package_context
+            .package_install_prefix
+            .get(manager)
+            .unwrap()
+            .2
+            .clone()
+    };
+
+    // Runs if we need to run the command as root.
+    if state
+        .package_context
+        .package_install_prefix
+        .get(&command)
+        .unwrap()
+        .0
+    {
+        args.insert(0, command);
+        command = "sudo".into(); // TODO: Support doas.
+    };
+
+    args.push(package.to_owned());
+
+    (command, args)
+}
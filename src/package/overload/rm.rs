@@ -10,7 +10,7 @@ pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::er
         }
     }
 
-    state.data.populate_file(state);
+    state.data.populate_file(state)?;
 
     Ok(())
 }
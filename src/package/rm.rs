@@ -1,9 +1,8 @@
 use crate::manifest::State;
-use crate::utils;
 use clap::ArgMatches;
 use log::warn;
 
-pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), utils::CommonErrors> {
+pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
     for unwanted_package in matches.values_of("packages").unwrap() {
         if state
             .data
@@ -29,7 +28,7 @@ pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), utils::CommonEr
         });
     }
 
-    state.data.populate_file(state);
+    state.data.populate_file(state)?;
 
     Ok(())
 }
@@ -0,0 +1,59 @@
+use crate::manifest::State;
+use crate::paths;
+use crate::utils;
+use clap::ArgMatches;
+use log::info;
+use snafu::{ResultExt, Snafu};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+pub enum AddPathError {
+    #[snafu(display("Great path variable {} is built in, and can't be changed!", name))]
+    ReservedName {
+        name: String,
+        source: std::io::Error,
+    },
+}
+
+/// Defines a path variable, such as {{CODE}} for ~/src. Tracked
+/// paths inside of the directory are rewritten to use it.
+pub fn add(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let name = matches
+        .value_of("name")
+        .unwrap()
+        .trim_start_matches("{{")
+        .trim_end_matches("}}")
+        .to_owned();
+    let dir = PathBuf::from(matches.value_of("dir").unwrap());
+
+    if name == "HOME" {
+        Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+            .context(ReservedName { name: &name })?;
+    }
+
+    let resolved = paths::resolve_files(&state.data);
+
+    // Forget the old value, so the new one isn't defined in terms of itself
+    paths::undefine(&name);
+    let value = utils::relative_to_special(&dir)
+        .context(utils::NoFileExistsError { file: &dir })?;
+
+    state
+        .data
+        .path_variables
+        .get_or_insert_with(BTreeMap::new)
+        .insert(name.clone(), value.clone());
+
+    paths::define_from(&state.data, true);
+    paths::respecialize(state, &resolved)?;
+    state.data.populate_file(state)?;
+
+    info!(
+        "{} now stands for {}! Pack to update the packed repository.",
+        paths::placeholder(&name),
+        value.display()
+    );
+
+    Ok(())
+}
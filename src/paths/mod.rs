@@ -0,0 +1,413 @@
+pub mod add;
+pub mod rm;
+
+use crate::manifest::{Manifest, State};
+use crate::plan::{self, Action};
+use crate::utils;
+use log::debug;
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::RwLock;
+
+lazy_static! {
+    static ref VARIABLES: RwLock<PathVariables> = RwLock::new(PathVariables::new());
+}
+
+/// Placeholders built into greatness, the XDG user directory each
+/// one stands for, and where that directory is if XDG doesn't say.
+const XDG_PLACEHOLDERS: &[(&str, &str, &str)] = &[
+    ("DESKTOP", "XDG_DESKTOP_DIR", "Desktop"),
+    ("DOCUMENTS", "XDG_DOCUMENTS_DIR", "Documents"),
+    ("DOWNLOADS", "XDG_DOWNLOAD_DIR", "Downloads"),
+    ("MUSIC", "XDG_MUSIC_DIR", "Music"),
+    ("PICTURES", "XDG_PICTURES_DIR", "Pictures"),
+    ("PUBLICSHARE", "XDG_PUBLICSHARE_DIR", "Public"),
+    ("TEMPLATES", "XDG_TEMPLATES_DIR", "Templates"),
+    ("VIDEOS", "XDG_VIDEOS_DIR", "Videos"),
+];
+
+/// A two way mapping between placeholders, such as {{HOME}}, and
+/// the directories they stand for on this machine.
+pub struct PathVariables {
+    /// Names (without the braces) and absolute directories, in the
+    /// order they were defined.
+    variables: Vec<(String, PathBuf)>,
+}
+
+impl PathVariables {
+    /// Creates the built in variables: {{HOME}}, and the XDG user
+    /// directories, read from the environment or `user-dirs.dirs`.
+    pub fn new() -> Self {
+        let home = home::home_dir().unwrap();
+        let user_dirs = read_user_dirs(&home);
+        let mut variables = vec![("HOME".to_owned(), home.clone())];
+
+        for (name, xdg, default) in XDG_PLACEHOLDERS {
+            let dir = std::env::var(xdg)
+                .ok()
+                .filter(|d| !d.is_empty())
+                .map(|d| expand_home(&d, &home))
+                .or_else(|| user_dirs.get(*xdg).cloned())
+                .unwrap_or_else(|| home.join(default));
+
+            // XDG directories pointing at home are disabled
+            if dir != home {
+                variables.push((name.to_string(), dir));
+            }
+        }
+
+        Self { variables }
+    }
+
+    /// Defines (or redefines) a variable. The value may itself use
+    /// placeholders, or start with `~`. {{HOME}} can't be redefined.
+    pub fn define(&mut self, name: &str, value: &Path) {
+        if name == "HOME" {
+            return;
+        }
+
+        let home = home::home_dir().unwrap();
+        let absolute = expand_home(self.to_absolute(value).to_str().unwrap(), &home);
+
+        self.variables.retain(|(n, _)| n != name);
+        self.variables.push((name.to_owned(), absolute));
+    }
+
+    /// Checks if a variable is defined, built in or not.
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.variables.iter().any(|(n, _)| n == name)
+    }
+
+    /// Transforms a special path to an absolute one.
+    /// {{HOME}}/.zshrc -> /home/milo/.zshrc
+    pub fn to_absolute(&self, special: &Path) -> PathBuf {
+        let mut components = special.components();
+
+        if let Some(Component::Normal(first)) = components.next() {
+            if let Some(name) = placeholder_name(first.to_str().unwrap_or("")) {
+                if let Some((_, dir)) = self.variables.iter().find(|(n, _)| n == name) {
+                    let mut ret = dir.clone();
+                    ret.extend(components);
+
+                    return ret;
+                }
+            }
+        }
+
+        special.to_path_buf()
+    }
+
+    /// Transforms an absolute path to a special one, using whichever
+    /// variable matches the most of it.
+    /// /home/milo/.zshrc -> {{HOME}}/.zshrc
+    /// /home/milo/Documents/notes.md -> {{DOCUMENTS}}/notes.md
+    pub fn to_special(&self, absolute: &Path) -> PathBuf {
+        let mut best: Option<&(String, PathBuf)> = None;
+
+        for variable in &self.variables {
+            if !absolute.starts_with(&variable.1) {
+                continue;
+            }
+
+            // Ties go to whoever was defined first, so built ins win
+            if best.map_or(true, |b| {
+                variable.1.components().count() > b.1.components().count()
+            }) {
+                best = Some(variable);
+            }
+        }
+
+        match best {
+            Some((name, dir)) => {
+                let mut ret = PathBuf::from(placeholder(name));
+                ret.extend(absolute.strip_prefix(dir).unwrap().components());
+
+                ret
+            }
+            None => absolute.to_path_buf(),
+        }
+    }
+
+    /// All the variables, and their absolute directories.
+    pub fn all(&self) -> Vec<(String, PathBuf)> {
+        self.variables.clone()
+    }
+}
+
+/// Transforms an absolute path to a special one.
+pub fn to_special(absolute: &Path) -> PathBuf {
+    VARIABLES.read().unwrap().to_special(absolute)
+}
+
+/// Transforms a special path to an absolute one.
+pub fn to_absolute(special: &Path) -> PathBuf {
+    VARIABLES.read().unwrap().to_absolute(special)
+}
+
+/// Gets all the variables currently defined.
+pub fn all() -> Vec<(String, PathBuf)> {
+    VARIABLES.read().unwrap().all()
+}
+
+/// Defines the variables of a manifest. If `overwrite` isn't set,
+/// variables that are already defined are left alone, so that
+/// states we pull can't move the users own directories around.
+pub fn define_from(manifest: &Manifest, overwrite: bool) {
+    let mut variables = VARIABLES.write().unwrap();
+
+    for (name, value) in manifest.path_variables.clone().unwrap_or_default() {
+        if overwrite || !variables.is_defined(&name) {
+            debug!("Defining great path variable {} as {}....", name, value.display());
+            variables.define(&name, &value);
+        }
+    }
+}
+
+/// Variables defined by `define_for`. The variables are put back as
+/// they were once it is dropped.
+pub struct Defined {
+    previous: Vec<(String, PathBuf)>,
+}
+
+impl Drop for Defined {
+    fn drop(&mut self) {
+        VARIABLES.write().unwrap().variables = std::mem::take(&mut self.previous);
+    }
+}
+
+/// Defines the variables of a state that isn't the main one, such as a
+/// pulled state, for as long as what is returned is kept. Variables that
+/// are already defined are left alone, like `define_from`.
+#[must_use]
+pub fn define_for(manifest: &Manifest) -> Defined {
+    let previous = VARIABLES.read().unwrap().variables.clone();
+    define_from(manifest, false);
+
+    Defined { previous }
+}
+
+/// Forgets a variable the user defined. Built in variables go
+/// back to their built in value.
+pub fn undefine(name: &str) {
+    let mut variables = VARIABLES.write().unwrap();
+    variables.variables.retain(|(n, _)| n != name);
+
+    if let Some(builtin) = PathVariables::new()
+        .variables
+        .into_iter()
+        .find(|(n, _)| n == name)
+    {
+        variables.variables.push(builtin);
+    }
+}
+
+/// Like `to_special`, but also recognises the home directories of
+/// other users and machines, such as /home/milo or /Users/milo.
+pub fn to_special_foreign(absolute: &Path) -> PathBuf {
+    let special = to_special(absolute);
+    if special != absolute {
+        return special;
+    }
+
+    let mut components = absolute.components();
+    match (components.next(), components.next(), components.next()) {
+        (Some(Component::RootDir), Some(Component::Normal(homes)), Some(Component::Normal(_)))
+            if homes == "home" || homes == "Users" =>
+        {
+            let mut ret = PathBuf::from(placeholder("HOME"));
+            ret.extend(components);
+
+            ret
+        }
+        _ => special,
+    }
+}
+
+/// Makes a placeholder out of the name of a variable.
+/// HOME -> {{HOME}}
+pub fn placeholder(name: &str) -> String {
+    format!("{{{{{}}}}}", name)
+}
+
+/// Gets the name of the variable of a placeholder, if it is one.
+/// {{HOME}} -> HOME
+fn placeholder_name(component: &str) -> Option<&str> {
+    component.strip_prefix("{{")?.strip_suffix("}}")
+}
+
+/// Gets where every tracked file is on this machine, so that they
+/// can be found again after the variables change.
+pub fn resolve_files(manifest: &Manifest) -> Vec<PathBuf> {
+    manifest
+        .files
+        .iter()
+        .flatten()
+        .map(|f| to_absolute(&f.path))
+        .collect()
+}
+
+/// Rewrites every tracked path with the current variables, moving
+/// packed copies along with them. Needed whenever the variables
+/// change, as a path like {{HOME}}/src/foo may now be {{CODE}}/foo.
+/// `resolved` is what `resolve_files` returned before the change.
+pub fn respecialize(state: &mut State, resolved: &[PathBuf]) -> Result<(), utils::CommonErrors> {
+    let mut files_dir = state.greatness_git_pack_dir.clone();
+    files_dir.push("files");
+
+    if let Some(files) = &mut state.data.files {
+        for (file, absolute) in files.iter_mut().zip(resolved) {
+            let new = to_special(absolute);
+            if new == file.path {
+                continue;
+            }
+
+            debug!("Great path {} is now {}....", file.path.display(), new.display());
+
+            let old_packed = utils::special_in_dir(&files_dir, &file.path);
+            let new_packed = utils::special_in_dir(&files_dir, &new);
+            if old_packed.exists()
+                && !plan::record(Action::Move {
+                    from: old_packed.clone(),
+                    to: new_packed.clone(),
+                })
+            {
+                std::fs::create_dir_all(new_packed.parent().unwrap()).context(
+                    utils::DirCreationError {
+                        dir: new_packed.parent().unwrap(),
+                    },
+                )?;
+                std::fs::rename(&old_packed, &new_packed).context(utils::FileCopyError {
+                    src: &old_packed,
+                    dest: &new_packed,
+                })?;
+            }
+
+            file.path = new;
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands a leading `~` or `$HOME`.
+fn expand_home(path: &str, home: &Path) -> PathBuf {
+    for prefix in &["~", "$HOME", "${HOME}"] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            let mut ret = home.to_path_buf();
+            ret.push(rest.trim_start_matches('/'));
+
+            return ret;
+        }
+    }
+
+    PathBuf::from(path)
+}
+
+/// Reads the XDG user directories out of `user-dirs.dirs`, which
+/// has lines like XDG_DESKTOP_DIR="$HOME/Desktop".
+fn read_user_dirs(home: &Path) -> HashMap<String, PathBuf> {
+    let mut user_dirs = HashMap::new();
+    let config_dir = std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".config"));
+
+    let contents = match std::fs::read_to_string(config_dir.join("user-dirs.dirs")) {
+        Ok(c) => c,
+        Err(_) => return user_dirs,
+    };
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim().trim_matches('"');
+            if !value.is_empty() {
+                user_dirs.insert(key.trim().to_owned(), expand_home(value, home));
+            }
+        }
+    }
+
+    user_dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> PathVariables {
+        PathVariables {
+            variables: vec![
+                ("HOME".to_owned(), PathBuf::from("/home/milo")),
+                ("CODE".to_owned(), PathBuf::from("/home/milo/src")),
+                ("WORK".to_owned(), PathBuf::from("/home/milo/src/work")),
+                ("SAME".to_owned(), PathBuf::from("/home/milo/src")),
+            ],
+        }
+    }
+
+    #[test]
+    fn the_longest_variable_wins() {
+        let variables = variables();
+
+        assert_eq!(
+            variables.to_special(Path::new("/home/milo/.zshrc")),
+            PathBuf::from("{{HOME}}/.zshrc")
+        );
+        assert_eq!(
+            variables.to_special(Path::new("/home/milo/src/foo")),
+            PathBuf::from("{{CODE}}/foo")
+        );
+        assert_eq!(
+            variables.to_special(Path::new("/home/milo/src/work/bar")),
+            PathBuf::from("{{WORK}}/bar")
+        );
+        assert_eq!(
+            variables.to_special(Path::new("/etc/hosts")),
+            PathBuf::from("/etc/hosts")
+        );
+    }
+
+    #[test]
+    fn special_paths_round_trip() {
+        let variables = variables();
+
+        for path in &[
+            "/home/milo/.zshrc",
+            "/home/milo/src",
+            "/home/milo/src/work/bar/baz",
+            "/home/milonga/x",
+            "/etc/hosts",
+        ] {
+            let special = variables.to_special(Path::new(path));
+            assert_eq!(variables.to_absolute(&special), PathBuf::from(path));
+        }
+    }
+
+    #[test]
+    fn variables_of_other_states_are_put_back() {
+        let variables = vec![(
+            "PULLED_ONLY".to_owned(),
+            PathBuf::from("/nonexistent/pulled"),
+        )];
+        let manifest = Manifest {
+            path_variables: Some(variables.into_iter().collect()),
+            ..Manifest::default()
+        };
+
+        {
+            let _defined = define_for(&manifest);
+            assert_eq!(
+                to_absolute(Path::new("{{PULLED_ONLY}}/a")),
+                PathBuf::from("/nonexistent/pulled/a")
+            );
+        }
+
+        assert!(!all().iter().any(|(name, _)| name == "PULLED_ONLY"));
+    }
+}
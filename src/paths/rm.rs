@@ -0,0 +1,31 @@
+use crate::manifest::State;
+use crate::paths;
+use clap::ArgMatches;
+use log::warn;
+
+/// Removes path variables. Tracked paths that used them are
+/// rewritten with whatever variables are left.
+pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let resolved = paths::resolve_files(&state.data);
+
+    for name in matches.values_of("names").unwrap() {
+        let name = name.trim_start_matches("{{").trim_end_matches("}}");
+
+        let removed = match &mut state.data.path_variables {
+            Some(variables) => variables.remove(name).is_some(),
+            None => false,
+        };
+
+        if !removed {
+            warn!("Not-yet-great path variable {} is not defined! Skipping....", name);
+            continue;
+        }
+
+        paths::undefine(name);
+    }
+
+    paths::respecialize(state, &resolved)?;
+    state.data.populate_file(state)?;
+
+    Ok(())
+}
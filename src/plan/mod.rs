@@ -0,0 +1,168 @@
+use log::{debug, info};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+lazy_static! {
+    /// Everything a dry run would have done, in order. `None` unless
+    /// this is a dry run.
+    static ref PLAN: Mutex<Option<Vec<Action>>> = Mutex::new(None);
+}
+
+/// Something greatness would do to the system.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Write a file, copying it from `from` if given.
+    WriteFile { path: PathBuf, from: Option<PathBuf> },
+    /// Link a file to a target.
+    Link {
+        path: PathBuf,
+        target: PathBuf,
+        hard: bool,
+    },
+    /// Copy a file somewhere safe before it is overwritten.
+    Backup { path: PathBuf, to: PathBuf },
+    /// Remove a file or directory.
+    Remove { path: PathBuf },
+    /// Move a file.
+    Move { from: PathBuf, to: PathBuf },
+    /// Create a directory.
+    CreateDir { path: PathBuf },
+    /// Clone a git repository.
+    Clone { url: String, into: PathBuf },
+    /// Copy a local directory, or extract an archive.
+    Fetch { from: PathBuf, into: PathBuf },
+    /// Run a script on a file.
+    RunScript { script: PathBuf, file: PathBuf },
+    /// Run a command, such as a package manager.
+    RunCommand { command: String, args: Vec<String> },
+    /// Change a manifest (greatness.yaml). `diff` is a unified diff.
+    ChangeManifest { path: PathBuf, diff: String },
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WriteFile { path, from: None } => write!(f, "write   {}", path.display()),
+            Self::WriteFile {
+                path,
+                from: Some(from),
+            } => write!(f, "write   {} (from {})", path.display(), from.display()),
+            Self::Link { path, target, hard } => write!(
+                f,
+                "{}  {} -> {}",
+                if *hard { "hardlink" } else { "symlink " },
+                path.display(),
+                target.display()
+            ),
+            Self::Backup { path, to } => {
+                write!(f, "backup  {} (to {})", path.display(), to.display())
+            }
+            Self::Remove { path } => write!(f, "remove  {}", path.display()),
+            Self::Move { from, to } => write!(f, "move    {} -> {}", from.display(), to.display()),
+            Self::CreateDir { path } => write!(f, "mkdir   {}", path.display()),
+            Self::Clone { url, into } => write!(f, "clone   {} (into {})", url, into.display()),
+            Self::Fetch { from, into } => {
+                write!(f, "fetch   {} (into {})", from.display(), into.display())
+            }
+            Self::RunScript { script, file } => {
+                write!(f, "script  {} (on {})", script.display(), file.display())
+            }
+            Self::RunCommand { command, args } => {
+                write!(f, "run     {} {}", command, args.join(" "))
+            }
+            Self::ChangeManifest { path, diff } => {
+                write!(f, "change  {}\n{}", path.display(), diff.trim_end())
+            }
+        }
+    }
+}
+
+/// Start a dry run. From now on, nothing is done, only planned.
+pub fn enable() {
+    *PLAN.lock().unwrap() = Some(vec![]);
+}
+
+/// Checks if this is a dry run.
+pub fn is_dry_run() -> bool {
+    PLAN.lock().unwrap().is_some()
+}
+
+/// Records an action, if this is a dry run. Returns true if it
+/// was recorded, in which case the caller must not actually do it.
+pub fn record(action: Action) -> bool {
+    match PLAN.lock().unwrap().as_mut() {
+        Some(plan) => {
+            debug!("Planning to {}....", action);
+
+            // Manifests are often saved more than once, only the last counts
+            if let Action::ChangeManifest { path, .. } = &action {
+                plan.retain(|a| !matches!(a, Action::ChangeManifest { path: p, .. } if p == path));
+            }
+
+            plan.push(action);
+
+            true
+        }
+        None => false,
+    }
+}
+
+/// Prints the plan, as `text` or `json`.
+pub fn print(format: &str) -> Result<(), serde_json::Error> {
+    let plan = PLAN.lock().unwrap().clone().unwrap_or_default();
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+        return Ok(());
+    }
+
+    if plan.is_empty() {
+        info!("Dry run; nothing would be done!");
+        return Ok(());
+    }
+
+    info!("Dry run; nothing was done. The plan:");
+    for action in &plan {
+        println!("  {}", action);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_are_shown_in_columns() {
+        let link = Action::Link {
+            path: PathBuf::from("/home/a/.bashrc"),
+            target: PathBuf::from("/repo/.bashrc"),
+            hard: false,
+        };
+        let run = Action::RunCommand {
+            command: "brew".to_owned(),
+            args: vec!["install".to_owned(), "fish".to_owned()],
+        };
+
+        assert_eq!(
+            link.to_string(),
+            "symlink   /home/a/.bashrc -> /repo/.bashrc"
+        );
+        assert_eq!(run.to_string(), "run     brew install fish");
+    }
+
+    #[test]
+    fn actions_are_tagged_in_json() {
+        let action = Action::Remove {
+            path: PathBuf::from("/home/a/.bashrc"),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&action).unwrap(),
+            serde_json::json!({ "action": "remove", "path": "/home/a/.bashrc" })
+        );
+    }
+}
@@ -1,13 +1,28 @@
-use crate::git::clone;
+use crate::backup;
+use crate::condition;
+use crate::crypt;
+use crate::deploy;
 use crate::init;
-use crate::manifest::{Manifest, State};
+use crate::git::clone;
+use crate::manifest::{AddedFile, DeployMode, FileMetadata, Manifest, Pin, Requirement, State};
+use crate::merge::{self, Conflicts, OnConflict};
 use crate::package;
+use crate::paths;
+use crate::plan::{self, Action};
 use crate::script;
+use crate::tag::selector::{self, Selector};
+use crate::template;
+use super::installed::{Installed, InstalledPackage};
+use super::lock::{Lock, Locked};
+use super::resolve::Graph;
+use super::source::Source;
+use super::transaction;
 use crate::utils;
 use clap::ArgMatches;
 use log::{debug, info, warn};
-use question::{Answer, Question};
+use serde_yaml::Value;
 use snafu::{ResultExt, Snafu};
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::PathBuf;
 
 #[derive(Debug, Snafu)]
@@ -21,6 +36,12 @@ pub enum CloneError {
         source: git2::Error,
     },
 
+    #[snafu(display("Failed to journal great location {} before cloning into it: {}", dir.display(), source))]
+    JournalFailure {
+        dir: PathBuf,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Failed to remove pre-pulling directory {}: {}", dir.display(), source))]
     RemoveFailure {
         dir: PathBuf,
@@ -40,117 +61,391 @@ pub enum InstallError {
     },
 }
 
-/// Clone and install a repository
+/// Pull an external state. Pulling is a transaction; if anything goes
+/// wrong, everything the pull did is rolled back. See `transaction`.
+pub fn add(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let source = Source::parse(matches.value_of("from").unwrap());
+    let requirement = Requirement {
+        name: match matches.value_of("name") {
+            Some(name) => name.to_owned(),
+            None => source.name(),
+        },
+        url: source.to_string(),
+        pin: pin_of(matches),
+        tag: matches.value_of("only-with-tag").map(str::to_owned),
+    };
+
+    // A typo in the selector shouldn't surface after fetching everything
+    selector::parse(requirement.tag.as_deref())?;
+
+    // Pulling again keeps to the locked commit, unless where it is
+    // pulled from, or what it is pinned to, changed
+    let mut lock = Lock::read(state)?;
+    let unchanged = state
+        .data
+        .requires
+        .iter()
+        .flatten()
+        .find(|r| r.name == requirement.name)
+        .map_or(false, |r| r.url == requirement.url && r.pin == requirement.pin);
+    if !unchanged {
+        lock.remove(&requirement.name);
+    }
+
+    transaction::begin(state, &requirement.url)?;
+
+    match clone_and_install_repo(&requirement, &lock, matches, state, false) {
+        Ok(()) => {
+            transaction::commit()?;
+            Ok(())
+        }
+        Err(e) => {
+            if !plan::is_dry_run() {
+                warn!("Pulling failed, so rolling back: {}", e);
+                transaction::rollback(&state.greatness_transaction_dir)?;
+            }
+
+            Err(e)
+        }
+    }
+}
+
+/// Gets what to pin a state being pulled to, from the arguments.
+fn pin_of(matches: &ArgMatches) -> Option<Pin> {
+    if let Some(branch) = matches.value_of("branch") {
+        Some(Pin::Branch(branch.to_owned()))
+    } else if let Some(tag) = matches.value_of("git-tag") {
+        Some(Pin::Tag(tag.to_owned()))
+    } else {
+        matches.value_of("commit").map(|c| Pin::Commit(c.to_owned()))
+    }
+}
+
+/// A state that was fetched, waiting to be installed.
+struct Fetched {
+    /// Where the state belongs.
+    clone_to: PathBuf,
+    /// Where it was fetched to, which is somewhere out of the way in a
+    /// dry run.
+    clone_into: PathBuf,
+    /// The commit checked out, if it is from git.
+    commit: Option<String>,
+    external_state: State,
+}
+
+/// Fetch and install a state, from anywhere `Source` can fetch from,
+/// along with everything it requires. Everything is fetched first, see
+/// `resolve`, then each state is installed after the states it requires.
+/// * `locks` - The commits to check out, instead of what the
+///   requirements are pinned to.
 pub fn clone_and_install_repo(
-    user_url: String,
+    requirement: &Requirement,
+    locks: &Lock,
     matches: &ArgMatches,
     state: &mut State,
     sub_state: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if matches.is_present("as-main") {
-        debug!("Installing as main!");
+    let result = fetch_and_install(requirement, locks, matches, state, sub_state);
+
+    // A dry run that fails would leave what it fetched behind
+    if result.is_err() && plan::is_dry_run() {
+        let _ = std::fs::remove_dir_all(dry_run_dir());
     }
 
-    // Normallize the URL and get a valid location to clone to
-    let (url, mut clone_to) = get_git_pair(state, user_url, matches);
+    result
+}
 
-    // Create the clone to directory if none exists
-    if clone_to.exists() {
-        std::fs::remove_dir_all(&clone_to).context(RemoveFailure { dir: &clone_to })?;
+fn fetch_and_install(
+    requirement: &Requirement,
+    locks: &Lock,
+    matches: &ArgMatches,
+    state: &mut State,
+    sub_state: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if matches.is_present("as-main") {
+        debug!("Installing as main!");
     }
 
-    // TODO: Implement a progress bar. https://docs.rs/git2/0.13.20/git2/struct.Progress.html
-    info!("Cloning from {} into {}....", url, &clone_to.display());
-    clone::clone_repo(&url, &clone_to)?;
-
-    // Parse the file. False as we want to enable git
-    let mut external_state = State::new(PathBuf::from(clone_to.to_str().unwrap()))?;
-    external_state.data = Manifest::populate_from_file(&&external_state)?;
-
-    install(
-        matches,
-        Some(url),
-        &mut clone_to,
-        state,
-        &mut external_state,
-        sub_state,
+    let fetching_state: &State = state;
+    let mut graph = Graph::resolve(
+        std::slice::from_ref(requirement),
+        locks,
+        |requirement, locks, is_sub_state| {
+            let fetched = fetch(requirement, locks, matches, fetching_state, sub_state || is_sub_state)?;
+            let requires = fetched
+                .external_state
+                .data
+                .requires
+                .clone()
+                .unwrap_or_default();
+            let lock = Lock::read(&fetched.external_state)?;
+
+            Ok((fetched, requires, lock))
+        },
     )?;
-    for requirement in external_state.data.requires.unwrap_or(vec![]) {
-        clone_and_install_repo(
-            requirement.0.unwrap_or("".to_string()),
+
+    for index in graph.order.clone() {
+        let node = &mut graph.nodes[index];
+        let fetched = &mut node.loaded;
+        let node_sub_state = sub_state || node.sub_state;
+        backup::set_reason(format!("pull {}", node.url));
+
+        install(
             matches,
+            &node.requirement,
+            &fetched.clone_to,
             state,
-            true,
+            &mut fetched.external_state,
+            node_sub_state,
         )?;
+
+        if let (Some(commit), false) = (
+            fetched.commit.clone(),
+            matches.is_present("as-main") && !node_sub_state,
+        ) {
+            let mut lock = Lock::read(state)?;
+            lock.set(Locked {
+                name: node.requirement.name.clone(),
+                url: node.url.clone(),
+                commit,
+            });
+            lock.write(state)?;
+        }
+
+        if fetched.clone_into != fetched.clone_to {
+            std::fs::remove_dir_all(&fetched.clone_into).context(RemoveFailure {
+                dir: &fetched.clone_into,
+            })?;
+        }
     }
 
-    if matches.is_present("as-main") {
+    if matches.is_present("as-main") && !sub_state {
         init::init_no_damage(matches, state)?;
     }
 
     Ok(())
 }
 
-/// Return a tuple contains the URL of a repository and where
-/// to clone it to.
-fn get_git_pair(state: &State, user_url: String, matches: &ArgMatches) -> (String, PathBuf) {
-    let url = utils::make_url_valid(user_url);
+/// Fetch a state to where it belongs, checking out what is locked or
+/// what it is pinned to. Only the state that is pulled can be pulled as
+/// the main state, so the states it requires are pulled as usual.
+fn fetch(
+    requirement: &Requirement,
+    locks: &Lock,
+    matches: &ArgMatches,
+    state: &State,
+    sub_state: bool,
+) -> Result<Fetched, Box<dyn std::error::Error>> {
+    // Normallize the source and get a valid location to fetch to
+    let source = Source::parse(&requirement.url);
+    let url = source.to_string();
+    let as_main = matches.is_present("as-main") && !sub_state;
+    let clone_to = if as_main {
+        state.greatness_dir.clone()
+    } else {
+        Source::dest(state, &requirement.name)
+    };
+
+    // Create the clone to directory if none exists. Mid pull, the old
+    // clone is only set aside, so that it can be rolled back to. The
+    // main state is fetched next to the state directory instead, and
+    // only replaces parts of it, see `replace_main`
+    if !as_main
+        && clone_to.exists()
+        && !plan::record(Action::Remove {
+            path: clone_to.clone(),
+        })
+        && !transaction::set_aside(&clone_to).context(RemoveFailure { dir: &clone_to })?
+    {
+        std::fs::remove_dir_all(&clone_to).context(RemoveFailure { dir: &clone_to })?;
+    }
 
-    let mut clone_to = PathBuf::from(&state.greatness_pulled_dir);
-    #[allow(unused_assignments)]
-    let mut dest_tmp = url.replace("https://", "");
-    dest_tmp = url.replace("http://", "");
-    dest_tmp = dest_tmp.replace(".git", "");
+    // A dry run still needs the repository to plan with, so it
+    // is cloned somewhere out of the way instead
+    let mut clone_into = if as_main {
+        let mut staging = state.greatness_dir.clone().into_os_string();
+        staging.push("-main");
+        PathBuf::from(staging)
+    } else {
+        clone_to.clone()
+    };
+    let dry_run = plan::record(source.action(&clone_to));
+    if dry_run {
+        clone_into = dry_run_dir().join(clone_to.file_name().unwrap_or_default());
+    }
 
-    if !matches.is_present("as-main") {
-        let dest: PathBuf = PathBuf::from(
-            dest_tmp
-                .split("/")
-                .collect::<Vec<&str>>()
-                .join(std::path::MAIN_SEPARATOR.to_string().as_str()),
-        );
+    if as_main || dry_run {
+        if clone_into.exists() {
+            std::fs::remove_dir_all(&clone_into).context(RemoveFailure { dir: &clone_into })?;
+        }
+    }
+
+    transaction::touch(&clone_into).context(JournalFailure { dir: &clone_into })?;
+    source.fetch(&clone_into)?;
+    let commit = check_out(
+        &source,
+        requirement,
+        &clone_into,
+        locks.commit_of(&requirement.name, &url),
+    )?;
+
+    if as_main && !dry_run {
+        replace_main(&clone_into, state)?;
+        clone_into = clone_to.clone();
+    }
 
-        clone_to.push(&dest);
+    // Parse the file. False as we want to enable git
+    let mut external_state = State::new(clone_into.clone())?;
+    external_state.data = Manifest::read_from_file(&external_state)?;
+
+    Ok(Fetched {
+        clone_to,
+        clone_into,
+        commit,
+        external_state,
+    })
+}
+
+/// Where a dry run fetches states to.
+fn dry_run_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("great-dry-run-{}", std::process::id()))
+}
+
+/// Make a fetched state the main one. Only what the main state is made
+/// of is replaced; what is local to this machine, such as backups and
+/// the key, is left alone. What is replaced is set aside first, so that
+/// it can be rolled back.
+fn replace_main(fetched: &PathBuf, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+    let name_of = |path: &PathBuf| path.file_name().unwrap_or_default().to_owned();
+    let local = vec![
+        std::ffi::OsString::from(".lock"),
+        name_of(&state.greatness_pulled_dir),
+        name_of(&state.greatness_variables),
+        name_of(&state.greatness_merge_bases_dir),
+        name_of(&state.greatness_backups_dir),
+        name_of(&state.greatness_key),
+        name_of(&state.greatness_installed),
+    ];
+    if let Some(packed) = state.greatness_git_pack_dir.parent() {
+        set_aside_or_remove(&packed.to_path_buf())?;
+    }
+    set_aside_or_remove(&state.greatness_state)?;
+    set_aside_or_remove(&state.greatness_lock)?;
+    set_aside_or_remove(&state.greatness_scripts_dir)?;
+
+    for entry in std::fs::read_dir(fetched).context(RemoveFailure { dir: fetched })? {
+        let entry = entry.context(RemoveFailure { dir: fetched })?;
+        if local.contains(&entry.file_name()) {
+            debug!(
+                "Not replacing {}, which is local to this machine....",
+                entry.file_name().to_string_lossy()
+            );
+            continue;
+        }
+
+        let to = state.greatness_dir.join(entry.file_name());
+        set_aside_or_remove(&to)?;
+        transaction::touch(&to).context(JournalFailure { dir: &to })?;
+        std::fs::rename(entry.path(), &to).context(RemoveFailure { dir: &to })?;
+    }
+
+    std::fs::remove_dir_all(fetched).context(RemoveFailure { dir: fetched })?;
+
+    Ok(())
+}
+
+/// Set a path aside in the transaction, or remove it outside of one.
+fn set_aside_or_remove(path: &PathBuf) -> Result<(), CloneError> {
+    let metadata = match path.symlink_metadata() {
+        Ok(m) => m,
+        Err(_) => return Ok(()),
+    };
+
+    if transaction::set_aside(path).context(RemoveFailure { dir: path })? {
+        return Ok(());
+    }
+
+    if metadata.is_dir() {
+        std::fs::remove_dir_all(path).context(RemoveFailure { dir: path })
     } else {
-        clone_to = state.greatness_dir.clone();
+        std::fs::remove_file(path).context(RemoveFailure { dir: path })
+    }
+}
+
+/// Check out the locked commit of a fetched state, or what it is pinned
+/// to. Returns the commit checked out, or nothing if it isn't from git.
+fn check_out(
+    source: &Source,
+    requirement: &Requirement,
+    dir: &PathBuf,
+    locked: Option<String>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if !matches!(source, Source::Git(_)) {
+        if let Some(pin) = &requirement.pin {
+            warn!(
+                "{} isn't a git repository, so it can't be pinned to {}!",
+                source, pin
+            );
+        }
+
+        return Ok(None);
     }
 
-    (url, clone_to)
+    let commit = match (locked, &requirement.pin) {
+        (Some(locked), _) => {
+            debug!("{} is locked to {}....", requirement.name, locked);
+            clone::checkout(dir, &locked)?
+        }
+        (None, Some(pin)) => {
+            info!("Checking out {} of {}....", pin, requirement.name);
+            clone::checkout(dir, &pin.revision())?
+        }
+        (None, None) => clone::head_commit(dir)?,
+    };
+
+    Ok(Some(commit))
 }
 
 /// Install external from a local directory
-/// * `url` - Optional URL that it was cloned from. Is used to update when wanted.
-/// * `from` - Where the external state is located on disk.
-/// * `manfiest` - State to write into.
+/// * `requirement` - What is being installed. Is used to update when wanted.
+/// * `install_from` - Where the external state is located on disk.
+/// * `state` - State to write into.
 pub fn install(
     matches: &ArgMatches,
-    url: Option<String>,
-    install_from: &mut PathBuf,
+    requirement: &Requirement,
+    install_from: &PathBuf,
     state: &mut State,
     external_state: &mut State,
     sub_state: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let external_state_data = external_state.data.clone();
-    if let Some(files) = external_state_data.files {
-        install_from.push("files");
-
-        for file in files {
-            if matches.is_present("only-with-tag")
-                && matches.value_of("only-with-tag").unwrap() == file.tag.unwrap_or("".to_owned())
-            {
-                continue;
-            }
+    let as_main = matches.is_present("as-main") && !sub_state;
+    let selector = selector::parse(requirement.tag.as_deref())?;
+    let source = if as_main {
+        "main".to_owned()
+    } else {
+        merge::source_name(state, install_from)
+    };
 
-            install_file(install_from, file.path)?;
-        }
+    if external_state.data.files.is_some() {
+        let mut files_dir = external_state.greatness_dir.clone();
+        files_dir.push("files");
+        install_files(
+            matches,
+            &files_dir,
+            state,
+            &external_state.data,
+            &source,
+            selector.as_ref(),
+        )?;
     }
 
-    install_mods(matches, external_state)?;
+    let packages = install_mods(matches, external_state, selector.as_ref())?;
+    Installed::record_packages(state, &source, &packages)?;
 
     // Make sure we mark this as a dependency, only if we are not
     // installing it as main
-    if !matches.is_present("as-main") {
-        mark_as_dependency(state, install_from, url, sub_state);
+    if !as_main {
+        mark_as_dependency(state, requirement, sub_state);
     } else {
         debug!("--as-main specified, not marking specfied as a dependency....");
     }
@@ -158,71 +453,116 @@ pub fn install(
     // Don't overwrite the state with nothing if
     // we plan to pull as main.
     if !matches.is_present("as-main") {
-        state.data.populate_file(state);
+        state.data.populate_file(state)?;
+    }
+
+    Ok(())
+}
+
+/// Install every file of a manifest, from the directory its files
+/// were packed into.
+/// * `source` - Name of what is being installed. See `merge::source_name`.
+/// * `selector` - Only install files it picks. See `tag::selector`.
+pub fn install_files(
+    matches: &ArgMatches,
+    files_dir: &PathBuf,
+    state: &State,
+    external: &Manifest,
+    source: &str,
+    selector: Option<&Selector>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The external state may place files using its own variables
+    let _variables = paths::define_for(external);
+
+    let variables = template::variables(external, state)?;
+    let conflicts = Conflicts::from_matches(matches, state, source)?;
+    let mut written = vec![];
+    for file in external.files.iter().flatten() {
+        if !selector.map_or(true, |s| s.matches(&file.tags)) {
+            debug!(
+                "Great file {} isn't picked by the tag selector, skipping....",
+                file.path.display()
+            );
+            continue;
+        }
+
+        if !condition::is_active(&file.when) {
+            debug!(
+                "Great file {} is not for this machine, skipping....",
+                file.path.display()
+            );
+            continue;
+        }
+
+        let packed = utils::special_in_dir(files_dir, &file.path);
+        for expanded_file in file.expand(&packed)? {
+            if install_file(files_dir, &expanded_file, file, &variables, &conflicts)? {
+                written.push(utils::special_to_absolute(&expanded_file));
+            }
+        }
+
+        // Installing the contents touches the directory itself
+        if let (true, Some(metadata)) = (file.directory, &file.metadata) {
+            deploy::restore_metadata(&utils::special_to_absolute(&file.path), metadata)?;
+        }
+    }
+
+    // What the main state installs is its own, so it isn't recorded
+    if source != "main" {
+        Installed::record(state, source, &written)?;
     }
 
     Ok(())
 }
 
+/// Run the scripts of the files a selector picks, and install the
+/// packages, if allowed to. Returns the packages that were installed.
 pub fn install_mods(
     matches: &ArgMatches,
     external_state: &mut State,
-) -> Result<(), Box<dyn std::error::Error>> {
+    selector: Option<&Selector>,
+) -> Result<Vec<InstalledPackage>, Box<dyn std::error::Error>> {
     // Run the scripts, and install the packages.
     if matches.is_present("allow-mods") {
         debug!("--allow-mods specified, running scripts....");
-        script::jog::jog(external_state)?;
+        script::jog::jog(external_state, selector)?;
 
         debug!("--allow-mods specified, installing packages....");
-        package::jog::jog(matches, external_state)?;
+        package::jog::jog(matches, external_state)
     } else {
         warn!("The --allow-mods (-d) argument is not passed! No scripts will be run for security reasons :D");
-    }
 
-    Ok(())
+        Ok(vec![])
+    }
 }
 
-fn mark_as_dependency(
-    state: &mut State,
-    install_from: &mut PathBuf,
-    url: Option<String>,
-    sub_state: bool,
-) {
-    if let Some(requires) = &mut state.data.requires {
-        let mut add = true;
-
-        // Check is already added. We do this last because the user may want to re-merge everything
-        requires.iter().for_each(|x| {
-            if x.1 == utils::absolute_to_special(install_from) {
-                add = false;
-            }
-        });
+/// Add a state to the requirements of the main state, replacing what
+/// was there under the same name. States required by other states are
+/// left to them.
+fn mark_as_dependency(state: &mut State, requirement: &Requirement, sub_state: bool) {
+    if sub_state {
+        return;
+    }
 
-        if add && !sub_state {
-            requires.push((url, utils::absolute_to_special(&install_from.clone())));
-        }
-    } else {
-        state.data.requires = Some(vec![(
-            url,
-            utils::absolute_to_special(&install_from.clone()),
-        )]);
+    let requires = state.data.requires.get_or_insert_with(Vec::new);
+    match requires.iter_mut().find(|r| r.name == requirement.name) {
+        Some(r) => *r = requirement.clone(),
+        None => requires.push(requirement.clone()),
     }
 }
 
-/// Install a file
-fn install_file(install_from: &PathBuf, file: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    let mut install_from_now = install_from.clone();
-    let mut install_from_intr: PathBuf = PathBuf::from(std::path::MAIN_SEPARATOR.to_string()); // Using "/" here simply makes home/milo turn into /home/milo, so we can replace it with {{HOME}}
-    let install_to = utils::special_to_absolute(&file);
-
-    install_to
-        .to_str()
-        .unwrap()
-        .to_string()
-        .split(std::path::MAIN_SEPARATOR)
-        .into_iter()
-        .for_each(|e| install_from_intr.push(e));
-    install_from_now.push(utils::absolute_to_special(&install_from_intr));
+/// Install a file. If a different file is already in its place, the
+/// collision is resolved as `conflicts` says. Returns whether the file
+/// was written.
+fn install_file(
+    install_from: &PathBuf,
+    file: &PathBuf,
+    added: &AddedFile,
+    variables: &Value,
+    conflicts: &Conflicts,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let install_from_now = utils::special_in_dir(install_from, file);
+    let install_to = utils::special_to_absolute(file);
 
     debug!(
         "Installing great file to great location; {} to {}....",
@@ -230,47 +570,244 @@ fn install_file(install_from: &PathBuf, file: PathBuf) -> Result<(), Box<dyn std
         install_to.display()
     );
 
-    if install_to.as_path().exists() {
-        // We need to make a backup
+    let mode = added.deploy;
+    let metadata = added.metadata_for(file);
+
+    // Encrypted files are decrypted, templates are rendered, and
+    // both are always copied
+    let prepared = match (added.encrypted, added.template) {
+        (false, false) => None,
+        (false, true) => Some(template::render_file(&install_from_now, variables)?.into_bytes()),
+        (true, template) => {
+            let plain = crypt::decrypt_file(&install_from_now)?;
+            match (template, utils::as_text(&plain)) {
+                (true, Some(text)) => {
+                    Some(template::render_str(text, &install_from_now, variables)?.into_bytes())
+                }
+                _ => Some(plain),
+            }
+        }
+    };
+
+    // What is being installed, which is the base of the next merge
+    let theirs = match &prepared {
+        Some(prepared) => prepared.clone(),
+        None => std::fs::read(&install_from_now)
+            .context(utils::FileReadError { file: &install_from_now })?,
+    };
+
+    let in_place = match &prepared {
+        Some(prepared) => std::fs::read(&install_to).map_or(false, |c| &c == prepared),
+        None => deploy::is_deployed(&install_from_now, &install_to, mode),
+    };
+    if in_place {
+        debug!("{} is already in place!", install_to.display());
+        conflicts.record_base(file, &theirs, added.encrypted)?;
+        return Ok(false);
+    }
 
+    if install_to.as_path().exists() {
         info!("{} already exists (which is great)!", install_to.display());
-        info!("Note that skipping doing this could cause the dotfiles you are pulling and merging to not work. A backup WILL be made!");
-        let answer = Question::new("Do you want to overwrite?")
-            .default(Answer::YES)
-            .show_defaults()
-            .confirm();
-
-        if answer != Answer::YES {
-            info!("Skipping....");
-            return Ok(());
-        }
 
-        utils::backup_file(&install_to)?;
-    } else {
+        match conflicts.choose(&install_to) {
+            OnConflict::Skip => {
+                info!("Skipping....");
+                return Ok(false);
+            }
+            OnConflict::Merge => {
+                let merged = match conflicts.merge(file, &install_to, &theirs, added.encrypted)? {
+                    Some(m) => m,
+                    None => {
+                        info!("Keeping {} as it is....", install_to.display());
+                        return Ok(false);
+                    }
+                };
+
+                if mode != DeployMode::Copy {
+                    warn!(
+                        "{} was merged, so it is a copy instead of a {}!",
+                        install_to.display(),
+                        mode
+                    );
+                }
+
+                backup::backup_file(&install_to)?;
+                deploy::deploy_contents(&merged, &install_to, metadata)?;
+                conflicts.record_base(file, &theirs, added.encrypted)?;
+
+                return Ok(true);
+            }
+            // Asking always ends in one of the others
+            OnConflict::Overwrite | OnConflict::Ask => backup::backup_file(&install_to)?,
+        }
+    } else if !plan::is_dry_run() {
         // Create the directories we need to house the file
         // that is to be installed
-        create_dirs_for_file_install(&install_to)?;
+        create_dirs_for_file_install(&install_to, metadata)?;
     }
 
-    std::fs::copy(&install_from_now, &install_to).context(utils::FileCopyError {
-        src: &install_from_now,
-        dest: &install_to,
-    })?;
+    match prepared {
+        Some(prepared) => deploy::deploy_contents(&prepared, &install_to, metadata)?,
+        None => deploy::deploy(&install_from_now, &install_to, mode, metadata)?,
+    }
 
-    Ok(())
+    conflicts.record_base(file, &theirs, added.encrypted)?;
+
+    Ok(true)
 }
 
-fn create_dirs_for_file_install(install_to: &PathBuf) -> Result<(), utils::CommonErrors> {
+fn create_dirs_for_file_install(
+    install_to: &PathBuf,
+    metadata: Option<&FileMetadata>,
+) -> Result<(), utils::CommonErrors> {
     let as_vec = &install_to.to_str().unwrap().to_string();
     let splitted = as_vec.split(std::path::MAIN_SEPARATOR);
     let mut dirs_to_create = splitted.clone().collect::<Vec<&str>>();
     dirs_to_create.remove(dirs_to_create.len() - 1);
     let str_dirs_to_create = dirs_to_create.join(std::path::MAIN_SEPARATOR.to_string().as_str());
 
+    transaction::touch(install_to).context(utils::FileCreationError { file: install_to })?;
     std::fs::create_dir_all(&str_dirs_to_create).context(utils::DirCreationError {
         dir: PathBuf::from(str_dirs_to_create),
     })?;
-    std::fs::File::create(&install_to).context(utils::FileCreationError { file: &install_to })?;
+    // Create the file with its recorded permissions from the start
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .mode(metadata.map_or(0o644, |m| m.mode))
+        .open(&install_to)
+        .context(utils::FileCreationError { file: &install_to })?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a git repository with a commit tagged v1, and a later one.
+    /// Returns the commits, oldest first.
+    fn tagged_repository(dir: &PathBuf) -> Vec<String> {
+        let repo = git2::Repository::init(dir).unwrap();
+        let signature = git2::Signature::now("great", "great@example.com").unwrap();
+
+        let mut commits = vec![];
+        for version in &["v1", "v2"] {
+            std::fs::write(dir.join("greatness.yaml"), version).unwrap();
+            let mut index = repo.index().unwrap();
+            index
+                .add_path(std::path::Path::new("greatness.yaml"))
+                .unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parent = commits.last().map(|c| repo.find_commit(*c).unwrap());
+            let parents = parent.iter().collect::<Vec<&git2::Commit>>();
+
+            commits.push(
+                repo.commit(
+                    Some("HEAD"),
+                    &signature,
+                    &signature,
+                    version,
+                    &tree,
+                    &parents,
+                )
+                .unwrap(),
+            );
+        }
+
+        let v1 = repo.find_object(commits[0], None).unwrap();
+        repo.tag_lightweight("v1", &v1, false).unwrap();
+
+        commits.iter().map(|c| c.to_string()).collect()
+    }
+
+    fn pinned(pin: Option<Pin>) -> Requirement {
+        Requirement {
+            name: "shared".to_owned(),
+            url: "https://example.com/shared.git".to_owned(),
+            pin,
+            tag: None,
+        }
+    }
+
+    #[test]
+    fn pins_are_checked_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().to_path_buf();
+        let commits = tagged_repository(&repo);
+        let source = Source::Git("https://example.com/shared.git".to_owned());
+
+        let tag = pinned(Some(Pin::Tag("v1".to_owned())));
+        let commit = check_out(&source, &tag, &repo, None).unwrap();
+        assert_eq!(commit, Some(commits[0].clone()));
+        assert_eq!(
+            std::fs::read_to_string(repo.join("greatness.yaml")).unwrap(),
+            "v1"
+        );
+
+        let exact = pinned(Some(Pin::Commit(commits[1].clone())));
+        let commit = check_out(&source, &exact, &repo, None).unwrap();
+        assert_eq!(commit, Some(commits[1].clone()));
+        assert_eq!(
+            std::fs::read_to_string(repo.join("greatness.yaml")).unwrap(),
+            "v2"
+        );
+    }
+
+    #[test]
+    fn locks_win_over_pins() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().to_path_buf();
+        let commits = tagged_repository(&repo);
+        let source = Source::Git("https://example.com/shared.git".to_owned());
+
+        let exact = pinned(Some(Pin::Commit(commits[1].clone())));
+        let commit = check_out(&source, &exact, &repo, Some(commits[0].clone())).unwrap();
+        assert_eq!(commit, Some(commits[0].clone()));
+        assert_eq!(
+            std::fs::read_to_string(repo.join("greatness.yaml")).unwrap(),
+            "v1"
+        );
+    }
+
+    #[test]
+    fn only_git_is_pinned() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_path_buf();
+        let source = Source::Dir(dir.clone());
+
+        let tag = pinned(Some(Pin::Tag("v1".to_owned())));
+        assert_eq!(check_out(&source, &tag, &dir, None).unwrap(), None);
+    }
+
+    #[test]
+    fn backups_survive_pulling_as_main() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("greatness");
+        let fetched = dir.path().join("greatness-main");
+        std::fs::create_dir_all(main.join("backups")).unwrap();
+        std::fs::create_dir_all(main.join("scripts")).unwrap();
+        std::fs::create_dir_all(fetched.join("scripts")).unwrap();
+        std::fs::write(main.join("backups/bashrc"), "old bashrc").unwrap();
+        std::fs::write(main.join("key"), "key").unwrap();
+        std::fs::write(main.join("greatness.yaml"), "old").unwrap();
+        std::fs::write(main.join("scripts/old.sh"), "").unwrap();
+        std::fs::write(main.join("greatness.lock"), "old lock").unwrap();
+        std::fs::write(fetched.join("greatness.yaml"), "new").unwrap();
+        std::fs::write(fetched.join("greatness.lock"), "new lock").unwrap();
+        std::fs::write(fetched.join("scripts/new.sh"), "").unwrap();
+        std::fs::write(fetched.join("key"), "someone else's key").unwrap();
+
+        let state = State::new(main.clone()).unwrap();
+        replace_main(&fetched, &state).unwrap();
+
+        let read = |path: &str| std::fs::read_to_string(main.join(path)).unwrap();
+        assert_eq!(read("backups/bashrc"), "old bashrc");
+        assert_eq!(read("key"), "key");
+        assert_eq!(read("greatness.yaml"), "new");
+        assert_eq!(read("greatness.lock"), "new lock");
+        assert!(main.join("scripts/new.sh").exists());
+        assert!(!main.join("scripts/old.sh").exists());
+        assert!(!fetched.exists());
+    }
+}
@@ -0,0 +1,119 @@
+use super::list;
+use super::lock::Lock;
+use super::resolve::{Graph, Node};
+use crate::manifest::State;
+use clap::ArgMatches;
+use log::info;
+
+/// Print the states the main state requires, and the states they
+/// require, as they are pulled. Either as a tree, or in DOT for
+/// graphviz.
+pub fn graph(matches: &ArgMatches, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+    let requires = state.data.requires.clone().unwrap_or_default();
+    if requires.is_empty() {
+        info!("\x1b[1mNo external states are required!\x1b[0m");
+        return Ok(());
+    }
+
+    // Nothing is fetched, each state is read from where it is pulled
+    let graph = Graph::resolve(&requires, &Lock::read(state)?, |requirement, _, _| {
+        match list::pulled_state(state, requirement)? {
+            Some(pulled) => Ok((
+                true,
+                pulled.data.requires.clone().unwrap_or_default(),
+                Lock::read(&pulled)?,
+            )),
+            None => Ok((false, vec![], Lock::default())),
+        }
+    })?;
+
+    if matches.is_present("dot") {
+        print_dot(&graph);
+    } else {
+        println!("main");
+        print_tree(&graph, &graph.roots, "", &mut vec![]);
+    }
+
+    Ok(())
+}
+
+fn describe(node: &Node<bool>) -> String {
+    let mut description = node.url.clone();
+    if let Some(pin) = &node.requirement.pin {
+        description.push_str(&format!(", {}", pin));
+    }
+    if let Some(tag) = &node.requirement.tag {
+        description.push_str(&format!(", tags {}", tag));
+    }
+    if !node.loaded {
+        description.push_str(", not pulled");
+    }
+
+    description
+}
+
+/// Print the states, each under the states requiring it. States that
+/// were already printed aren't printed again.
+fn print_tree(graph: &Graph<bool>, indices: &[usize], prefix: &str, shown: &mut Vec<usize>) {
+    for (i, index) in indices.iter().enumerate() {
+        let node = &graph.nodes[*index];
+        let last = i == indices.len() - 1;
+
+        if shown.contains(index) {
+            println!(
+                "{}{} {} (see above)",
+                prefix,
+                if last { "└──" } else { "├──" },
+                node.requirement.name
+            );
+            continue;
+        }
+
+        shown.push(*index);
+        println!(
+            "{}{} {} ({})",
+            prefix,
+            if last { "└──" } else { "├──" },
+            node.requirement.name,
+            describe(node)
+        );
+        print_tree(
+            graph,
+            &node.requires,
+            &format!("{}{}", prefix, if last { "    " } else { "│   " }),
+            shown,
+        );
+    }
+}
+
+fn print_dot(graph: &Graph<bool>) {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+
+    println!("digraph greatness {{");
+    println!("    {} [shape=box];", quote("main"));
+    for node in &graph.nodes {
+        println!(
+            "    {} [label={}];",
+            quote(&node.requirement.name),
+            quote(&format!("{}\n{}", node.requirement.name, describe(node))).replace('\n', "\\n")
+        );
+    }
+
+    for index in &graph.roots {
+        println!(
+            "    {} -> {};",
+            quote("main"),
+            quote(&graph.nodes[*index].requirement.name)
+        );
+    }
+    for node in &graph.nodes {
+        for index in &node.requires {
+            println!(
+                "    {} -> {};",
+                quote(&node.requirement.name),
+                quote(&graph.nodes[*index].requirement.name)
+            );
+        }
+    }
+    println!("}}");
+}
@@ -0,0 +1,169 @@
+//! installed.yaml records the files each pulled state wrote, and the
+//! packages greatness installed, on this machine. Purging removes only
+//! those; files that were skipped, and files or packages that were
+//! there before, are left alone. It is never packed.
+
+use crate::manifest::State;
+use crate::plan::{self, Action};
+use crate::utils;
+use super::transaction;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum InstalledError {
+    #[snafu(display("Failed to parse great installed file {}: {}", file.display(), source))]
+    InstalledParseError {
+        file: PathBuf,
+        source: serde_yaml::Error,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct Installed {
+    /// The files written, by the name of the state that wrote them.
+    /// See `merge::source_name`. Paths are absolute, as pulled states
+    /// may place files with their own variables.
+    #[serde(default)]
+    pub states: BTreeMap<String, Vec<PathBuf>>,
+    /// The packages installed, by the name of the state that
+    /// installed them, or main.
+    #[serde(default)]
+    pub packages: BTreeMap<String, Vec<InstalledPackage>>,
+}
+
+/// A package, and the manager that installed it.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct InstalledPackage {
+    pub package: String,
+    pub manager: String,
+}
+
+impl Installed {
+    pub fn read(state: &State) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = &state.greatness_installed;
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = std::fs::read_to_string(file).context(utils::FileReadError { file })?;
+
+        Ok(serde_yaml::from_str(&contents).context(InstalledParseError { file })?)
+    }
+
+    pub fn write(&self, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+        let file = &state.greatness_installed;
+        if plan::record(Action::WriteFile {
+            path: file.clone(),
+            from: None,
+        }) {
+            return Ok(());
+        }
+
+        transaction::touch(file).context(utils::FileWriteError { file })?;
+        utils::write_atomically(file, serde_yaml::to_string(self)?.as_bytes())
+            .context(utils::FileWriteError { file })?;
+
+        Ok(())
+    }
+
+    /// Record the files a state wrote, along with what it wrote before.
+    pub fn record(
+        state: &State,
+        source: &str,
+        written: &[PathBuf],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if written.is_empty() {
+            return Ok(());
+        }
+
+        let mut installed = Self::read(state)?;
+        let files = installed.states.entry(source.to_owned()).or_default();
+        for path in written {
+            if !files.contains(path) {
+                files.push(path.clone());
+            }
+        }
+
+        installed.write(state)
+    }
+
+    /// Record the packages a state installed.
+    pub fn record_packages(
+        state: &State,
+        source: &str,
+        packages: &[InstalledPackage],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if packages.is_empty() {
+            return Ok(());
+        }
+
+        let mut installed = Self::read(state)?;
+        let recorded = installed.packages.entry(source.to_owned()).or_default();
+        for package in packages {
+            if !recorded.contains(package) {
+                recorded.push(package.clone());
+            }
+        }
+
+        installed.write(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str) -> InstalledPackage {
+        InstalledPackage {
+            package: name.to_owned(),
+            manager: "pacman".to_owned(),
+        }
+    }
+
+    #[test]
+    fn nothing_is_installed_at_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = State::new(dir.path().to_path_buf()).unwrap();
+
+        assert_eq!(Installed::read(&state).unwrap(), Installed::default());
+    }
+
+    #[test]
+    fn files_are_recorded_once_per_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = State::new(dir.path().to_path_buf()).unwrap();
+        let bashrc = PathBuf::from("/home/milo/.bashrc");
+        let vimrc = PathBuf::from("/home/milo/.vimrc");
+
+        let both = vec![bashrc.clone(), vimrc.clone()];
+
+        Installed::record(&state, "shared", &both[..1]).unwrap();
+        Installed::record(&state, "shared", &both).unwrap();
+        Installed::record(&state, "other", &both[1..]).unwrap();
+
+        let installed = Installed::read(&state).unwrap();
+        assert_eq!(installed.states["shared"], vec![bashrc, vimrc.clone()]);
+        assert_eq!(installed.states["other"], vec![vimrc]);
+    }
+
+    #[test]
+    fn packages_are_recorded_once_per_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = State::new(dir.path().to_path_buf()).unwrap();
+
+        Installed::record_packages(&state, "main", &[package("ripgrep")]).unwrap();
+        Installed::record_packages(&state, "main", &[package("ripgrep"), package("fd")]).unwrap();
+        Installed::record(&state, "main", &[]).unwrap();
+
+        let installed = Installed::read(&state).unwrap();
+        assert_eq!(
+            installed.packages["main"],
+            vec![package("ripgrep"), package("fd")]
+        );
+        assert!(installed.states.is_empty());
+    }
+}
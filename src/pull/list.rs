@@ -0,0 +1,208 @@
+use super::lock::Lock;
+use super::source::Source;
+use crate::condition;
+use crate::git::clone;
+use crate::manifest::{AddedFile, Manifest, Requirement, State};
+use crate::tag::selector::{self, SelectorError};
+use clap::ArgMatches;
+use log::{info, warn};
+use std::path::PathBuf;
+
+/// List the required states, with where each of them is at.
+pub fn list(matches: &ArgMatches, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+    let requires = state.data.requires.clone().unwrap_or_default();
+    if requires.is_empty() {
+        info!("\x1b[1mNo external states are required!\x1b[0m");
+        return Ok(());
+    }
+
+    let lock = Lock::read(state)?;
+    for requirement in &requires {
+        info!("\x1b[1m{}\x1b[0m ({})", requirement.name, requirement.url);
+
+        let pulled = match pulled_state(state, requirement)? {
+            Some(pulled) => pulled,
+            None => {
+                warn!("\tnot pulled yet! See `great pull update`.");
+                continue;
+            }
+        };
+
+        if matches!(Source::parse(&requirement.url), Source::Git(_)) {
+            if matches.is_present("fetch") {
+                clone::fetch(&pulled.greatness_dir)?;
+            }
+
+            info!("\t{}", git_status(requirement, &pulled.greatness_dir, &lock)?);
+        }
+
+        info!(
+            "\tfiles: {}, scripts: {}, packages: {}",
+            contributed(requirement, &pulled.data)?.len(),
+            pulled.data.all_scripts().unwrap_or_default().len(),
+            pulled.data.packages.iter().flatten().count(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Gets the state pulled for a requirement, or nothing if it hasn't
+/// been pulled.
+pub fn pulled_state(
+    state: &State,
+    requirement: &Requirement,
+) -> Result<Option<State>, Box<dyn std::error::Error>> {
+    let dest = Source::dest(state, &requirement.name);
+    let mut pulled = State::new(dest)?;
+    if !pulled.greatness_state.exists() {
+        return Ok(None);
+    }
+
+    pulled.data = Manifest::read_from_file(&pulled)?;
+
+    Ok(Some(pulled))
+}
+
+/// Gets the files of a state that were installed from it, which are
+/// those its tag selector picks, for this machine.
+pub fn contributed<'a>(
+    requirement: &Requirement,
+    manifest: &'a Manifest,
+) -> Result<Vec<&'a AddedFile>, SelectorError> {
+    let selector = selector::parse(requirement.tag.as_deref())?;
+
+    Ok(manifest
+        .files
+        .iter()
+        .flatten()
+        .filter(|file| selector.as_ref().map_or(true, |s| s.matches(&file.tags)))
+        .filter(|file| condition::is_active(&file.when))
+        .collect())
+}
+
+/// Describe what is checked out in a clone; the commit, whether it was
+/// changed, and how far behind what it follows it is.
+pub fn git_status(
+    requirement: &Requirement,
+    dir: &PathBuf,
+    lock: &Lock,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let commit = clone::head_commit(dir)?;
+    let mut status = format!("commit: {}", &commit[..7]);
+
+    if clone::is_dirty(dir)? {
+        status.push_str(", dirty");
+    }
+
+    match lock.commit_of(&requirement.name, &requirement.url) {
+        Some(locked) if locked != commit => {
+            status.push_str(&format!(", locked to {}", &locked[..7]))
+        }
+        _ => (),
+    }
+
+    let revision = match &requirement.pin {
+        Some(pin) => pin.revision(),
+        None => clone::default_revision(dir),
+    };
+    match clone::behind(dir, &revision) {
+        Ok(0) => status.push_str(", up to date"),
+        Ok(behind) => status.push_str(&format!(", {} behind", behind)),
+        Err(e) => warn!("Couldn't compare {} with {}: {}", requirement.name, revision, e),
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Condition;
+
+    fn requirement(tag: Option<&str>) -> Requirement {
+        Requirement {
+            name: "shared".to_owned(),
+            url: "https://example.com/shared.git".to_owned(),
+            pin: None,
+            tag: tag.map(str::to_owned),
+        }
+    }
+
+    fn tagged(path: &str, tags: &[&str]) -> AddedFile {
+        AddedFile {
+            path: PathBuf::from(path),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..AddedFile::default()
+        }
+    }
+
+    fn paths(files: Vec<&AddedFile>) -> Vec<PathBuf> {
+        files.iter().map(|f| f.path.clone()).collect()
+    }
+
+    #[test]
+    fn everything_is_contributed_without_a_tag() {
+        let manifest = Manifest {
+            files: Some(vec![
+                tagged("{{HOME}}/.bashrc", &[]),
+                tagged("{{HOME}}/.vimrc", &["editor"]),
+            ]),
+            ..Manifest::default()
+        };
+
+        let picked = contributed(&requirement(None), &manifest).unwrap();
+        assert_eq!(picked.len(), 2);
+    }
+
+    #[test]
+    fn only_what_the_tag_picks_is_contributed() {
+        let manifest = Manifest {
+            files: Some(vec![
+                tagged("{{HOME}}/.bashrc", &["shell"]),
+                tagged("{{HOME}}/.vimrc", &["editor"]),
+                tagged("{{HOME}}/.xinitrc", &[]),
+            ]),
+            ..Manifest::default()
+        };
+
+        let picked = contributed(&requirement(Some("shell,editor")), &manifest).unwrap();
+        assert_eq!(
+            paths(picked),
+            vec![
+                PathBuf::from("{{HOME}}/.bashrc"),
+                PathBuf::from("{{HOME}}/.vimrc")
+            ]
+        );
+
+        let picked = contributed(&requirement(Some("!editor")), &manifest).unwrap();
+        assert_eq!(
+            paths(picked),
+            vec![
+                PathBuf::from("{{HOME}}/.bashrc"),
+                PathBuf::from("{{HOME}}/.xinitrc")
+            ]
+        );
+    }
+
+    #[test]
+    fn files_for_other_machines_are_not_contributed() {
+        let mut elsewhere = tagged("{{HOME}}/.bashrc", &[]);
+        elsewhere.when = Some(Condition {
+            os: Some(vec!["not an os".to_owned()]),
+            ..Condition::default()
+        });
+        let manifest = Manifest {
+            files: Some(vec![elsewhere, tagged("{{HOME}}/.vimrc", &[])]),
+            ..Manifest::default()
+        };
+
+        let picked = contributed(&requirement(None), &manifest).unwrap();
+        assert_eq!(paths(picked), vec![PathBuf::from("{{HOME}}/.vimrc")]);
+    }
+
+    #[test]
+    fn invalid_tags_are_errors() {
+        assert!(contributed(&requirement(Some("shell,")), &Manifest::default()).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+//! greatness.lock records the exact commit of every state pulled from
+//! git, so that pulling on another machine installs the very same
+//! files. Pulling again keeps to the locked commit; only
+//! `great pull update` moves it.
+
+use crate::manifest::State;
+use crate::plan::{self, Action};
+use crate::utils;
+use super::transaction;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum LockError {
+    #[snafu(display("Failed to parse great lock file {}: {}", file.display(), source))]
+    LockParseError {
+        file: PathBuf,
+        source: serde_yaml::Error,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Default)]
+pub struct Lock {
+    #[serde(default)]
+    pub states: Vec<Locked>,
+}
+
+/// A state as it was pulled.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Locked {
+    /// Name of the requirement, see `manifest::Requirement`.
+    pub name: String,
+    pub url: String,
+    /// The commit that was checked out.
+    pub commit: String,
+}
+
+impl Lock {
+    /// Read the lock file of a state. A state without one has
+    /// nothing locked.
+    pub fn read(state: &State) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = &state.greatness_lock;
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = std::fs::read_to_string(file).context(utils::FileReadError { file })?;
+
+        Ok(serde_yaml::from_str(&contents).context(LockParseError { file })?)
+    }
+
+    pub fn write(&self, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+        let file = &state.greatness_lock;
+        if plan::record(Action::WriteFile {
+            path: file.clone(),
+            from: None,
+        }) {
+            return Ok(());
+        }
+
+        transaction::touch(file).context(utils::FileWriteError { file })?;
+        utils::write_atomically(file, serde_yaml::to_string(self)?.as_bytes())
+            .context(utils::FileWriteError { file })?;
+
+        Ok(())
+    }
+
+    /// Gets the commit locked for a state, if it is still pulled from
+    /// the same place.
+    pub fn commit_of(&self, name: &str, url: &str) -> Option<String> {
+        self.states
+            .iter()
+            .find(|l| l.name == name && l.url == url)
+            .map(|l| l.commit.clone())
+    }
+
+    pub fn set(&mut self, locked: Locked) {
+        match self.states.iter_mut().find(|l| l.name == locked.name) {
+            Some(l) => *l = locked,
+            None => self.states.push(locked),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.states.retain(|l| l.name != name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locked(name: &str, url: &str, commit: &str) -> Locked {
+        Locked {
+            name: name.to_owned(),
+            url: url.to_owned(),
+            commit: commit.to_owned(),
+        }
+    }
+
+    #[test]
+    fn locks_are_kept_to_where_states_are_from() {
+        let mut lock = Lock::default();
+        lock.set(locked("shared", "https://example.com/shared.git", "abc"));
+
+        let commit = lock.commit_of("shared", "https://example.com/shared.git");
+        assert_eq!(commit, Some("abc".to_owned()));
+        assert_eq!(
+            lock.commit_of("shared", "https://example.com/fork.git"),
+            None
+        );
+        assert_eq!(
+            lock.commit_of("other", "https://example.com/shared.git"),
+            None
+        );
+    }
+
+    #[test]
+    fn setting_a_lock_replaces_it() {
+        let mut lock = Lock::default();
+        lock.set(locked("shared", "https://example.com/shared.git", "abc"));
+        lock.set(locked("other", "https://example.com/other.git", "def"));
+        lock.set(locked("shared", "https://example.com/fork.git", "123"));
+
+        assert_eq!(lock.states.len(), 2);
+        let commit = lock.commit_of("shared", "https://example.com/fork.git");
+        assert_eq!(commit, Some("123".to_owned()));
+
+        lock.remove("shared");
+        let other = locked("other", "https://example.com/other.git", "def");
+        assert_eq!(lock.states, vec![other]);
+    }
+
+    #[test]
+    fn locks_are_written_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = State::new(dir.path().to_path_buf()).unwrap();
+        assert_eq!(Lock::read(&state).unwrap(), Lock::default());
+
+        let mut lock = Lock::default();
+        lock.set(locked("shared", "https://example.com/shared.git", "abc"));
+        lock.write(&state).unwrap();
+
+        assert_eq!(Lock::read(&state).unwrap(), lock);
+    }
+}
@@ -1,2 +1,11 @@
 pub mod add;
+pub mod graph;
+pub mod installed;
+pub mod list;
+pub mod lock;
+pub mod resolve;
 pub mod rm;
+pub mod show;
+pub mod source;
+pub mod transaction;
+pub mod update;
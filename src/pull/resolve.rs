@@ -0,0 +1,328 @@
+//! Resolving the states required by a state, and the states they
+//! require, before anything is installed. A state required more than
+//! once is only fetched once, a state requiring itself, however far
+//! down, is refused, and a state pinned differently by two others is a
+//! conflict.
+
+use super::lock::Lock;
+use super::source::Source;
+use crate::manifest::Requirement;
+use log::warn;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum ResolveError {
+    #[snafu(display("Great states require each other, which can't be installed: {}", cycle))]
+    Cycle {
+        cycle: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Great state {} is required at {} by {}, but at {} by {}!",
+        url,
+        pin,
+        by,
+        other_pin,
+        other_by
+    ))]
+    PinConflict {
+        url: String,
+        pin: String,
+        by: String,
+        other_pin: String,
+        other_by: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Two different great states are called {}; {} and {}! Give one of them another name.",
+        name,
+        url,
+        other_url
+    ))]
+    NameConflict {
+        name: String,
+        url: String,
+        other_url: String,
+        source: std::io::Error,
+    },
+}
+
+/// A required state, and what it requires.
+pub struct Node<T> {
+    pub requirement: Requirement,
+    /// Where it is from, as `Source` gives it. States are told apart
+    /// by this.
+    pub url: String,
+    /// The name of the state that first required it.
+    pub by: String,
+    /// Whether it is required by another required state, instead of
+    /// by the main one.
+    pub sub_state: bool,
+    /// Indices of the states it requires.
+    pub requires: Vec<usize>,
+    /// What loading it gave back. See `Graph::resolve`.
+    pub loaded: T,
+}
+
+/// Every state required by some states, with each of them only once.
+pub struct Graph<T> {
+    pub nodes: Vec<Node<T>>,
+    /// Indices of the states required by the main state.
+    pub roots: Vec<usize>,
+    /// Indices of every state, with each after the states it requires.
+    /// This is the order to install them in.
+    pub order: Vec<usize>,
+}
+
+/// What loading a state gives back; whatever is kept of it, what it
+/// requires, and its lock.
+pub type Loaded<T> = (T, Vec<Requirement>, Lock);
+
+impl<T> Graph<T> {
+    /// Resolve states, and all of the states they require.
+    /// * `roots` - The states required by the main state.
+    /// * `locks` - The commits locked by the main state.
+    /// * `load` - Gets a state, such as by fetching it. Is given what
+    ///   is locked for it, and whether it is a sub state. Only called
+    ///   once for each state.
+    pub fn resolve(
+        roots: &[Requirement],
+        locks: &Lock,
+        mut load: impl FnMut(&Requirement, &Lock, bool) -> Result<Loaded<T>, Box<dyn std::error::Error>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut graph = Graph {
+            nodes: vec![],
+            roots: vec![],
+            order: vec![],
+        };
+
+        for root in roots {
+            let index = graph.visit(root, "main", locks, &mut vec![], &mut load)?;
+            if !graph.roots.contains(&index) {
+                graph.roots.push(index);
+            }
+        }
+
+        Ok(graph)
+    }
+
+    fn visit(
+        &mut self,
+        requirement: &Requirement,
+        by: &str,
+        locks: &Lock,
+        stack: &mut Vec<usize>,
+        load: &mut impl FnMut(&Requirement, &Lock, bool) -> Result<Loaded<T>, Box<dyn std::error::Error>>,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let url = Source::parse(&requirement.url).to_string();
+
+        if let Some(index) = self.nodes.iter().position(|n| n.url == url) {
+            if let Some(at) = stack.iter().position(|i| *i == index) {
+                let mut cycle: Vec<&str> = stack[at..]
+                    .iter()
+                    .map(|i| self.nodes[*i].requirement.name.as_str())
+                    .collect();
+                cycle.push(&self.nodes[index].requirement.name);
+
+                return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+                    .context(Cycle {
+                        cycle: cycle.join(" -> "),
+                    })?;
+            }
+
+            let node = &self.nodes[index];
+            if node.requirement.pin != requirement.pin {
+                let pin_of = |r: &Requirement| {
+                    r.pin
+                        .as_ref()
+                        .map_or("the default branch".to_owned(), |p| p.to_string())
+                };
+
+                return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+                    .context(PinConflict {
+                        url: &url,
+                        pin: pin_of(&node.requirement),
+                        by: &node.by,
+                        other_pin: pin_of(requirement),
+                        other_by: by,
+                    })?;
+            }
+
+            if node.requirement.tag != requirement.tag {
+                warn!(
+                    "{} is required with different tags by {} and {}; only the files {} asked for are installed!",
+                    node.requirement.name, node.by, by, node.by
+                );
+            }
+
+            return Ok(index);
+        }
+
+        if let Some(node) = self
+            .nodes
+            .iter()
+            .find(|n| n.requirement.name == requirement.name)
+        {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+                .context(NameConflict {
+                    name: &requirement.name,
+                    url: &node.url,
+                    other_url: &url,
+                })?;
+        }
+
+        let (loaded, requires, lock) = load(requirement, locks, !stack.is_empty())?;
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            requirement: requirement.clone(),
+            url,
+            by: by.to_owned(),
+            sub_state: !stack.is_empty(),
+            requires: vec![],
+            loaded,
+        });
+
+        // What is locked above comes first, then what the state locked
+        let mut sub_locks = lock;
+        for locked in &locks.states {
+            sub_locks.set(locked.clone());
+        }
+
+        stack.push(index);
+        for sub_requirement in &requires {
+            let sub_index = self.visit(
+                sub_requirement,
+                &requirement.name,
+                &sub_locks,
+                stack,
+                load,
+            )?;
+
+            if !self.nodes[index].requires.contains(&sub_index) {
+                self.nodes[index].requires.push(sub_index);
+            }
+        }
+        stack.pop();
+
+        self.order.push(index);
+
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Pin;
+    use std::collections::HashMap;
+
+    fn requirement(name: &str) -> Requirement {
+        Requirement {
+            name: name.to_owned(),
+            url: format!("https://example.com/{}.git", name),
+            pin: None,
+            tag: None,
+        }
+    }
+
+    /// Resolve `roots`, with each state requiring what `requires` says,
+    /// giving back how many times each state was loaded.
+    fn resolve(
+        roots: &[Requirement],
+        requires: &HashMap<&str, Vec<Requirement>>,
+    ) -> Result<Graph<usize>, Box<dyn std::error::Error>> {
+        let mut loads = 0;
+        Graph::resolve(roots, &Lock::default(), |requirement, _, _| {
+            loads += 1;
+            Ok((
+                loads,
+                requires
+                    .get(requirement.name.as_str())
+                    .cloned()
+                    .unwrap_or_default(),
+                Lock::default(),
+            ))
+        })
+    }
+
+    fn names(graph: &Graph<usize>, indices: &[usize]) -> Vec<String> {
+        indices
+            .iter()
+            .map(|i| graph.nodes[*i].requirement.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn states_are_installed_after_what_they_require() {
+        let mut requires = HashMap::new();
+        requires.insert("a", vec![requirement("b"), requirement("c")]);
+        requires.insert("b", vec![requirement("d")]);
+        requires.insert("c", vec![requirement("d")]);
+
+        let graph = resolve(&[requirement("a")], &requires).unwrap();
+
+        assert_eq!(names(&graph, &graph.order), vec!["d", "b", "c", "a"]);
+        assert_eq!(names(&graph, &graph.roots), vec!["a"]);
+        assert!(graph
+            .nodes
+            .iter()
+            .all(|n| n.sub_state == (n.requirement.name != "a")));
+    }
+
+    #[test]
+    fn states_required_twice_are_loaded_once() {
+        let mut requires = HashMap::new();
+        let mut short = requirement("shared");
+        short.url = "milo/shared".to_owned();
+        let mut full = requirement("shared");
+        full.url = "https://github.com/milo/shared.git".to_owned();
+        requires.insert("a", vec![short]);
+        requires.insert("b", vec![full]);
+
+        let graph = resolve(&[requirement("a"), requirement("b")], &requires).unwrap();
+
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(
+            graph.nodes.iter().map(|n| n.loaded).collect::<Vec<usize>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(graph.nodes[0].requires, graph.nodes[2].requires);
+    }
+
+    #[test]
+    fn cycles_are_refused() {
+        let mut requires = HashMap::new();
+        requires.insert("a", vec![requirement("b")]);
+        requires.insert("b", vec![requirement("c")]);
+        requires.insert("c", vec![requirement("a")]);
+
+        let error = resolve(&[requirement("a")], &requires).err().unwrap();
+
+        match error.downcast_ref::<ResolveError>() {
+            Some(ResolveError::Cycle { cycle, .. }) => assert_eq!(cycle, "a -> b -> c -> a"),
+            _ => panic!("expected a cycle, got {}", error),
+        }
+    }
+
+    #[test]
+    fn different_pins_conflict() {
+        let mut requires = HashMap::new();
+        let mut pinned = requirement("shared");
+        pinned.pin = Some(Pin::Branch("stable".to_owned()));
+        requires.insert("a", vec![requirement("shared")]);
+        requires.insert("b", vec![pinned]);
+
+        let error = resolve(&[requirement("a"), requirement("b")], &requires)
+            .err()
+            .unwrap();
+
+        match error.downcast_ref::<ResolveError>() {
+            Some(ResolveError::PinConflict { by, other_by, .. }) => {
+                assert_eq!((by.as_str(), other_by.as_str()), ("a", "b"))
+            }
+            _ => panic!("expected a pin conflict, got {}", error),
+        }
+    }
+}
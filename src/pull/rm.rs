@@ -1,25 +1,24 @@
-// use std::path::PathBuf;
+use super::lock::Lock;
 use crate::manifest::State;
-use crate::utils;
 use clap::ArgMatches;
-// use snafu::ResultExt;
+use log::info;
 
-pub fn repel(matches: &ArgMatches, state: &mut State) -> Result<(), utils::CommonErrors> {
+/// Stop requiring a pulled state. What it installed is left alone.
+pub fn repel(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
     let to_repel = matches.value_of("name").unwrap();
 
     if let Some(requires) = &mut state.data.requires {
-        requires.retain(|e| {
-            e.1.components()
-                .last()
-                .unwrap()
-                .as_os_str()
-                .to_str()
-                .unwrap()
-                != to_repel
-        });
+        requires.retain(|r| r.name != to_repel);
     }
 
-    state.data.populate_file(&state);
+    let mut lock = Lock::read(state)?;
+    if lock.states.iter().any(|l| l.name == to_repel) {
+        lock.remove(to_repel);
+        lock.write(state)?;
+    }
+
+    info!("No longer requiring {}.", to_repel);
+    state.data.populate_file(&state)?;
 
     Ok(())
 }
@@ -0,0 +1,120 @@
+use super::list;
+use super::lock::Lock;
+use super::source::Source;
+use super::update::NotRequired;
+use crate::manifest::{DeployMode, State};
+use crate::utils;
+use clap::ArgMatches;
+use log::info;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum ShowError {
+    #[snafu(display("Great state {} isn't pulled yet! Pull it with `great pull update {}`.", name, name))]
+    NotPulled { name: String, source: std::io::Error },
+}
+
+/// Show everything a required state defines, as its own greatness.yaml
+/// says.
+pub fn show(matches: &ArgMatches, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+    let name = matches.value_of("name").unwrap();
+    let requirement = match state.data.requires.iter().flatten().find(|r| r.name == name) {
+        Some(r) => r.clone(),
+        None => {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                .context(NotRequired { name })?
+        }
+    };
+
+    let pulled = match list::pulled_state(state, &requirement)? {
+        Some(pulled) => pulled,
+        None => {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                .context(NotPulled { name })?
+        }
+    };
+
+    info!("name: \x1b[1m{}\x1b[0m", requirement.name);
+    info!("url: {}", requirement.url);
+    info!("directory: {}", pulled.greatness_dir.display());
+    if let Some(pin) = &requirement.pin {
+        info!("pin: {}", pin);
+    }
+    if let Some(tag) = &requirement.tag {
+        info!("only with tags: {}", tag);
+    }
+    if matches!(Source::parse(&requirement.url), Source::Git(_)) {
+        info!(
+            "{}",
+            list::git_status(&requirement, &pulled.greatness_dir, &Lock::read(state)?)?
+        );
+    }
+
+    print!("\n");
+
+    let contributed = list::contributed(&requirement, &pulled.data)?;
+    match &pulled.data.files {
+        Some(files) if !files.is_empty() => {
+            info!("Files:");
+            for file in files {
+                info!("\tpath: {}", utils::special_to_absolute(&file.path).display());
+
+                if !file.tags.is_empty() {
+                    info!("\t\ttags: {}", file.tags.join(", "));
+                }
+
+                if let Some(when) = &file.when {
+                    info!("\t\twhen: {}", when);
+                }
+
+                if file.template {
+                    info!("\t\ttemplate: yes");
+                }
+
+                if file.encrypted {
+                    info!("\t\tencrypted: yes");
+                }
+
+                if file.deploy != DeployMode::Copy {
+                    info!("\t\tdeploy: {}", file.deploy);
+                }
+
+                if !contributed.contains(&file) {
+                    info!("\t\t\x1b[1mnot installed\x1b[0m");
+                }
+            }
+        }
+        _ => info!("\x1b[1mNo files!\x1b[0m"),
+    }
+
+    if let Some(scripts) = pulled.data.all_scripts() {
+        info!("Scripts:");
+        for script in scripts {
+            info!("\t{}", utils::special_to_absolute(&script).display());
+        }
+    }
+
+    if let Some(packages) = &pulled.data.packages {
+        info!("Packages:");
+        for package in packages {
+            info!("\tname: {}", package.package);
+
+            if let Some(when) = &package.when {
+                info!("\t\twhen: {}", when);
+            }
+
+            for (manager, overload) in &package.package_overloads {
+                info!("\t\t{}: {}", manager, overload);
+            }
+        }
+    }
+
+    if let Some(requires) = &pulled.data.requires {
+        info!("Requires:");
+        for required in requires {
+            info!("\t{} ({})", required.name, required.url);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,396 @@
+//! Where states are pulled from. Besides git repositories, a state can
+//! be pulled from a directory on this machine, or an archive made by
+//! `great export`, neither of which need git or a network.
+
+use crate::export::{self, Format};
+use crate::git::clone;
+use crate::manifest::State;
+use crate::plan::Action;
+use crate::utils;
+use log::{debug, info};
+use snafu::{ResultExt, Snafu};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, PathBuf};
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum SourceError {
+    #[snafu(display("Great source {} doesn't exist!", path.display()))]
+    NoSuchSource {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Great source {} isn't a greatness state, as it has no greatness.yaml!", from))]
+    NotAState { from: String, source: std::io::Error },
+
+    #[snafu(display("Failed to extract great archive {}: {}", archive.display(), source))]
+    ExtractError {
+        archive: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to extract great zip archive {}: {}", archive.display(), source))]
+    ZipExtractError {
+        archive: PathBuf,
+        source: zip::result::ZipError,
+    },
+
+    #[snafu(display("Great archive {} has an unsafe path {} in it, so it wasn't extracted!", archive.display(), path.display()))]
+    UnsafePath {
+        archive: PathBuf,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Something a state can be pulled from.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Source {
+    /// A git repository, which is cloned.
+    Git(String),
+    /// A directory on this machine, which is copied.
+    Dir(PathBuf),
+    /// An archive made by `great export`, which is extracted.
+    Archive(PathBuf, Format),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Git(url) => write!(f, "{}", url),
+            Self::Dir(path) | Self::Archive(path, _) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Source {
+    /// Work out what kind of source something to pull from is. Paths
+    /// (absolute, relative, or `file://` URLs) are local, as is
+    /// anything that exists here and looks like a state or an archive.
+    /// Everything else is a git repository.
+    pub fn parse(from: &str) -> Self {
+        let local = from.strip_prefix("file://");
+        let path = PathBuf::from(local.unwrap_or(from));
+
+        let explicit = local.is_some() || from.starts_with(&['/', '.'][..]);
+        let looks_local = path.join("greatness.yaml").exists()
+            || (path.is_file() && Format::of(&path).is_some());
+        if !explicit && !looks_local {
+            return Self::Git(utils::make_url_valid(from.to_owned()));
+        }
+
+        let path = match path.canonicalize() {
+            Ok(p) => p,
+            Err(_) => std::env::current_dir().unwrap_or_default().join(path),
+        };
+
+        match Format::of(&path) {
+            Some(format) if !path.is_dir() => Self::Archive(path, format),
+            _ => Self::Dir(path),
+        }
+    }
+
+    /// Gets the name of a state pulled from here, such as
+    /// github.com/user/dotfiles. It is pulled to this, inside of the
+    /// pulled directory.
+    pub fn name(&self) -> String {
+        match self {
+            Self::Git(url) => {
+                let name = url.split("://").last().unwrap_or(url);
+                let name = name.strip_prefix("git@").unwrap_or(name).replacen(':', "/", 1);
+
+                name.trim_matches('/')
+                    .trim_end_matches(".git")
+                    .to_owned()
+            }
+            Self::Dir(path) => path
+                .file_name()
+                .unwrap_or_default()
+                .to_str()
+                .unwrap()
+                .to_owned(),
+            Self::Archive(path, format) => format.strip(path.file_name().unwrap().to_str().unwrap()),
+        }
+    }
+
+    /// Gets where a state of this name is pulled to. Only the normal
+    /// parts of the name are used, so it can't point outside of the
+    /// pulled directory.
+    pub fn dest(state: &State, name: &str) -> PathBuf {
+        let mut dest = state.greatness_pulled_dir.clone();
+        for component in PathBuf::from(name).components() {
+            if let Component::Normal(part) = component {
+                dest.push(part);
+            }
+        }
+
+        dest
+    }
+
+    /// What fetching into `into` does, for dry runs.
+    pub fn action(&self, into: &PathBuf) -> Action {
+        match self {
+            Self::Git(url) => Action::Clone {
+                url: url.clone(),
+                into: into.clone(),
+            },
+            Self::Dir(path) | Self::Archive(path, _) => Action::Fetch {
+                from: path.clone(),
+                into: into.clone(),
+            },
+        }
+    }
+
+    /// Get the state into `into`, which doesn't exist yet.
+    pub fn fetch(&self, into: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Self::Git(url) => {
+                // TODO: Implement a progress bar. https://docs.rs/git2/0.13.20/git2/struct.Progress.html
+                info!("Cloning from {} into {}....", url, into.display());
+                clone::clone_repo(url, into)?;
+            }
+            Self::Dir(path) => {
+                info!("Copying from {} into {}....", path.display(), into.display());
+                if !path.is_dir() {
+                    return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                        .context(NoSuchSource { path })?;
+                }
+
+                // Don't copy all of some directory that was mistyped
+                if !path.join("greatness.yaml").exists() {
+                    return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                        .context(NotAState { from: self.to_string() })?;
+                }
+
+                copy_dir(path, into)?;
+            }
+            Self::Archive(path, format) => {
+                info!("Extracting {} into {}....", path.display(), into.display());
+                let file = std::fs::File::open(path).context(NoSuchSource { path })?;
+
+                match format {
+                    Format::TarGz => extract_tar(flate2::read::GzDecoder::new(file), path, into)?,
+                    Format::TarZst => extract_tar(
+                        zstd::Decoder::new(file).context(ExtractError { archive: path })?,
+                        path,
+                        into,
+                    )?,
+                    Format::Zip => extract_zip(file, path, into)?,
+                }
+            }
+        }
+
+        if !into.join("greatness.yaml").exists() {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                .context(NotAState { from: self.to_string() })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Copy a directory, leaving out git's own files.
+fn copy_dir(from: &PathBuf, to: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in walkdir::WalkDir::new(from)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git")
+    {
+        let entry = entry?;
+        let dest = to.join(entry.path().strip_prefix(from).unwrap());
+        debug!("Copying {} -> {}....", entry.path().display(), dest.display());
+
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest).context(utils::DirCreationError { dir: &dest })?;
+        } else if entry.file_type().is_symlink() {
+            let target = std::fs::read_link(entry.path())
+                .context(utils::FileReadError { file: entry.path() })?;
+            std::os::unix::fs::symlink(target, &dest)
+                .context(utils::FileWriteError { file: &dest })?;
+        } else {
+            std::fs::copy(entry.path(), &dest).context(utils::FileCopyError {
+                src: entry.path(),
+                dest: &dest,
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets where an archived path goes. Exported archives keep everything
+/// under `greatness/`. Paths that would escape `into` are refused.
+fn extracted_path(archive: &PathBuf, path: &PathBuf, into: &PathBuf) -> Result<PathBuf, SourceError> {
+    let relative = path.strip_prefix(export::ARCHIVE_ROOT).unwrap_or(path);
+
+    if relative
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidData))
+            .context(UnsafePath { archive, path });
+    }
+
+    Ok(into.join(relative))
+}
+
+fn extract_tar<R: Read>(
+    reader: R,
+    archive: &PathBuf,
+    into: &PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tar = tar::Archive::new(reader);
+    std::fs::create_dir_all(into).context(utils::DirCreationError { dir: into })?;
+
+    for entry in tar.entries().context(ExtractError { archive })? {
+        let mut entry = entry.context(ExtractError { archive })?;
+        let path = entry.path().context(ExtractError { archive })?.to_path_buf();
+        let dest = extracted_path(archive, &path, into)?;
+        debug!("Extracting {} -> {}....", path.display(), dest.display());
+        refuse_linked_parents(archive, &path, &dest, into)?;
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).context(utils::DirCreationError { dir: parent })?;
+        }
+
+        // Links are only made to what is in the archive
+        let link = entry.link_name().context(ExtractError { archive })?;
+        match (entry.header().entry_type(), link) {
+            (tar::EntryType::Symlink, Some(target)) => {
+                if link_escapes(dest.strip_prefix(into).unwrap(), &target) {
+                    return Err(std::io::Error::from(std::io::ErrorKind::InvalidData))
+                        .context(UnsafePath { archive, path })?;
+                }
+
+                std::os::unix::fs::symlink(&target, &dest)
+                    .context(utils::FileWriteError { file: &dest })?;
+            }
+            (tar::EntryType::Link, Some(target)) => {
+                let target = extracted_path(archive, &target.into_owned(), into)?;
+                std::fs::hard_link(&target, &dest).context(utils::FileWriteError { file: &dest })?;
+            }
+            _ => {
+                entry.unpack(&dest).context(ExtractError { archive })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuse to extract through a link made by an earlier entry, which
+/// could point anywhere.
+fn refuse_linked_parents(
+    archive: &PathBuf,
+    path: &PathBuf,
+    dest: &PathBuf,
+    into: &PathBuf,
+) -> Result<(), SourceError> {
+    let mut parent = dest.parent();
+    while let Some(dir) = parent {
+        if dir == into.as_path() {
+            break;
+        }
+
+        if dir.symlink_metadata().map_or(false, |m| m.file_type().is_symlink()) {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidData))
+                .context(UnsafePath { archive, path });
+        }
+        parent = dir.parent();
+    }
+
+    Ok(())
+}
+
+/// Whether a symlink at `at`, relative to where the archive is
+/// extracted, would point outside of it. Targets must be relative, and
+/// may only go up at the start, so that no link they go through can
+/// take them further up.
+fn link_escapes(at: &std::path::Path, target: &std::path::Path) -> bool {
+    let mut depth = at.components().count().saturating_sub(1);
+    let mut going_up = true;
+    for component in target.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if going_up && depth > 0 => depth -= 1,
+            Component::Normal(_) => going_up = false,
+            _ => return true,
+        }
+    }
+
+    false
+}
+
+fn extract_zip(
+    file: std::fs::File,
+    archive: &PathBuf,
+    into: &PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut zip = zip::ZipArchive::new(file).context(ZipExtractError { archive })?;
+    std::fs::create_dir_all(into).context(utils::DirCreationError { dir: into })?;
+
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).context(ZipExtractError { archive })?;
+        let path = PathBuf::from(entry.name());
+        let dest = extracted_path(archive, &path, into)?;
+        debug!("Extracting {} -> {}....", path.display(), dest.display());
+
+        if entry.is_dir() {
+            std::fs::create_dir_all(&dest).context(utils::DirCreationError { dir: &dest })?;
+            continue;
+        }
+
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).context(utils::DirCreationError { dir: parent })?;
+        }
+
+        let mut contents = vec![];
+        entry
+            .read_to_end(&mut contents)
+            .context(ExtractError { archive })?;
+        std::fs::write(&dest, contents).context(utils::FileWriteError { file: &dest })?;
+
+        if let Some(mode) = entry.unix_mode() {
+            std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(mode & 0o777))
+                .context(utils::FileWriteError { file: &dest })?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn links_may_not_escape() {
+        assert!(!link_escapes(Path::new("a/b"), Path::new("c")));
+        assert!(!link_escapes(Path::new("a/b"), Path::new("../c/d")));
+        assert!(link_escapes(Path::new("a/b"), Path::new("../../c")));
+        assert!(link_escapes(Path::new("a"), Path::new("/etc/passwd")));
+        assert!(link_escapes(Path::new("a/b"), Path::new("c/../..")));
+    }
+
+    #[test]
+    fn archives_are_not_extracted_through_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("state.tar");
+        let into = dir.path().join("into");
+
+        let mut builder = tar::Builder::new(std::fs::File::create(&archive).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "greatness/files", dir.path())
+            .unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let file = std::fs::File::open(&archive).unwrap();
+        assert!(extract_tar(file, &archive, &into).is_err());
+        assert!(into.join("files").symlink_metadata().is_err());
+    }
+}
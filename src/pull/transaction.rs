@@ -0,0 +1,373 @@
+//! Pulls are transactions. Before anything is written during a pull,
+//! what was there is saved and written to a journal, so that a failed
+//! (or interrupted) pull can be rolled back. Scripts and packages
+//! can't be rolled back, as there is no telling what they did.
+
+use crate::manifest::State;
+use crate::plan;
+use crate::utils;
+use clap::ArgMatches;
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum TransactionError {
+    #[snafu(display("A previous pull was interrupted, and may have left things half done! Run `great pull abort` to roll it back first."))]
+    Interrupted { source: std::io::Error },
+
+    #[snafu(display("Failed to journal great file {}: {}", file.display(), source))]
+    JournalError {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to roll back great file {}: {}", file.display(), source))]
+    RollbackError {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// What was at a path before the pull touched it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+enum Entry {
+    /// The pull started.
+    Begin { what: String },
+    /// Nothing was there. Rolling back removes whatever is now.
+    Created { path: PathBuf },
+    /// Something was there, and was saved to `saved`.
+    Replaced { path: PathBuf, saved: PathBuf },
+}
+
+struct Transaction {
+    dir: PathBuf,
+    journal: std::fs::File,
+    /// Paths already journaled, as only the first state of a path matters.
+    touched: HashSet<PathBuf>,
+    /// How many things were saved so far.
+    saved: usize,
+}
+
+lazy_static! {
+    static ref TRANSACTION: Mutex<Option<Transaction>> = Mutex::new(None);
+}
+
+fn journal_of(dir: &PathBuf) -> PathBuf {
+    dir.join("journal")
+}
+
+/// Start a transaction. Fails if a previous one never finished.
+pub fn begin(state: &State, what: &str) -> Result<(), TransactionError> {
+    if plan::is_dry_run() {
+        return Ok(());
+    }
+
+    let transaction = Transaction::start(&state.greatness_transaction_dir, what)?;
+    *TRANSACTION.lock().unwrap() = Some(transaction);
+
+    Ok(())
+}
+
+impl Transaction {
+    /// Start journaling into `dir`.
+    fn start(dir: &PathBuf, what: &str) -> Result<Self, TransactionError> {
+        let file = journal_of(dir);
+        if file.exists() {
+            return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists))
+                .context(Interrupted {});
+        }
+
+        std::fs::create_dir_all(dir.join("saved")).context(JournalError { file: &file })?;
+        let journal = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file)
+            .context(JournalError { file: &file })?;
+
+        let mut transaction = Transaction {
+            dir: dir.clone(),
+            journal,
+            touched: HashSet::new(),
+            saved: 0,
+        };
+        transaction
+            .append(&Entry::Begin {
+                what: what.to_owned(),
+            })
+            .context(JournalError { file: &file })?;
+
+        Ok(transaction)
+    }
+
+    /// Write an entry to the journal, making sure it is on disk before
+    /// whatever it describes happens.
+    fn append(&mut self, entry: &Entry) -> Result<(), std::io::Error> {
+        let line = serde_json::to_string(entry)?;
+        writeln!(self.journal, "{}", line)?;
+        self.journal.sync_all()
+    }
+
+    fn next_saved(&mut self) -> PathBuf {
+        self.saved += 1;
+        self.dir.join("saved").join(self.saved.to_string())
+    }
+
+    fn touch(&mut self, path: &PathBuf) -> Result<(), std::io::Error> {
+        // The outermost directory that doesn't exist is what gets created
+        let mut path = path.clone();
+        while let Some(parent) = path.parent() {
+            if parent.symlink_metadata().is_ok() {
+                break;
+            }
+            path = parent.to_path_buf();
+        }
+
+        if self.touched.contains(&path) {
+            return Ok(());
+        }
+
+        let entry = match path.symlink_metadata() {
+            Err(_) => Entry::Created { path: path.clone() },
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let saved = self.next_saved();
+                std::os::unix::fs::symlink(std::fs::read_link(&path)?, &saved)?;
+                Entry::Replaced {
+                    path: path.clone(),
+                    saved,
+                }
+            }
+            Ok(metadata) if metadata.is_file() => {
+                let saved = self.next_saved();
+                std::fs::copy(&path, &saved)?;
+                Entry::Replaced {
+                    path: path.clone(),
+                    saved,
+                }
+            }
+            // Directories that are written into are left be
+            Ok(_) => return Ok(()),
+        };
+
+        debug!("Journaling {:?}....", entry);
+        self.append(&entry)?;
+        self.touched.insert(path);
+
+        Ok(())
+    }
+
+    fn set_aside(&mut self, path: &PathBuf) -> Result<(), std::io::Error> {
+        let saved = self.next_saved();
+        self.append(&Entry::Replaced {
+            path: path.clone(),
+            saved: saved.clone(),
+        })?;
+        std::fs::rename(path, &saved)?;
+        self.touched.insert(path.clone());
+
+        Ok(())
+    }
+}
+
+/// Call before writing to a path. Saves what is there now, so that it
+/// can be put back. Directories that don't exist yet are journaled too,
+/// so that rolling back doesn't leave them behind. Does nothing outside
+/// of a transaction.
+pub fn touch(path: &PathBuf) -> Result<(), std::io::Error> {
+    match TRANSACTION.lock().unwrap().as_mut() {
+        Some(transaction) => transaction.touch(path),
+        None => Ok(()),
+    }
+}
+
+/// Call instead of removing a path. Moves it out of the way, so that
+/// it can be put back. Returns false outside of a transaction, where
+/// the caller should remove it themselves.
+pub fn set_aside(path: &PathBuf) -> Result<bool, std::io::Error> {
+    match TRANSACTION.lock().unwrap().as_mut() {
+        Some(transaction) => transaction.set_aside(path).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+/// Finish the transaction, keeping everything it did.
+pub fn commit() -> Result<(), utils::CommonErrors> {
+    if let Some(transaction) = TRANSACTION.lock().unwrap().take() {
+        std::fs::remove_dir_all(&transaction.dir).context(utils::DirDeletionError {
+            dir: &transaction.dir,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Undo everything the transaction journaled in `dir`, newest first.
+pub fn rollback(dir: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+    // Nothing may be journaled whilst rolling back
+    TRANSACTION.lock().unwrap().take();
+
+    let file = journal_of(dir);
+    let journal = std::fs::File::open(&file).context(utils::FileOpenError { file: &file })?;
+
+    let mut entries = vec![];
+    for line in std::io::BufReader::new(journal).lines() {
+        let line = line.context(utils::FileReadError { file: &file })?;
+
+        // The last line may have been cut off, if so, what it was
+        // about to describe never happened
+        match serde_json::from_str::<Entry>(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => warn!("Skipping the unfinished journal entry {}....", line),
+        }
+    }
+
+    for entry in entries.iter().rev() {
+        match entry {
+            Entry::Begin { what } => info!("Rolled back pulling {}!", what),
+            Entry::Created { path } => {
+                debug!("Rolling back {} by removing it....", path.display());
+                remove_any(path).context(RollbackError { file: path })?;
+            }
+            Entry::Replaced { path, saved } => {
+                // Interrupted before it was set aside, so it is untouched
+                if saved.symlink_metadata().is_err() {
+                    continue;
+                }
+
+                debug!("Rolling back {} from {}....", path.display(), saved.display());
+                remove_any(path).context(RollbackError { file: path })?;
+                std::fs::rename(saved, path).context(RollbackError { file: path })?;
+            }
+        }
+    }
+
+    std::fs::remove_dir_all(dir).context(utils::DirDeletionError { dir })?;
+
+    Ok(())
+}
+
+/// Remove a file, link or directory, if there is one.
+fn remove_any(path: &PathBuf) -> Result<(), std::io::Error> {
+    match path.symlink_metadata() {
+        Err(_) => Ok(()),
+        Ok(m) if m.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+    }
+}
+
+/// Roll back a pull that was interrupted.
+pub fn abort(_matches: &ArgMatches, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+    if !journal_of(&state.greatness_transaction_dir).exists() {
+        info!("No pull was interrupted, so there is nothing to abort, which is great!");
+        return Ok(());
+    }
+
+    if plan::is_dry_run() {
+        info!(
+            "Would roll back the interrupted pull journaled in {}.",
+            state.greatness_transaction_dir.display()
+        );
+        return Ok(());
+    }
+
+    rollback(&state.greatness_transaction_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(dir: &tempfile::TempDir) -> Transaction {
+        Transaction::start(&dir.path().join("greatness-pull"), "test").unwrap()
+    }
+
+    #[test]
+    fn created_files_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let created = dir.path().join("new/dirs/file");
+        let mut transaction = start(&dir);
+
+        transaction.touch(&created).unwrap();
+        std::fs::create_dir_all(created.parent().unwrap()).unwrap();
+        std::fs::write(&created, "new").unwrap();
+        rollback(&transaction.dir).unwrap();
+
+        assert!(!dir.path().join("new").exists());
+        assert!(!transaction.dir.exists());
+    }
+
+    #[test]
+    fn replaced_files_and_links_are_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        let link = dir.path().join("link");
+        std::fs::write(&file, "old").unwrap();
+        std::os::unix::fs::symlink("old target", &link).unwrap();
+        let mut transaction = start(&dir);
+
+        transaction.touch(&file).unwrap();
+        std::fs::write(&file, "new").unwrap();
+        transaction.touch(&file).unwrap();
+        std::fs::write(&file, "newer").unwrap();
+        transaction.touch(&link).unwrap();
+        std::fs::remove_file(&link).unwrap();
+        std::fs::write(&link, "not a link").unwrap();
+        rollback(&transaction.dir).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "old");
+        assert_eq!(
+            std::fs::read_link(&link).unwrap(),
+            PathBuf::from("old target")
+        );
+    }
+
+    #[test]
+    fn set_aside_directories_are_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts = dir.path().join("scripts");
+        std::fs::create_dir(&scripts).unwrap();
+        std::fs::write(scripts.join("old.lua"), "old").unwrap();
+        let mut transaction = start(&dir);
+
+        transaction.set_aside(&scripts).unwrap();
+        std::fs::create_dir(&scripts).unwrap();
+        std::fs::write(scripts.join("new.lua"), "new").unwrap();
+        rollback(&transaction.dir).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(scripts.join("old.lua")).unwrap(),
+            "old"
+        );
+        assert!(!scripts.join("new.lua").exists());
+    }
+
+    #[test]
+    fn cut_off_entries_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "old").unwrap();
+        let mut transaction = start(&dir);
+
+        transaction.touch(&file).unwrap();
+        std::fs::write(&file, "new").unwrap();
+        write!(transaction.journal, "{{\"entry\":\"created\",\"pa").unwrap();
+        rollback(&transaction.dir).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "old");
+    }
+
+    #[test]
+    fn unfinished_transactions_block_new_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let _transaction = start(&dir);
+
+        assert!(Transaction::start(&dir.path().join("greatness-pull"), "again").is_err());
+    }
+}
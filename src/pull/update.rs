@@ -0,0 +1,566 @@
+//! Bringing pulled states up to date. Git states are fetched into a
+//! copy of their clone, everything else is fetched again. Only the files
+//! that changed are installed again.
+
+use super::add;
+use super::list;
+use super::installed::Installed;
+use super::lock::{Lock, Locked};
+use super::resolve::Graph;
+use super::source::Source;
+use super::transaction;
+use crate::backup;
+use crate::git::clone;
+use crate::manifest::{AddedFile, Manifest, Requirement, State};
+use crate::merge;
+use crate::plan::{self, Action};
+use crate::tag::selector;
+use crate::utils;
+use clap::ArgMatches;
+use log::{debug, info, warn};
+use snafu::{ResultExt, Snafu};
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum UpdateError {
+    #[snafu(display("Great state {} isn't required! See `great pull list`.", name))]
+    NotRequired { name: String, source: std::io::Error },
+}
+
+/// What changed in a state since it was last pulled.
+struct Changes {
+    /// Paths that changed, relative to the state.
+    paths: Vec<PathBuf>,
+    /// Files that are new or changed, and are installed again.
+    files: Vec<AddedFile>,
+    /// Files that are new, out of the above.
+    added: Vec<PathBuf>,
+    /// Files that are no longer in the state. They are left alone.
+    removed: Vec<PathBuf>,
+    scripts: Vec<PathBuf>,
+    added_packages: Vec<String>,
+    removed_packages: Vec<String>,
+}
+
+/// Update the given required states, or all of them, along with the
+/// states they require. Updating is a transaction, like pulling. See
+/// `transaction`.
+pub fn update(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let requires = state.data.requires.clone().unwrap_or_default();
+    let to_update = match matches.values_of("names") {
+        Some(names) => {
+            let mut to_update = vec![];
+            for name in names {
+                match requires.iter().find(|r| r.name == name) {
+                    Some(r) => to_update.push(r.clone()),
+                    None => {
+                        return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                            .context(NotRequired { name })?
+                    }
+                }
+            }
+
+            to_update
+        }
+        None => requires,
+    };
+
+    if to_update.is_empty() {
+        info!("Nothing is required, so there is nothing to update!");
+        return Ok(());
+    }
+
+    // The states they require are updated too, each before the states
+    // requiring it. They are resolved as they are pulled now
+    let pulled: &State = state;
+    let graph = Graph::resolve(&to_update, &Lock::read(state)?, |requirement, _, _| {
+        match list::pulled_state(pulled, requirement)? {
+            Some(pulled) => Ok((
+                (),
+                pulled.data.requires.clone().unwrap_or_default(),
+                Lock::read(&pulled)?,
+            )),
+            None => Ok(((), vec![], Lock::default())),
+        }
+    })?;
+
+    transaction::begin(state, "update")?;
+
+    let mut updated = || -> Result<(), Box<dyn std::error::Error>> {
+        for index in &graph.order {
+            let node = &graph.nodes[*index];
+            update_one(&node.requirement, node.sub_state, matches, state)?;
+        }
+
+        Ok(())
+    };
+
+    match updated() {
+        Ok(()) => {
+            transaction::commit()?;
+            Ok(())
+        }
+        Err(e) => {
+            if !plan::is_dry_run() {
+                warn!("Updating failed, so rolling back: {}", e);
+                transaction::rollback(&state.greatness_transaction_dir)?;
+            }
+
+            Err(e)
+        }
+    }
+}
+
+/// Update a state. States it newly requires are pulled.
+/// * `sub_state` - Whether it is required by another required state,
+///   instead of by the main one.
+fn update_one(
+    requirement: &Requirement,
+    sub_state: bool,
+    matches: &ArgMatches,
+    state: &mut State,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dest = Source::dest(state, &requirement.name);
+    if !dest.exists() {
+        info!("{} isn't pulled yet, so pulling it....", requirement.name);
+        return add::clone_and_install_repo(
+            requirement,
+            &Lock::default(),
+            matches,
+            state,
+            sub_state,
+        );
+    }
+
+    backup::set_reason(format!("update {}", requirement.name));
+    let mut old_state = State::new(dest.clone())?;
+    old_state.data = Manifest::read_from_file(&old_state)?;
+
+    // The new version is fetched next to the old one, and only replaces
+    // it once it is known to be a state
+    let source = Source::parse(&requirement.url);
+    let staging = staging_dir(state, &dest)?;
+    let (changed, commit) = match fetch_new(requirement, &source, &dest, &staging) {
+        Ok((changed, _)) if changed.is_empty() => {
+            std::fs::remove_dir_all(&staging).context(utils::DirDeletionError { dir: &staging })?;
+            info!("{} is up to date, which is great!", requirement.name);
+            return Ok(());
+        }
+        Ok(fetched) => fetched,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+    };
+
+    // A dry run plans with the new version where it was fetched to
+    let new_dir = if plan::record(Action::Fetch {
+        from: PathBuf::from(&requirement.url),
+        into: dest.clone(),
+    }) {
+        staging.clone()
+    } else {
+        if !transaction::set_aside(&dest).context(utils::FileDeletionError { file: &dest })? {
+            std::fs::remove_dir_all(&dest).context(utils::DirDeletionError { dir: &dest })?;
+        }
+        std::fs::rename(&staging, &dest).context(utils::FileWriteError { file: &dest })?;
+        dest.clone()
+    };
+
+    let source_name = merge::source_name(state, &dest);
+    let installed = install_changes(
+        requirement,
+        matches,
+        state,
+        &old_state.data,
+        &new_dir,
+        &source_name,
+        changed,
+    );
+    if new_dir != dest {
+        std::fs::remove_dir_all(&new_dir).context(utils::DirDeletionError { dir: &new_dir })?;
+    }
+    installed?;
+
+    if let Some(commit) = commit {
+        let mut lock = Lock::read(state)?;
+        lock.set(Locked {
+            name: requirement.name.clone(),
+            url: source.to_string(),
+            commit,
+        });
+        lock.write(state)?;
+    }
+
+    Ok(())
+}
+
+/// Fetch the new version of a state into `into`. Git states are copied
+/// and fetched into, so that only what changed is downloaded. Returns
+/// the paths that changed, and the commit checked out if from git.
+fn fetch_new(
+    requirement: &Requirement,
+    source: &Source,
+    dest: &PathBuf,
+    into: &PathBuf,
+) -> Result<(Vec<PathBuf>, Option<String>), Box<dyn std::error::Error>> {
+    if !matches!(source, Source::Git(_)) {
+        source.fetch(into)?;
+
+        return Ok((changed_between_dirs(dest, into)?, None));
+    }
+
+    let mut options = fs_extra::dir::CopyOptions::new();
+    options.content_only = true;
+    std::fs::create_dir_all(into).context(utils::DirCreationError { dir: into })?;
+    fs_extra::dir::copy(dest, into, &options)?;
+
+    let old = clone::head_commit(into)?;
+    info!("Fetching {} from {}....", requirement.name, source);
+    clone::fetch(into)?;
+
+    let revision = match &requirement.pin {
+        Some(pin) => pin.revision(),
+        None => clone::default_revision(into),
+    };
+    let new = clone::resolve(into, &revision)?;
+    if new == old {
+        return Ok((vec![], Some(new)));
+    }
+
+    info!(
+        "Updating {} from {} to {}....",
+        requirement.name,
+        &old[..7],
+        &new[..7]
+    );
+    let changed = clone::changed_between(into, &old, &new)?;
+    clone::checkout(into, &new)?;
+
+    Ok((changed, Some(new)))
+}
+
+/// Show what changed, and install the files that did.
+fn install_changes(
+    requirement: &Requirement,
+    matches: &ArgMatches,
+    state: &mut State,
+    old: &Manifest,
+    new_dir: &PathBuf,
+    source_name: &str,
+    changed: Vec<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut new_state = State::new(new_dir.clone())?;
+    new_state.data = Manifest::read_from_file(&new_state)?;
+
+    // Files the requirement doesn't pick were never installed
+    let selector = selector::parse(requirement.tag.as_deref())?;
+    let mut changes = changes_of(old, &new_state.data, changed);
+    changes
+        .files
+        .retain(|file| selector.as_ref().map_or(true, |s| s.matches(&file.tags)));
+    print_changes(&changes);
+
+    let mut affected = new_state.data.clone();
+    affected.files = Some(changes.files.clone());
+    add::install_files(
+        matches,
+        &new_dir.join("files"),
+        state,
+        &affected,
+        source_name,
+        selector.as_ref(),
+    )?;
+
+    if !changes.scripts.is_empty()
+        || !changes.added_packages.is_empty()
+        || !changes.removed_packages.is_empty()
+    {
+        let packages = add::install_mods(matches, &mut new_state, selector.as_ref())?;
+        Installed::record_packages(state, source_name, &packages)?;
+    }
+
+    // States it newly requires are pulled, like when pulling it
+    for sub_requirement in new_state.data.requires.clone().unwrap_or_default() {
+        if !Source::dest(state, &sub_requirement.name).exists() {
+            add::clone_and_install_repo(
+                &sub_requirement,
+                &Lock::read(&new_state)?,
+                matches,
+                state,
+                true,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Work out what changed between two versions of a state, from the
+/// paths that differ.
+fn changes_of(old: &Manifest, new: &Manifest, paths: Vec<PathBuf>) -> Changes {
+    let files_dir = PathBuf::from("files");
+    let variables_changed = old.variables != new.variables;
+
+    let files = new
+        .files
+        .iter()
+        .flatten()
+        .filter(|file| {
+            let packed = utils::special_in_dir(&files_dir, &file.path);
+
+            old.files.iter().flatten().find(|f| f.path == file.path) != Some(*file)
+                || paths.iter().any(|p| p.starts_with(&packed))
+                || (file.template && variables_changed)
+        })
+        .cloned()
+        .collect();
+
+    let added = new
+        .files
+        .iter()
+        .flatten()
+        .filter(|file| !old.files.iter().flatten().any(|f| f.path == file.path))
+        .map(|file| file.path.clone())
+        .collect();
+
+    let removed = old
+        .files
+        .iter()
+        .flatten()
+        .filter(|file| !new.files.iter().flatten().any(|f| f.path == file.path))
+        .map(|file| file.path.clone())
+        .collect();
+
+    let package_names = |manifest: &Manifest| {
+        manifest
+            .packages
+            .iter()
+            .flatten()
+            .map(|p| p.package.clone())
+            .collect::<Vec<String>>()
+    };
+    let (old_packages, new_packages) = (package_names(old), package_names(new));
+
+    Changes {
+        scripts: paths
+            .iter()
+            .filter(|p| p.starts_with("scripts"))
+            .cloned()
+            .collect(),
+        added_packages: new_packages
+            .iter()
+            .filter(|p| !old_packages.contains(p))
+            .cloned()
+            .collect(),
+        removed_packages: old_packages
+            .iter()
+            .filter(|p| !new_packages.contains(p))
+            .cloned()
+            .collect(),
+        paths,
+        files,
+        added,
+        removed,
+    }
+}
+
+fn print_changes(changes: &Changes) {
+    debug!("Changed: {:?}", changes.paths);
+
+    if changes.files.is_empty() && changes.removed.is_empty() {
+        info!("\tNo files changed.");
+    }
+
+    for file in &changes.files {
+        if changes.added.contains(&file.path) {
+            info!("\tfile    added   {}", file.path.display());
+        } else {
+            info!("\tfile    changed {}", file.path.display());
+        }
+    }
+
+    for file in &changes.removed {
+        warn!(
+            "\tfile    removed {} (it is left in place)",
+            file.display()
+        );
+    }
+
+    for script in &changes.scripts {
+        info!("\tscript  changed {}", script.display());
+    }
+
+    for package in &changes.added_packages {
+        info!("\tpackage added   {}", package);
+    }
+
+    for package in &changes.removed_packages {
+        warn!("\tpackage removed {} (it is left installed)", package);
+    }
+}
+
+/// Gets where a new version of a state is fetched to. It is next to
+/// the old one, so that it can be moved into place, unless this is a
+/// dry run.
+fn staging_dir(state: &State, dest: &PathBuf) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let name = dest.file_name().unwrap_or_default().to_owned();
+    let staging = if plan::is_dry_run() {
+        let mut staging = std::env::temp_dir();
+        staging.push(format!("great-dry-run-{}", std::process::id()));
+        staging.join(name)
+    } else {
+        let mut staging_name = name;
+        staging_name.push(format!(".update-{}", std::process::id()));
+        dest.with_file_name(staging_name)
+    };
+
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging).context(utils::DirDeletionError { dir: &staging })?;
+    }
+    transaction::touch(&staging).context(utils::DirCreationError { dir: &staging })?;
+    debug!(
+        "Fetching the new version of {} into {}....",
+        merge::source_name(state, dest),
+        staging.display()
+    );
+
+    Ok(staging)
+}
+
+/// Gets the paths that differ between two directories, relative to
+/// them, leaving out git's own files.
+fn changed_between_dirs(
+    old: &PathBuf,
+    new: &PathBuf,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let files_in = |dir: &PathBuf| -> Result<Vec<PathBuf>, walkdir::Error> {
+        let mut files = vec![];
+        for entry in walkdir::WalkDir::new(dir)
+            .into_iter()
+            .filter_entry(|e| e.file_name() != ".git")
+        {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                files.push(entry.path().strip_prefix(dir).unwrap().to_path_buf());
+            }
+        }
+
+        Ok(files)
+    };
+
+    let (old_files, new_files) = (files_in(old)?, files_in(new)?);
+    let mut changed = vec![];
+    for file in new_files.iter().chain(old_files.iter()) {
+        if changed.contains(file) {
+            continue;
+        }
+
+        let differs = match (old_files.contains(file), new_files.contains(file)) {
+            (true, true) => utils::hash_file(&old.join(file))? != utils::hash_file(&new.join(file))?,
+            _ => true,
+        };
+
+        if differs {
+            changed.push(file.clone());
+        }
+    }
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{AddedPackage, DeployMode};
+
+    fn manifest(files: &[AddedFile], packages: &[&str]) -> Manifest {
+        Manifest {
+            files: Some(files.to_vec()),
+            packages: Some(
+                packages
+                    .iter()
+                    .map(|p| AddedPackage {
+                        package: p.to_string(),
+                        ..AddedPackage::new()
+                    })
+                    .collect(),
+            ),
+            ..Manifest::default()
+        }
+    }
+
+    fn file(path: &str) -> AddedFile {
+        AddedFile::from(PathBuf::from(path))
+    }
+
+    #[test]
+    fn only_changed_files_are_installed_again() {
+        let old = manifest(&[file("{{HOME}}/.bashrc"), file("{{HOME}}/.vimrc")], &[]);
+        let new = old.clone();
+
+        let changes = changes_of(&old, &new, vec![PathBuf::from("files/{{HOME}}/.vimrc")]);
+
+        let paths = changes
+            .files
+            .iter()
+            .map(|f| f.path.clone())
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(paths, vec![PathBuf::from("{{HOME}}/.vimrc")]);
+        assert!(changes.added.is_empty());
+        assert!(changes.removed.is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_files_are_told_apart() {
+        let old = manifest(&[file("{{HOME}}/.bashrc"), file("{{HOME}}/.vimrc")], &[]);
+        let new = manifest(&[file("{{HOME}}/.bashrc"), file("{{HOME}}/.zshrc")], &[]);
+
+        let changes = changes_of(&old, &new, vec![]);
+
+        assert_eq!(changes.added, vec![PathBuf::from("{{HOME}}/.zshrc")]);
+        assert_eq!(changes.removed, vec![PathBuf::from("{{HOME}}/.vimrc")]);
+        assert_eq!(changes.files, vec![file("{{HOME}}/.zshrc")]);
+    }
+
+    #[test]
+    fn files_whose_entries_changed_are_installed_again() {
+        let old = manifest(&[file("{{HOME}}/.bashrc")], &[]);
+        let mut linked = file("{{HOME}}/.bashrc");
+        linked.deploy = DeployMode::Symlink;
+        let new = manifest(&[linked.clone()], &[]);
+
+        assert_eq!(changes_of(&old, &new, vec![]).files, vec![linked]);
+    }
+
+    #[test]
+    fn templates_are_rendered_again_when_variables_change() {
+        let mut template = file("{{HOME}}/.gitconfig");
+        template.template = true;
+        let old = manifest(&[template.clone(), file("{{HOME}}/.bashrc")], &[]);
+        let mut new = old.clone();
+        new.variables = Some(
+            vec![(
+                "email".to_owned(),
+                serde_yaml::Value::from("milo@example.com"),
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        assert_eq!(changes_of(&old, &new, vec![]).files, vec![template]);
+    }
+
+    #[test]
+    fn packages_and_scripts_are_compared() {
+        let old = manifest(&[], &["ripgrep", "exa"]);
+        let new = manifest(&[], &["ripgrep", "fd"]);
+
+        let changes = changes_of(&old, &new, vec![PathBuf::from("scripts/bashrc.lua")]);
+
+        assert_eq!(changes.added_packages, vec!["fd".to_owned()]);
+        assert_eq!(changes.removed_packages, vec!["exa".to_owned()]);
+        assert_eq!(changes.scripts, vec![PathBuf::from("scripts/bashrc.lua")]);
+    }
+}
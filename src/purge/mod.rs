@@ -0,0 +1,233 @@
+use crate::backup;
+use crate::deploy;
+use crate::manifest::{Manifest, State};
+use crate::package;
+use crate::paths;
+use crate::plan::{self, Action};
+use crate::pull::installed::{Installed, InstalledPackage};
+use crate::utils;
+use clap::ArgMatches;
+use log::{debug, info};
+use question::{Answer, Question};
+use snafu::ResultExt;
+use std::path::PathBuf;
+
+/// Something purging does.
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// Put back the file as it was before great, from the oldest
+    /// backup of it.
+    Restore {
+        path: PathBuf,
+        backup: String,
+        stored: PathBuf,
+    },
+    /// Delete a file great created.
+    Delete { path: PathBuf },
+    /// Turn a link into the greatness directory back into a file of
+    /// its own, so that it survives the directory being removed.
+    Unlink { path: PathBuf },
+    /// Remove the packages great installed.
+    Uninstall { packages: Vec<InstalledPackage> },
+    /// Remove the greatness directory itself.
+    RemoveDir { dir: PathBuf },
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Restore { path, backup, .. } => {
+                write!(f, "restore   {} (from backup {})", path.display(), backup)
+            }
+            Step::Delete { path } => write!(f, "delete    {}", path.display()),
+            Step::Unlink { path } => write!(f, "unlink    {}", path.display()),
+            Step::Uninstall { packages } => write!(
+                f,
+                "uninstall {}",
+                packages
+                    .iter()
+                    .map(|p| format!("{} ({})", p.package, p.manager))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Step::RemoveDir { dir } => write!(f, "remove    {}", dir.display()),
+        }
+    }
+}
+
+/// Removes every trace of greatness from this machine. Files great
+/// replaced are restored from the backups, files pulls wrote are
+/// deleted, and files of the main state are kept, but no longer linked
+/// into the greatness directory. Then the greatness directory itself is
+/// removed, and optionally the packages.
+pub fn purge(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let steps = plan_purge(matches, state)?;
+
+    // A dry run prints the plan of what would be done instead
+    if !plan::is_dry_run() {
+        info!("Purging greatness will:");
+        for step in &steps {
+            info!("\t{}", step);
+        }
+
+        if !matches.is_present("yes") {
+            let answer = Question::new("Do you want to purge greatness from this machine? This can't be undone!")
+                .default(Answer::NO)
+                .show_defaults()
+                .confirm();
+
+            if answer != Answer::YES {
+                info!("Not purging, which is great!");
+                return Ok(());
+            }
+        }
+    }
+
+    for step in &steps {
+        debug!("Purging: {}", step);
+        run_step(step, state)?;
+    }
+
+    if !plan::is_dry_run() {
+        info!("Purged greatness. Your computer is now merely good.");
+    }
+
+    Ok(())
+}
+
+fn plan_purge(matches: &ArgMatches, state: &State) -> Result<Vec<Step>, Box<dyn std::error::Error>> {
+    let mut steps = vec![];
+    let mut handled = vec![];
+
+    // The oldest backup of a file is what was there before great
+    for id in backup::all() {
+        for file in backup::read_index(&id)?.files {
+            let path = utils::special_to_absolute(&file.path);
+            if handled.contains(&path) || path.starts_with(&state.greatness_dir) {
+                continue;
+            }
+
+            handled.push(path.clone());
+            steps.push(Step::Restore {
+                path,
+                backup: id.clone(),
+                stored: backup::stored_path(&id, &file.path),
+            });
+        }
+    }
+
+    for path in live_files(&state.data)? {
+        if handled.contains(&path) {
+            continue;
+        }
+
+        let is_link = path
+            .symlink_metadata()
+            .map(|m| m.file_type().is_symlink())
+            .unwrap_or(false);
+        let packed = utils::special_in_dir(
+            &state.greatness_git_pack_dir.join("files"),
+            &utils::absolute_to_special(&path),
+        );
+
+        if is_link && !path.exists() {
+            steps.push(Step::Delete { path: path.clone() });
+        } else if deploy::same_file(&packed, &path) {
+            steps.push(Step::Unlink { path: path.clone() });
+        }
+        handled.push(path);
+    }
+
+    // Only what pulls wrote is deleted, not what they skipped
+    let installed = Installed::read(state)?;
+    for (_, written) in installed.states {
+        for path in written {
+            if handled.contains(&path) || path.symlink_metadata().is_err() {
+                continue;
+            }
+
+            steps.push(Step::Delete { path: path.clone() });
+            handled.push(path);
+        }
+    }
+
+    // Only what great installed is uninstalled, not what was there
+    if matches.is_present("packages") {
+        let packages = installed
+            .packages
+            .into_iter()
+            .flat_map(|(_, packages)| packages)
+            .fold(vec![], |mut all, package| {
+                if !all.contains(&package) {
+                    all.push(package);
+                }
+                all
+            });
+
+        if !packages.is_empty() {
+            steps.push(Step::Uninstall { packages });
+        }
+    }
+
+    if state.greatness_transaction_dir.exists() {
+        steps.push(Step::RemoveDir {
+            dir: state.greatness_transaction_dir.clone(),
+        });
+    }
+
+    steps.push(Step::RemoveDir {
+        dir: state.greatness_dir.clone(),
+    });
+
+    Ok(steps)
+}
+
+/// Every file of a manifest that is on the system, as absolute paths.
+fn live_files(manifest: &Manifest) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let _variables = paths::define_for(manifest);
+
+    let mut files = vec![];
+    for file in manifest.files.iter().flatten() {
+        let live = utils::special_to_absolute(&file.path);
+        if live.symlink_metadata().is_err() {
+            continue;
+        }
+
+        for expanded in file.expand(&live)? {
+            files.push(utils::special_to_absolute(&expanded));
+        }
+    }
+
+    Ok(files)
+}
+
+fn run_step(step: &Step, state: &State) -> Result<(), Box<dyn std::error::Error>> {
+    match step {
+        Step::Restore { path, stored, .. } => {
+            backup::restore::restore_file(stored, path, false)?;
+        }
+        Step::Delete { path } => {
+            if !plan::record(Action::Remove { path: path.clone() }) {
+                std::fs::remove_file(path).context(utils::FileDeletionError { file: path })?;
+            }
+        }
+        Step::Unlink { path } => {
+            let contents = std::fs::read(path).context(utils::FileReadError { file: path })?;
+            let metadata = deploy::metadata_of(path)?;
+
+            if !plan::record(Action::Remove { path: path.clone() }) {
+                std::fs::remove_file(path).context(utils::FileDeletionError { file: path })?;
+            }
+
+            deploy::deploy_contents(&contents, path, Some(&metadata))?;
+        }
+        Step::Uninstall { packages } => package::jog::unjog(state, packages)?,
+        Step::RemoveDir { dir } => {
+            if !plan::record(Action::Remove { path: dir.clone() }) {
+                std::fs::remove_dir_all(dir).context(utils::DirDeletionError { dir })?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,18 +1,19 @@
 use crate::manifest::State;
+use crate::plan;
 use crate::utils;
 use clap::ArgMatches;
 use log::warn;
 use snafu::ResultExt;
 use std::path::PathBuf;
 
-pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), utils::CommonErrors> {
+pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
     let files = matches.values_of("files").unwrap();
 
     for file in files.into_iter() {
         rm_file(file, state)?;
     }
 
-    state.data.populate_file(state);
+    state.data.populate_file(state)?;
 
     Ok(())
 }
@@ -20,7 +21,7 @@ pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), utils::CommonEr
 fn rm_file(file: &str, state: &mut State) -> Result<(), utils::CommonErrors> {
     // We cannot canonicalize path if it doesn't exist, so we create it temporalily.
     let mut must_delete_tmp = false;
-    if !PathBuf::from(file).exists() {
+    if !PathBuf::from(file).exists() && !plan::is_dry_run() {
         std::fs::File::create(file).context(utils::FileCreationError { file })?;
         must_delete_tmp = true;
     }
@@ -38,7 +38,7 @@ pub fn assign(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std
         }
     }
 
-    state.data.populate_file(&state);
+    state.data.populate_file(&state)?;
 
     Ok(())
 }
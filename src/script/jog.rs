@@ -1,16 +1,53 @@
+use crate::backup;
+use crate::condition;
 use crate::manifest::State;
+use crate::plan::{self, Action};
+use crate::pull::transaction;
+use crate::tag::selector::{self, Selector};
 use crate::utils;
+use clap::ArgMatches;
 use log::debug;
 use snafu::ResultExt;
 use std::fs::File;
 use std::io::Write;
 
-pub fn jog(state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+/// Run the scripts of the files picked by --only-with-tag, or of every
+/// file without it.
+pub fn jog_selected(
+    matches: &ArgMatches,
+    state: &mut State,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let selector = selector::parse(matches.value_of("only-with-tag"))?;
+
+    jog(state, selector.as_ref())
+}
+
+/// Run the scripts of every file, or of the files a selector picks.
+pub fn jog(state: &mut State, selector: Option<&Selector>) -> Result<(), Box<dyn std::error::Error>> {
     if let Some(files) = &state.data.files {
         for file in files {
+            if !selector.map_or(true, |s| s.matches(&file.tags)) {
+                continue;
+            }
+
+            if !condition::is_active(&file.when) {
+                debug!(
+                    "Great file {} is not for this machine, not running its scripts....",
+                    file.path.display()
+                );
+                continue;
+            }
+
             if let Some(scripts) = &file.scripts {
                 for script in scripts {
                     let abs = utils::special_to_absolute(&file.path);
+                    if plan::record(Action::RunScript {
+                        script: utils::special_to_absolute(script),
+                        file: abs.clone(),
+                    }) {
+                        continue;
+                    }
+
                     let processed = state
                         .script_state
                         .script_on_file(&abs, &utils::special_to_absolute(script))?;
@@ -18,6 +55,11 @@ pub fn jog(state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
                     debug!("Writting processed file:\n\n{}", processed);
                     debug!("\nEnd of writting processed file!");
 
+                    // The file is replaced, so it can be rolled back and
+                    // restored
+                    transaction::touch(&abs).context(utils::FileWriteError { file: &abs })?;
+                    backup::backup_file(&abs)?;
+
                     File::create(&abs)
                         .context(utils::FileOpenError { file: &abs })?
                         .write_all(processed.as_bytes())
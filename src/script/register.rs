@@ -1,4 +1,5 @@
 use super::ScriptsState;
+use crate::secret;
 use log::{error, info, warn};
 
 impl ScriptsState {
@@ -44,6 +45,17 @@ impl ScriptsState {
                         .unwrap(),
                 )
                 .unwrap();
+
+            globals
+                .set(
+                    "secret",
+                    lua_ctx
+                        .create_function(|_, name: String| {
+                            secret::get(&name).map_err(rlua::Error::external)
+                        })
+                        .unwrap(),
+                )
+                .unwrap();
         });
     }
 }
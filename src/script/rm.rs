@@ -32,7 +32,7 @@ pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::er
         }
     }
 
-    state.data.populate_file(&state);
+    state.data.populate_file(&state)?;
 
     Ok(())
 }
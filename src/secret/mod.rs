@@ -0,0 +1,247 @@
+//! Secrets from password managers. A secret is fetched by running the
+//! configured provider whenever a template is rendered, or a script
+//! asks for it, so that it never lands in the repository.
+
+use lazy_static::lazy_static;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Mutex;
+
+/// What the name of a secret is replaced with in a custom command.
+const PLACEHOLDER: &str = "{}";
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum SecretError {
+    #[snafu(display("No great secret provider is set! Set `secrets` in greatness.yaml to get secret {}.", name))]
+    NoProvider { name: String, source: std::io::Error },
+
+    #[snafu(display("Great secret provider `command` needs a command to run!"))]
+    NoCommand { source: std::io::Error },
+
+    #[snafu(display("Great secret provider command {} can't be split into words: {}", command, source))]
+    InvalidCommand {
+        command: String,
+        source: shell_words::ParseError,
+    },
+
+    #[snafu(display("Failed to run great secret provider {} for secret {}: {}", command, name, source))]
+    ProviderError {
+        command: String,
+        name: String,
+        source: subprocess::PopenError,
+    },
+
+    #[snafu(display("Great secret provider {} failed to get secret {}!", command, name))]
+    ProviderFailed {
+        command: String,
+        name: String,
+        source: std::io::Error,
+    },
+}
+
+/// Password managers secrets can be taken from.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// pass, the standard unix password manager. The secret is the
+    /// first line of the entry.
+    Pass,
+    /// gopass. The secret is the password of the entry.
+    Gopass,
+    /// The Bitwarden CLI. The secret is the password of the item.
+    #[serde(alias = "bitwarden")]
+    Bw,
+    /// The 1Password CLI. The name is a secret reference, such as
+    /// `op://vault/item/field`.
+    #[serde(alias = "1password")]
+    Op,
+    /// Any command that prints the secret. See `SecretProvider::command`.
+    Command,
+}
+
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pass => write!(f, "pass"),
+            Self::Gopass => write!(f, "gopass"),
+            Self::Bw => write!(f, "bw"),
+            Self::Op => write!(f, "op"),
+            Self::Command => write!(f, "command"),
+        }
+    }
+}
+
+/// Where secrets come from, under `secrets` in greatness.yaml.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct SecretProvider {
+    pub provider: Provider,
+    /// The command of the `command` provider. `{}` is replaced with the
+    /// name of the secret, which is appended if there is no `{}`. It is
+    /// split into words like a shell would, but isn't run by one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+impl SecretProvider {
+    /// Gets the command (and its arguments) that prints a secret.
+    fn command_for(&self, name: &str) -> Result<Vec<String>, SecretError> {
+        let builtin = |words: &[&str]| words.iter().map(|w| w.to_string()).collect();
+        let mut words: Vec<String> = match self.provider {
+            Provider::Pass => builtin(&["pass", "show", PLACEHOLDER]),
+            Provider::Gopass => builtin(&["gopass", "show", "--password", PLACEHOLDER]),
+            Provider::Bw => builtin(&["bw", "get", "password", PLACEHOLDER]),
+            Provider::Op => builtin(&["op", "read", PLACEHOLDER]),
+            Provider::Command => match &self.command {
+                Some(command) => shell_words::split(command).context(InvalidCommand { command })?,
+                None => vec![],
+            },
+        };
+        if words.is_empty() {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput)).context(NoCommand {});
+        }
+
+        if !words.iter().any(|w| w.contains(PLACEHOLDER)) {
+            words.push(PLACEHOLDER.to_owned());
+        }
+
+        Ok(words.iter().map(|w| w.replace(PLACEHOLDER, name)).collect())
+    }
+}
+
+struct Secrets {
+    provider: Option<SecretProvider>,
+    /// Secrets already fetched this run, so that each is only asked
+    /// for (and maybe unlocked) once.
+    fetched: HashMap<String, String>,
+}
+
+lazy_static! {
+    static ref SECRETS: Mutex<Secrets> = Mutex::new(Secrets {
+        provider: None,
+        fetched: HashMap::new(),
+    });
+}
+
+/// Set where secrets come from.
+pub fn use_provider(provider: Option<SecretProvider>) {
+    SECRETS.lock().unwrap().provider = provider;
+}
+
+/// Gets a secret by name from the provider. Trailing newlines are
+/// stripped, and for pass only the first line is kept.
+pub fn get(name: &str) -> Result<String, SecretError> {
+    let mut secrets = SECRETS.lock().unwrap();
+    if let Some(secret) = secrets.fetched.get(name) {
+        return Ok(secret.clone());
+    }
+
+    let provider = match &secrets.provider {
+        Some(p) => p.clone(),
+        None => {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                .context(NoProvider { name })
+        }
+    };
+
+    let words = provider.command_for(name)?;
+    let command = words[0].clone();
+    debug!("Getting great secret {} with {}....", name, provider.provider);
+
+    let mut process = subprocess::Exec::cmd(&command)
+        .args(&words[1..])
+        .stdout(subprocess::Redirection::Pipe)
+        .popen()
+        .context(ProviderError { command: &command, name })?;
+
+    let mut output = String::new();
+    if let Some(stdout) = &mut process.stdout {
+        stdout
+            .read_to_string(&mut output)
+            .context(ProviderFailed { command: &command, name })?;
+    }
+
+    let status = process
+        .wait()
+        .context(ProviderError { command: &command, name })?;
+    if !status.success() {
+        return Err(std::io::Error::from(std::io::ErrorKind::Other))
+            .context(ProviderFailed { command: &command, name });
+    }
+
+    let secret = secret_of(provider.provider, &output);
+    secrets.fetched.insert(name.to_owned(), secret.clone());
+
+    Ok(secret)
+}
+
+/// Gets the secret out of what a provider printed.
+fn secret_of(provider: Provider, output: &str) -> String {
+    match provider {
+        Provider::Pass => output.lines().next().unwrap_or("").to_owned(),
+        _ => output.trim_end_matches(&['\r', '\n'][..]).to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn command(command: &str) -> SecretProvider {
+        SecretProvider {
+            provider: Provider::Command,
+            command: Some(command.to_owned()),
+        }
+    }
+
+    #[test]
+    fn names_replace_the_placeholder() {
+        assert_eq!(
+            command("lookup --name={} 'two words'")
+                .command_for("github")
+                .unwrap(),
+            vec!["lookup", "--name=github", "two words"]
+        );
+        assert_eq!(
+            command("lookup").command_for("github").unwrap(),
+            vec!["lookup", "github"]
+        );
+        assert!(command("lookup 'unfinished").command_for("github").is_err());
+        assert!(command("").command_for("github").is_err());
+    }
+
+    #[test]
+    fn pass_keeps_only_the_first_line() {
+        assert_eq!(
+            secret_of(Provider::Pass, "hunter2\nuser: milo\n"),
+            "hunter2"
+        );
+        assert_eq!(
+            secret_of(Provider::Command, "hunter2\nuser\r\n\n"),
+            "hunter2\nuser"
+        );
+    }
+
+    #[test]
+    fn secrets_come_from_the_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let stub = dir.path().join("stub");
+        std::fs::write(
+            &stub,
+            "#!/bin/sh\n[ \"$2\" = missing ] && exit 1\nprintf 'secret of %s\\n\\n' \"$2\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        use_provider(Some(command(&format!("'{}' --get {{}}", stub.display()))));
+        assert_eq!(get("github").unwrap(), "secret of github");
+        assert!(matches!(
+            get("missing"),
+            Err(SecretError::ProviderFailed { .. })
+        ));
+    }
+}
@@ -1,4 +1,7 @@
-use crate::manifest::State;
+use crate::manifest::{Condition, DeployMode, State};
+use crate::pack;
+use crate::paths;
+use crate::pull::lock::Lock;
 use crate::utils;
 use log::info;
 
@@ -23,6 +26,40 @@ pub fn print_status(state: &State) {
         "Greatness scripts  : \x1b[1m{}\x1b[0m",
         state.greatness_scripts_dir.display()
     );
+    info!(
+        "Greatness variables: \x1b[1m{}\x1b[0m",
+        state.greatness_variables.display()
+    );
+    info!(
+        "Greatness merge bases: \x1b[1m{}\x1b[0m",
+        state.greatness_merge_bases_dir.display()
+    );
+    info!(
+        "Greatness backups  : \x1b[1m{}\x1b[0m",
+        state.greatness_backups_dir.display()
+    );
+    info!(
+        "Greatness key      : \x1b[1m{}\x1b[0m",
+        state.greatness_key.display()
+    );
+    info!(
+        "Greatness lock     : \x1b[1m{}\x1b[0m",
+        state.greatness_lock.display()
+    );
+
+    if let Some(secrets) = &state.data.secrets {
+        info!(
+            "Secret provider    : \x1b[1m{}\x1b[0m",
+            secrets.command.as_deref().unwrap_or(&secrets.provider.to_string())
+        );
+    }
+
+    print!("\n");
+
+    info!("Path variables:");
+    for (name, dir) in paths::all() {
+        info!("\t{}: {}", paths::placeholder(&name), dir.display());
+    }
 
     print!("\n");
 
@@ -31,6 +68,7 @@ pub fn print_status(state: &State) {
 
         for package in packages {
             info!("\tname: {}", package.package);
+            print_condition(&package.when);
 
             if package.package_overloads.len() != 0 {
                 info!("\t\toverlods:");
@@ -51,8 +89,33 @@ pub fn print_status(state: &State) {
                 utils::special_to_absolute(&file.path).display()
             );
 
-            if file.tag.is_some() && file.tag != Some("".to_owned()) {
-                info!("\t\ttag: {}", file.tag.clone().unwrap());
+            if !file.tags.is_empty() {
+                info!("\t\ttags: {}", file.tags.join(", "));
+            }
+
+            print_condition(&file.when);
+
+            if file.template {
+                info!("\t\ttemplate: yes");
+            }
+
+            if file.encrypted {
+                info!("\t\tencrypted: yes");
+            }
+
+            if file.deploy != DeployMode::Copy {
+                info!("\t\tdeploy: {}", file.deploy);
+            }
+
+            let drifted = pack::drifted(file);
+            if !drifted.is_empty() {
+                info!("\t\t\x1b[1mchanged since packing\x1b[0m:");
+                for drifted_file in drifted {
+                    info!(
+                        "\t\t\t{}",
+                        utils::special_to_absolute(&drifted_file).display()
+                    );
+                }
             }
 
             if file.scripts.is_some() {
@@ -69,14 +132,39 @@ pub fn print_status(state: &State) {
     if let Some(requires) = &state.data.requires {
         info!("\nExternal repositories of dotfiless:");
 
+        let lock = Lock::read(state).unwrap_or_default();
         for required in requires {
-            info!("\tat : {}", required.1.display());
+            info!("\tname: {}", required.name);
+            info!("\t\turl: {}", required.url);
 
-            if required.0.is_some() {
-                info!("\turl: {}", required.0.clone().unwrap());
+            if let Some(pin) = &required.pin {
+                info!("\t\tpin: {}", pin);
+            }
+
+            if let Some(tag) = &required.tag {
+                info!("\t\tonly with tags: {}", tag);
+            }
+
+            if let Some(commit) = lock.commit_of(&required.name, &required.url) {
+                info!("\t\tlocked: {}", commit);
             }
         }
     } else {
         info!("\x1b[1mNo external repositories installed!\x1b[0m");
     }
 }
+
+/// Prints the condition of an entry, and if it is active here.
+fn print_condition(when: &Option<Condition>) {
+    if let Some(when) = when {
+        info!("\t\twhen: {}", when);
+        info!(
+            "\t\tactive: {}",
+            if when.is_met() {
+                "yes"
+            } else {
+                "\x1b[1mno\x1b[0m, not for this machine"
+            }
+        );
+    }
+}
@@ -1,3 +1,5 @@
+pub mod selector;
+
 use crate::manifest::State;
 use crate::utils;
 use clap::ArgMatches;
@@ -11,18 +13,36 @@ pub enum TagError {
         source
     ))]
     NoTrackedFileExistance { source: std::io::Error },
+
+    #[snafu(display(
+        "Great tag {} can't have spaces or any of ,&!() in it, as selectors use them!",
+        tag
+    ))]
+    InvalidTag { tag: String, source: std::io::Error },
 }
 
+/// Tag files, or untag them. Files can have many tags.
 pub fn tag(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let tag = matches.value_of("tag").unwrap();
+    if tag.is_empty()
+        || tag
+            .chars()
+            .any(|c| c.is_whitespace() || ",&!()".contains(c))
+    {
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+            .context(InvalidTag { tag })?;
+    }
+
     for file in matches.values_of("files").unwrap() {
         tag_file(
             PathBuf::from(file),
-            matches.value_of("tag").unwrap().to_string(),
+            tag.to_string(),
+            matches.is_present("remove"),
             state,
         )?;
     }
 
-    state.data.populate_file(state);
+    state.data.populate_file(state)?;
 
     Ok(())
 }
@@ -30,6 +50,7 @@ pub fn tag(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::e
 pub fn tag_file(
     file: PathBuf,
     tag: String,
+    remove: bool,
     state: &mut State,
 ) -> Result<(), Box<dyn std::error::Error>> {
     if !file.as_path().exists() {
@@ -52,7 +73,11 @@ pub fn tag_file(
         }
     };
 
-    contains.tag = Some(tag);
+    if remove {
+        contains.tags.retain(|t| t != &tag);
+    } else if !contains.tags.contains(&tag) {
+        contains.tags.push(tag);
+    }
 
     state.data.add_file(contains);
 
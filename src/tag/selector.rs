@@ -0,0 +1,248 @@
+//! Tag selectors pick files by their tags. A selector is a tag, such as
+//! `shell`, combined with others:
+//! * `shell,editor` - Files tagged as either.
+//! * `desktop&linux` - Files tagged as both.
+//! * `!work` - Files not tagged as work, including untagged files.
+//!
+//! `&` binds tighter than `,`, and brackets group, so
+//! `!(work,school)&shell` is shell files that are for neither.
+
+use snafu::{ResultExt, Snafu};
+use std::str::FromStr;
+
+#[derive(Debug, Snafu)]
+pub enum SelectorError {
+    #[snafu(display("Invalid great tag selector {}: {}", selector, reason))]
+    InvalidSelector {
+        selector: String,
+        reason: String,
+        source: std::io::Error,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Selector {
+    Tag(String),
+    Not(Box<Selector>),
+    /// Every one of them matches.
+    All(Vec<Selector>),
+    /// Any one of them matches.
+    Any(Vec<Selector>),
+}
+
+impl Selector {
+    /// Does a file with these tags match?
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            Self::Tag(tag) => tags.contains(tag),
+            Self::Not(selector) => !selector.matches(tags),
+            Self::All(selectors) => selectors.iter().all(|s| s.matches(tags)),
+            Self::Any(selectors) => selectors.iter().any(|s| s.matches(tags)),
+        }
+    }
+}
+
+/// Parse a selector, if there is one. Without one, everything is
+/// selected.
+pub fn parse(selector: Option<&str>) -> Result<Option<Selector>, SelectorError> {
+    selector.map(str::parse).transpose()
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s);
+        let mut parser = Parser { tokens, at: 0 };
+
+        let parsed = parser.any().and_then(|selector| match parser.peek() {
+            None => Ok(selector),
+            Some(token) => Err(format!("unexpected {}", token)),
+        });
+
+        match parsed {
+            Ok(selector) => Ok(selector),
+            Err(reason) => Err(std::io::Error::from(std::io::ErrorKind::InvalidInput))
+                .context(InvalidSelector {
+                    selector: s,
+                    reason,
+                }),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Tag(String),
+    Or,
+    And,
+    Not,
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tag(tag) => write!(f, "tag {}", tag),
+            Self::Or => write!(f, "','"),
+            Self::And => write!(f, "'&'"),
+            Self::Not => write!(f, "'!'"),
+            Self::Open => write!(f, "'('"),
+            Self::Close => write!(f, "')'"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut tag = String::new();
+
+    for c in s.chars() {
+        let token = match c {
+            ',' => Some(Token::Or),
+            '&' => Some(Token::And),
+            '!' => Some(Token::Not),
+            '(' => Some(Token::Open),
+            ')' => Some(Token::Close),
+            c if c.is_whitespace() => None,
+            c => {
+                tag.push(c);
+                continue;
+            }
+        };
+
+        if !tag.is_empty() {
+            tokens.push(Token::Tag(std::mem::take(&mut tag)));
+        }
+        tokens.extend(token);
+    }
+
+    if !tag.is_empty() {
+        tokens.push(Token::Tag(tag));
+    }
+
+    tokens
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.at)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.at += 1;
+        self.tokens.get(self.at - 1)
+    }
+
+    /// `all (',' all)*`
+    fn any(&mut self) -> Result<Selector, String> {
+        let mut selectors = vec![self.all()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            selectors.push(self.all()?);
+        }
+
+        Ok(match selectors.len() {
+            1 => selectors.remove(0),
+            _ => Selector::Any(selectors),
+        })
+    }
+
+    /// `not ('&' not)*`
+    fn all(&mut self) -> Result<Selector, String> {
+        let mut selectors = vec![self.not()?];
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            selectors.push(self.not()?);
+        }
+
+        Ok(match selectors.len() {
+            1 => selectors.remove(0),
+            _ => Selector::All(selectors),
+        })
+    }
+
+    /// `'!' not | '(' any ')' | tag`
+    fn not(&mut self) -> Result<Selector, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Selector::Not(Box::new(self.not()?))),
+            Some(Token::Open) => {
+                let selector = self.any()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(selector),
+                    Some(token) => Err(format!("expected ')', found {}", token)),
+                    None => Err("expected ')'".to_owned()),
+                }
+            }
+            Some(Token::Tag(tag)) => Ok(Selector::Tag(tag.clone())),
+            Some(token) => Err(format!("expected a tag, found {}", token)),
+            None => Err("expected a tag".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(tag: &str) -> Selector {
+        Selector::Tag(tag.to_owned())
+    }
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_and_or() {
+        assert_eq!(
+            "!(work,school)&shell".parse::<Selector>().unwrap(),
+            Selector::All(vec![
+                Selector::Not(Box::new(Selector::Any(vec![tag("work"), tag("school")]))),
+                tag("shell"),
+            ])
+        );
+        assert_eq!(
+            "a,b&c".parse::<Selector>().unwrap(),
+            Selector::Any(vec![tag("a"), Selector::All(vec![tag("b"), tag("c")])])
+        );
+        assert_eq!(
+            "!a&b".parse::<Selector>().unwrap(),
+            Selector::All(vec![Selector::Not(Box::new(tag("a"))), tag("b")])
+        );
+    }
+
+    #[test]
+    fn selectors_match_tags() {
+        let selector = "!(work,school)&shell".parse::<Selector>().unwrap();
+
+        assert!(selector.matches(&tags(&["shell"])));
+        assert!(selector.matches(&tags(&["shell", "linux"])));
+        assert!(!selector.matches(&tags(&["shell", "work"])));
+        assert!(!selector.matches(&tags(&[])));
+        assert!("!work".parse::<Selector>().unwrap().matches(&tags(&[])));
+    }
+
+    #[test]
+    fn invalid_selectors_are_refused() {
+        for invalid in &["shell,", "(work,school", "work)", "&shell", "!", ""] {
+            assert!(
+                invalid.parse::<Selector>().is_err(),
+                "{} should be invalid",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn no_selector_selects_everything() {
+        assert_eq!(parse(None).unwrap(), None);
+        assert_eq!(parse(Some("shell")).unwrap(), Some(tag("shell")));
+    }
+}
@@ -0,0 +1,49 @@
+use crate::manifest::{DeployMode, State};
+use crate::utils;
+use clap::ArgMatches;
+use log::{info, warn};
+use snafu::ResultExt;
+use std::path::PathBuf;
+
+/// Marks (a) file(s) as templates. Their packed copies are the
+/// template, which is rendered whenever the file is installed.
+pub fn add(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let mut files_dir = state.greatness_git_pack_dir.clone();
+    files_dir.push("files");
+
+    for file in matches.values_of("files").unwrap() {
+        let file = PathBuf::from(file);
+        let normalized_file = utils::relative_to_special(&file)?;
+        let mut contains = match state.data.contains(&normalized_file) {
+            Some(c) => c.0.clone(),
+            None => {
+                return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                    .context(utils::FileNotTracked { file: &file })?;
+            }
+        };
+
+        // A rendered file differs from its template, so it can't be linked
+        if contains.deploy != DeployMode::Copy {
+            warn!(
+                "Great template {} will be copied instead of {}ed!",
+                file.display(),
+                contains.deploy
+            );
+            contains.deploy = DeployMode::Copy;
+        }
+
+        contains.template = true;
+
+        info!(
+            "Great template {} is at {}, once packed. Edit it there!",
+            file.display(),
+            utils::special_in_dir(&files_dir, &normalized_file).display()
+        );
+
+        state.data.add_file(contains);
+    }
+
+    state.data.populate_file(state)?;
+
+    Ok(())
+}
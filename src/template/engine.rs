@@ -0,0 +1,612 @@
+//! A small template language, in the spirit of Go's text/template.
+//!
+//! ```text
+//! {{ .name }}                     a variable
+//! {{ .great.os }}                 a field of a variable
+//! {{ if eq .great.os "linux" }}   blocks, with `else if` and `else`
+//! {{ range .servers }}{{ . }}{{ end }}
+//! {{- trim -}}                    trim whitespace before/after
+//! {{/* a comment */}}
+//! ```
+//!
+//! Inside of `range`, `.` is the current element, and `$` is always
+//! the variables the template was rendered with.
+
+use serde_yaml::Value;
+
+/// A piece of a parsed template.
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Print(Expr, usize),
+    /// Each branch has its condition, and the line it is on.
+    If(Vec<(Expr, Vec<Node>, usize)>, Vec<Node>),
+    Range(Expr, Vec<Node>, Vec<Node>, usize),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    /// A field path. `root` is set for `$.a.b`, otherwise it is
+    /// looked up on the current `.`.
+    Field { root: bool, path: Vec<String> },
+    Literal(Value),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Field { root: bool, path: Vec<String> },
+    Str(String),
+    Number(String),
+    Ident(String),
+    Open,
+    Close,
+}
+
+/// A lexed piece of a template; either text, or what's in an action.
+enum Item {
+    Text(String),
+    Action(Vec<Token>, usize),
+}
+
+/// Extra functions a template can call, by name. Given the evaluated
+/// arguments, returns `None` if the function doesn't exist.
+pub type Functions<'a> = &'a dyn Fn(&str, &[Value]) -> Option<Result<Value, String>>;
+
+/// Renders a template with some variables.
+pub fn render(template: &str, variables: &Value, functions: Functions) -> Result<String, String> {
+    let items = lex(template)?;
+    let mut pos = 0;
+    let (nodes, end) = parse(&items, &mut pos)?;
+
+    if let Some((keyword, line)) = end {
+        return Err(format!("line {}: unexpected {{{{ {} }}}}", line, keyword));
+    }
+
+    let mut out = String::new();
+    let context = Context {
+        root: variables,
+        functions,
+    };
+    context.render_nodes(&nodes, variables, &mut out)?;
+
+    Ok(out)
+}
+
+fn lex(template: &str) -> Result<Vec<Item>, String> {
+    let mut items = vec![];
+    let mut rest = template;
+    let mut line = 1;
+    let mut trim_next = false;
+
+    while let Some(start) = rest.find("{{") {
+        let mut text = &rest[..start];
+        if trim_next {
+            text = text.trim_start();
+        }
+
+        let after = &rest[start + 2..];
+        let trim_before = after.starts_with('-');
+        if trim_before {
+            text = text.trim_end();
+        }
+
+        items.push(Item::Text(text.to_owned()));
+        line += rest[..start].matches('\n').count();
+
+        let body_start = if trim_before { 1 } else { 0 };
+        let end = find_action_end(&after[body_start..])
+            .ok_or_else(|| format!("line {}: unclosed action", line))?
+            + body_start;
+
+        let mut body = &after[body_start..end];
+        trim_next = body.ends_with('-');
+        if trim_next {
+            body = &body[..body.len() - 1];
+        }
+
+        let trimmed = body.trim();
+        if !(trimmed.starts_with("/*") && trimmed.ends_with("*/")) {
+            items.push(Item::Action(tokenize(trimmed, line)?, line));
+        }
+
+        line += after[..end].matches('\n').count();
+        rest = &after[end + 2..];
+    }
+
+    let text = if trim_next { rest.trim_start() } else { rest };
+    items.push(Item::Text(text.to_owned()));
+
+    Ok(items)
+}
+
+/// Finds the `}}` closing an action, ignoring any inside of strings.
+fn find_action_end(s: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let mut in_string = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if in_string => i += 1,
+            b'"' => in_string = !in_string,
+            b'}' if !in_string && bytes.get(i + 1) == Some(&b'}') => return Some(i),
+            _ => (),
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+fn tokenize(action: &str, line: usize) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = action.chars().collect();
+    let mut i = 0;
+
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::Open);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            i += 1;
+        } else if c == '.' || c == '$' {
+            let root = c == '$';
+            if root {
+                i += 1;
+            }
+
+            let mut path = vec![];
+            while i < chars.len() && chars[i] == '.' {
+                i += 1;
+                let start = i;
+                while i < chars.len() && is_ident(chars[i]) {
+                    i += 1;
+                }
+
+                if i > start {
+                    path.push(chars[start..i].iter().collect());
+                }
+            }
+
+            tokens.push(Token::Field { root, path });
+        } else if c == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("line {}: unclosed string", line)),
+                    Some('"') => break,
+                    Some('\\') => {
+                        i += 1;
+                        match chars.get(i) {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(c) => s.push(*c),
+                            None => return Err(format!("line {}: unclosed string", line)),
+                        }
+                    }
+                    Some(c) => s.push(*c),
+                }
+                i += 1;
+            }
+            i += 1;
+
+            tokens.push(Token::Str(s));
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).map_or(false, |c| c.is_ascii_digit())) {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if is_ident(c) {
+            let start = i;
+            while i < chars.len() && is_ident(chars[i]) {
+                i += 1;
+            }
+
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            return Err(format!("line {}: unexpected character {:?}", line, c));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses nodes until the end of the template, or an `else`/`end`,
+/// which is returned (with its line) for the caller to deal with.
+fn parse(
+    items: &[Item],
+    pos: &mut usize,
+) -> Result<(Vec<Node>, Option<(String, usize)>), String> {
+    let mut nodes = vec![];
+
+    while *pos < items.len() {
+        let item = &items[*pos];
+        *pos += 1;
+
+        let (tokens, line) = match item {
+            Item::Text(text) => {
+                if !text.is_empty() {
+                    nodes.push(Node::Text(text.clone()));
+                }
+                continue;
+            }
+            Item::Action(tokens, line) => (tokens, *line),
+        };
+
+        match tokens.first() {
+            None => return Err(format!("line {}: empty action", line)),
+
+            Some(Token::Ident(keyword)) if keyword == "end" => {
+                return Ok((nodes, Some(("end".to_owned(), line))));
+            }
+
+            Some(Token::Ident(keyword)) if keyword == "else" => {
+                let keyword = match tokens.get(1) {
+                    Some(Token::Ident(i)) if i == "if" => "else if",
+                    _ => "else",
+                };
+
+                // Step back, so the block parser can see the condition
+                *pos -= 1;
+                return Ok((nodes, Some((keyword.to_owned(), line))));
+            }
+
+            Some(Token::Ident(keyword)) if keyword == "if" => {
+                let mut branches = vec![];
+                let mut otherwise = vec![];
+                let mut condition = parse_expr(&tokens[1..], line)?;
+                let mut condition_line = line;
+
+                loop {
+                    let (body, end) = parse(items, pos)?;
+                    match end {
+                        Some((keyword, _)) if keyword == "end" => {
+                            branches.push((condition, body, condition_line));
+                            break;
+                        }
+                        Some((keyword, else_line)) if keyword == "else if" => {
+                            branches.push((condition, body, condition_line));
+                            let tokens = action_tokens(&items[*pos]);
+                            *pos += 1;
+                            condition = parse_expr(&tokens[2..], else_line)?;
+                            condition_line = else_line;
+                        }
+                        Some((keyword, _)) if keyword == "else" => {
+                            branches.push((condition, body, condition_line));
+                            *pos += 1;
+                            let (body, end) = parse(items, pos)?;
+                            expect_end(end, line)?;
+                            otherwise = body;
+                            break;
+                        }
+                        _ => return Err(format!("line {}: {{{{ if }}}} without {{{{ end }}}}", line)),
+                    }
+                }
+
+                nodes.push(Node::If(branches, otherwise));
+            }
+
+            Some(Token::Ident(keyword)) if keyword == "range" => {
+                let list = parse_expr(&tokens[1..], line)?;
+                let (body, end) = parse(items, pos)?;
+                let mut otherwise = vec![];
+
+                match end.as_ref().map(|(k, _)| k.as_str()) {
+                    Some("end") => (),
+                    Some("else") => {
+                        *pos += 1;
+                        let (body, end) = parse(items, pos)?;
+                        expect_end(end, line)?;
+                        otherwise = body;
+                    }
+                    _ => return Err(format!("line {}: {{{{ range }}}} without {{{{ end }}}}", line)),
+                }
+
+                nodes.push(Node::Range(list, body, otherwise, line));
+            }
+
+            _ => nodes.push(Node::Print(parse_expr(tokens, line)?, line)),
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+fn action_tokens(item: &Item) -> Vec<Token> {
+    match item {
+        Item::Action(tokens, _) => tokens.clone(),
+        Item::Text(_) => vec![],
+    }
+}
+
+fn expect_end(end: Option<(String, usize)>, line: usize) -> Result<(), String> {
+    match end {
+        Some((keyword, _)) if keyword == "end" => Ok(()),
+        Some((keyword, line)) => Err(format!("line {}: unexpected {{{{ {} }}}}", line, keyword)),
+        None => Err(format!("line {}: block without {{{{ end }}}}", line)),
+    }
+}
+
+fn parse_expr(tokens: &[Token], line: usize) -> Result<Expr, String> {
+    match tokens.first() {
+        None => Err(format!("line {}: missing expression", line)),
+
+        Some(Token::Ident(name)) if name != "true" && name != "false" => {
+            let mut args = vec![];
+            let mut i = 1;
+            while i < tokens.len() {
+                let (arg, used) = parse_term(&tokens[i..], line)?;
+                args.push(arg);
+                i += used;
+            }
+
+            Ok(Expr::Call(name.clone(), args))
+        }
+
+        _ => {
+            let (term, used) = parse_term(tokens, line)?;
+            if used != tokens.len() {
+                return Err(format!("line {}: unexpected {:?}", line, tokens[used]));
+            }
+
+            Ok(term)
+        }
+    }
+}
+
+/// Parses a single term, returning it and how many tokens it used.
+fn parse_term(tokens: &[Token], line: usize) -> Result<(Expr, usize), String> {
+    match &tokens[0] {
+        Token::Field { root, path } => Ok((
+            Expr::Field {
+                root: *root,
+                path: path.clone(),
+            },
+            1,
+        )),
+        Token::Str(s) => Ok((Expr::Literal(Value::String(s.clone())), 1)),
+        Token::Number(n) => {
+            let value = serde_yaml::from_str(n).map_err(|_| format!("line {}: bad number {}", line, n))?;
+            Ok((Expr::Literal(value), 1))
+        }
+        Token::Ident(b) if b == "true" || b == "false" => Ok((Expr::Literal(Value::Bool(b == "true")), 1)),
+        Token::Ident(name) => Ok((Expr::Call(name.clone(), vec![]), 1)),
+        Token::Open => {
+            let mut depth = 0;
+            for (i, token) in tokens.iter().enumerate() {
+                match token {
+                    Token::Open => depth += 1,
+                    Token::Close => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok((parse_expr(&tokens[1..i], line)?, i + 1));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            Err(format!("line {}: unclosed parenthesis", line))
+        }
+        Token::Close => Err(format!("line {}: unexpected )", line)),
+    }
+}
+
+struct Context<'a> {
+    root: &'a Value,
+    functions: Functions<'a>,
+}
+
+impl<'a> Context<'a> {
+    fn render_nodes(&self, nodes: &[Node], dot: &Value, out: &mut String) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+
+                Node::Print(expr, line) => {
+                    let value = self.eval(expr, dot, *line)?;
+                    out.push_str(&to_text(&value).map_err(|e| format!("line {}: {}", line, e))?);
+                }
+
+                Node::If(branches, otherwise) => {
+                    let mut taken = false;
+                    for (condition, body, line) in branches {
+                        if is_truthy(&self.eval(condition, dot, *line)?) {
+                            self.render_nodes(body, dot, out)?;
+                            taken = true;
+                            break;
+                        }
+                    }
+
+                    if !taken {
+                        self.render_nodes(otherwise, dot, out)?;
+                    }
+                }
+
+                Node::Range(list, body, otherwise, line) => {
+                    let items: Vec<Value> = match self.eval(list, dot, *line)? {
+                        Value::Sequence(items) => items,
+                        Value::Mapping(map) => map.into_iter().map(|(_, v)| v).collect(),
+                        Value::Null => vec![],
+                        other => {
+                            return Err(format!("line {}: can't range over {:?}", line, other))
+                        }
+                    };
+
+                    if items.is_empty() {
+                        self.render_nodes(otherwise, dot, out)?;
+                    }
+
+                    for item in &items {
+                        self.render_nodes(body, item, out)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, dot: &Value, line: usize) -> Result<Value, String> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+
+            Expr::Field { root, path } => {
+                let mut value = if *root { self.root } else { dot };
+                for name in path {
+                    value = match value.get(name.as_str()) {
+                        Some(v) => v,
+                        None => return Ok(Value::Null),
+                    };
+                }
+
+                Ok(value.clone())
+            }
+
+            Expr::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.eval(a, dot, line))
+                    .collect::<Result<Vec<Value>, String>>()?;
+
+                call(name, &args)
+                    .or_else(|| (self.functions)(name, &args))
+                    .unwrap_or_else(|| Err(format!("unknown function {}", name)))
+                    .map_err(|e| format!("line {}: {}", line, e))
+            }
+        }
+    }
+}
+
+/// The functions every template has.
+fn call(name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+    let arg = |i: usize| args.get(i).cloned().unwrap_or(Value::Null);
+
+    Some(Ok(match name {
+        "eq" => Value::Bool(args.len() > 1 && args[1..].iter().any(|a| *a == arg(0))),
+        "ne" => Value::Bool(arg(0) != arg(1)),
+        "not" => Value::Bool(!is_truthy(&arg(0))),
+        "and" => Value::Bool(args.iter().all(is_truthy)),
+        "or" => Value::Bool(args.iter().any(is_truthy)),
+        "default" => {
+            if is_truthy(&arg(1)) {
+                arg(1)
+            } else {
+                arg(0)
+            }
+        }
+        "env" => match arg(0).as_str().map(std::env::var) {
+            Some(Ok(value)) => Value::String(value),
+            _ => Value::String("".to_owned()),
+        },
+        "contains" => Value::Bool(match (&arg(0), &arg(1)) {
+            (Value::Sequence(items), item) => items.contains(item),
+            (Value::String(s), Value::String(sub)) => s.contains(sub.as_str()),
+            _ => false,
+        }),
+        "join" => match (&arg(0), arg(1).as_str()) {
+            (Value::Sequence(items), Some(sep)) => {
+                let texts = items
+                    .iter()
+                    .map(to_text)
+                    .collect::<Result<Vec<String>, String>>();
+                match texts {
+                    Ok(texts) => Value::String(texts.join(sep)),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            _ => return Some(Err("join takes a list and a separator".to_owned())),
+        },
+        "lower" => Value::String(arg(0).as_str().unwrap_or("").to_lowercase()),
+        "upper" => Value::String(arg(0).as_str().unwrap_or("").to_uppercase()),
+        _ => return None,
+    }))
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().map_or(true, |n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Sequence(s) => !s.is_empty(),
+        Value::Mapping(m) => !m.is_empty(),
+    }
+}
+
+fn to_text(value: &Value) -> Result<String, String> {
+    match value {
+        Value::Null => Ok("".to_owned()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::String(s) => Ok(s.clone()),
+        _ => Err("can't print a list or a mapping, use range or join".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_with(template: &str, variables: &str) -> Result<String, String> {
+        let variables: Value = serde_yaml::from_str(variables).unwrap();
+        render(template, &variables, &|_, _| None)
+    }
+
+    #[test]
+    fn else_if_chains_take_the_first_true_branch() {
+        let template = r#"{{ if eq .os "linux" }}tux{{ else if eq .os "darwin" }}apple{{ else if eq .os "windows" }}window{{ else }}?{{ end }}"#;
+
+        assert_eq!(render_with(template, "os: linux").unwrap(), "tux");
+        assert_eq!(render_with(template, "os: darwin").unwrap(), "apple");
+        assert_eq!(render_with(template, "os: windows").unwrap(), "window");
+        assert_eq!(render_with(template, "os: plan9").unwrap(), "?");
+    }
+
+    #[test]
+    fn range_else_is_for_nothing_to_range_over() {
+        let template = "{{ range .servers }}[{{ . }}]{{ else }}none{{ end }}";
+
+        assert_eq!(render_with(template, "servers: [a, b]").unwrap(), "[a][b]");
+        assert_eq!(render_with(template, "servers: []").unwrap(), "none");
+        assert_eq!(render_with(template, "{}").unwrap(), "none");
+    }
+
+    #[test]
+    fn dashes_trim_whitespace() {
+        assert_eq!(
+            render_with("a  \n  {{- .b -}}  \n  c", "b: B").unwrap(),
+            "aBc"
+        );
+        assert_eq!(render_with("a {{- .b }} c", "b: B").unwrap(), "aB c");
+        assert_eq!(render_with("a {{ .b -}} c", "b: B").unwrap(), "a Bc");
+    }
+
+    #[test]
+    fn unknown_functions_are_errors() {
+        let error = render_with("\n{{ shout .name }}", "name: milo").unwrap_err();
+
+        assert!(error.contains("line 2"), "{}", error);
+        assert!(error.contains("unknown function shout"), "{}", error);
+    }
+
+    #[test]
+    fn else_if_errors_are_on_their_own_line() {
+        let error = render_with("{{ if .a }}\n\n{{ else if ) }}{{ end }}", "a: 1").unwrap_err();
+
+        assert!(error.starts_with("line 3:"), "{}", error);
+    }
+}
@@ -0,0 +1,149 @@
+pub mod add;
+pub mod engine;
+pub mod render;
+pub mod rm;
+
+use crate::condition::FACTS;
+use crate::manifest::{Manifest, State};
+use crate::secret;
+use serde_yaml::{Mapping, Value};
+use snafu::{ResultExt, Snafu};
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+/// Errors pretaining to rendering templates
+pub enum TemplateError {
+    #[snafu(display("Failed to render great template {}: {}", file.display(), reason))]
+    RenderError {
+        file: PathBuf,
+        reason: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to read great template {}: {}", file.display(), source))]
+    TemplateReadError {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to read great variables file {}: {}", file.display(), source))]
+    VariablesReadError {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to parse great variables file {}: {}", file.display(), source))]
+    VariablesParseError {
+        file: PathBuf,
+        source: serde_yaml::Error,
+    },
+}
+
+/// Gets the variables templates are rendered with. Those in the
+/// manifest are overridden by those in this machine's variables
+/// file, and the facts of this machine are under `great`.
+pub fn variables(manifest: &Manifest, state: &State) -> Result<Value, TemplateError> {
+    let mut variables = Mapping::new();
+    for (name, value) in manifest.variables.clone().unwrap_or_default() {
+        variables.insert(Value::String(name), value);
+    }
+
+    let mut variables = Value::Mapping(variables);
+
+    if state.greatness_variables.exists() {
+        let contents = std::fs::read_to_string(&state.greatness_variables).context(
+            VariablesReadError {
+                file: &state.greatness_variables,
+            },
+        )?;
+        let overrides: Value = serde_yaml::from_str(&contents).context(VariablesParseError {
+            file: &state.greatness_variables,
+        })?;
+
+        merge(&mut variables, overrides);
+    }
+
+    if let Value::Mapping(variables) = &mut variables {
+        variables.insert(Value::String("great".to_owned()), facts());
+    }
+
+    Ok(variables)
+}
+
+/// Renders a template file.
+pub fn render_file(file: &PathBuf, variables: &Value) -> Result<String, TemplateError> {
+    let template =
+        std::fs::read_to_string(file).context(TemplateReadError { file })?;
+
+    render_str(&template, file, variables)
+}
+
+/// Renders a template that has already been read. `file` is only
+/// used for errors.
+pub fn render_str(template: &str, file: &PathBuf, variables: &Value) -> Result<String, TemplateError> {
+    engine::render(template, variables, &functions).or_else(|reason| {
+        Err(std::io::Error::from(std::io::ErrorKind::InvalidData))
+            .context(RenderError { file, reason })
+    })
+}
+
+/// The functions templates have on top of the engine's own.
+///
+/// `{{ secret "name" }}` - a secret from the secret provider.
+fn functions(name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+    match name {
+        "secret" => Some(match args.get(0).and_then(Value::as_str) {
+            Some(name) => secret::get(name)
+                .map(Value::String)
+                .map_err(|e| e.to_string()),
+            None => Err("secret takes the name of a secret".to_owned()),
+        }),
+        _ => None,
+    }
+}
+
+/// The facts of this machine, as template variables.
+fn facts() -> Value {
+    let mut facts = Mapping::new();
+    let mut insert = |name: &str, value: Value| {
+        facts.insert(Value::String(name.to_owned()), value);
+    };
+
+    insert("hostname", Value::String(FACTS.hostname.clone()));
+    insert("os", Value::String(FACTS.os.clone()));
+    insert(
+        "distro",
+        FACTS.distro.clone().map_or(Value::Null, Value::String),
+    );
+    insert(
+        "distro_like",
+        Value::Sequence(FACTS.distro_like.iter().cloned().map(Value::String).collect()),
+    );
+    insert("arch", Value::String(FACTS.arch.clone()));
+    insert("user", Value::String(FACTS.user.clone()));
+    insert(
+        "home",
+        Value::String(home::home_dir().unwrap().to_str().unwrap().to_owned()),
+    );
+
+    Value::Mapping(facts)
+}
+
+/// Merges `overrides` into `base`. Mappings are merged key by key,
+/// anything else is replaced.
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Mapping(base), Value::Mapping(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
@@ -0,0 +1,29 @@
+use crate::manifest::State;
+use crate::template;
+use crate::utils;
+use clap::ArgMatches;
+use snafu::ResultExt;
+use std::path::PathBuf;
+
+/// Prints what a packed template renders to on this machine,
+/// without installing it.
+pub fn render(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    let file = PathBuf::from(matches.value_of("file").unwrap());
+    let normalized_file = utils::relative_to_special(&file)?;
+
+    if state.data.contains(&normalized_file).is_none() {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+            .context(utils::FileNotTracked { file: &file })?;
+    }
+
+    let mut files_dir = state.greatness_git_pack_dir.clone();
+    files_dir.push("files");
+
+    let variables = template::variables(&state.data, state)?;
+    let rendered =
+        template::render_file(&utils::special_in_dir(&files_dir, &normalized_file), &variables)?;
+
+    print!("{}", rendered);
+
+    Ok(())
+}
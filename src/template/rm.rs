@@ -0,0 +1,29 @@
+use crate::manifest::State;
+use crate::utils;
+use clap::ArgMatches;
+use snafu::ResultExt;
+use std::path::PathBuf;
+
+/// Stops treating (a) file(s) as templates. The next pack replaces
+/// the template with the file as it is on the system.
+pub fn rm(matches: &ArgMatches, state: &mut State) -> Result<(), Box<dyn std::error::Error>> {
+    for file in matches.values_of("files").unwrap() {
+        let file = PathBuf::from(file);
+        let normalized_file = utils::relative_to_special(&file)?;
+        let mut contains = match state.data.contains(&normalized_file) {
+            Some(c) => c.0.clone(),
+            None => {
+                return Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+                    .context(utils::FileNotTracked { file: &file })?;
+            }
+        };
+
+        contains.template = false;
+
+        state.data.add_file(contains);
+    }
+
+    state.data.populate_file(state)?;
+
+    Ok(())
+}
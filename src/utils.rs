@@ -1,6 +1,9 @@
 use crate::manifest::State;
+use crate::paths;
+use sha2::{Digest, Sha256};
 use snafu::{ResultExt, Snafu};
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Component, PathBuf};
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub(crate)")]
@@ -73,55 +76,6 @@ pub enum CommonErrors {
     },
 }
 
-lazy_static! {
-    static ref REPLACE_DATA: std::collections::HashMap<String, String> = {
-        let replace: std::collections::HashMap<String, String>;
-        let home_to_replace = home::home_dir().unwrap();
-        let mut desktop_to_replace = home_to_replace.clone();
-        desktop_to_replace.push("/Desktops");
-        let mut documents_to_replace = home_to_replace.clone();
-        documents_to_replace.push("/Documents");
-        let mut downloads_to_replace = home_to_replace.clone();
-        downloads_to_replace.push("/Downloads");
-        let mut music_to_replace = home_to_replace.clone();
-        music_to_replace.push("/Music");
-        let mut pictures_to_replace = home_to_replace.clone();
-        pictures_to_replace.push("/Pictures");
-        let mut publicshare_to_replace = home_to_replace.clone();
-        publicshare_to_replace.push("/Public");
-        let mut templates_to_replace = home_to_replace.clone();
-        templates_to_replace.push("/Templates");
-        let mut video_to_replace = home_to_replace.clone();
-        video_to_replace.push("/Videos");
-
-        if std::env::var("XDG_PUBLICSHARE_DIR").unwrap_or("".to_owned()) != "" {
-            // XDG is installed
-            desktop_to_replace = PathBuf::from(std::env::var("XDG_DESKTOP_DIR").unwrap());
-            documents_to_replace = PathBuf::from(std::env::var("XDG_DOCUMENTS_DIR").unwrap());
-            downloads_to_replace = PathBuf::from(std::env::var("XDG_DOWNLOAD_DIR").unwrap());
-            music_to_replace = PathBuf::from(std::env::var("XDG_MUSIC_DIR").unwrap());
-            pictures_to_replace = PathBuf::from(std::env::var("XDG_PICTURES_DIR").unwrap());
-            publicshare_to_replace = PathBuf::from(std::env::var("XDG_PUBLICSHARE_DIR").unwrap());
-            templates_to_replace = PathBuf::from(std::env::var("XDG_TEMPLATES_DIR").unwrap());
-            video_to_replace = PathBuf::from(std::env::var("XDG_VIDEOS_DIR").unwrap());
-        }
-
-        replace = hashmap! {
-            "{{HOME}}".to_owned() => home_to_replace.to_str().unwrap().to_string(),
-            "{{DESKTOP}}".to_owned() => desktop_to_replace.to_str().unwrap().to_string(),
-            "{{DOCUMENTS}}".to_owned() => documents_to_replace.to_str().unwrap().to_string(),
-            "{{DOWNLOADS}}".to_owned() => downloads_to_replace.to_str().unwrap().to_string(),
-            "{{MUSIC}}".to_owned() => music_to_replace.to_str().unwrap().to_string(),
-            "{{PICTURES}}".to_owned() => pictures_to_replace.to_str().unwrap().to_string(),
-            "{{PUBLICSHARE}}".to_owned() => publicshare_to_replace.to_str().unwrap().to_string(),
-            "{{TEMPLATES}}".to_owned() => templates_to_replace.to_str().unwrap().to_string(),
-            "{{VIDEOS}}".to_owned() => video_to_replace.to_str().unwrap().to_string(),
-        };
-
-        replace
-    };
-}
-
 /// Transmute urls into something git can handle. For example:
 /// Pattern 	        HTTPS Repo
 /// user 	            https://github.com/user/dotfiles.git
@@ -150,28 +104,61 @@ pub fn make_url_valid(url: String) -> String {
 /// Transforms an absolute path to a special one.
 /// /home/milo/.zshrc -> {{HOME}}/.zshrc
 pub fn absolute_to_special(absolute: &PathBuf) -> PathBuf {
-    let mut ret = absolute.clone().to_str().unwrap().to_string();
-
-    for (k, v) in REPLACE_DATA.clone().into_iter() {
-        ret = ret.replace(k.as_str(), v.as_str());
-    }
-
-    PathBuf::from(ret)
+    paths::to_special(absolute)
 }
 
 /// Calls absolute_to_special, but calls .cannonicalize()
-/// on the relative path first.
+/// on the relative path first. Symlinks themselves are not
+/// followed, as they may be files we deployed.
 pub fn relative_to_special(relative: &PathBuf) -> Result<PathBuf, std::io::Error> {
+    let is_link = relative
+        .symlink_metadata()
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+
+    if let (true, Some(name)) = (is_link, relative.file_name()) {
+        let mut parent = relative.parent().unwrap_or(std::path::Path::new("")).to_path_buf();
+        if parent.as_os_str().is_empty() {
+            parent = PathBuf::from(".");
+        }
+
+        let mut absolute = parent.canonicalize()?;
+        absolute.push(name);
+
+        return Ok(absolute_to_special(&absolute));
+    }
+
     Ok(absolute_to_special(&relative.canonicalize()?))
 }
 
+/// Like `relative_to_special`, but for paths that may not exist on
+/// the system anymore.
+pub fn any_to_special(path: &PathBuf) -> Result<PathBuf, std::io::Error> {
+    if path.symlink_metadata().is_ok() {
+        return relative_to_special(path);
+    }
+
+    Ok(absolute_to_special(&std::env::current_dir()?.join(path)))
+}
+
 /// Transforms a special path to an absolute one.
 /// {{HOME}}/.zshrc -> /home/milo/.zshrc
 pub fn special_to_absolute(special: &PathBuf) -> PathBuf {
-    let home_to_substitute = home::home_dir().unwrap();
-    let special_string = special.to_str().unwrap().to_string();
+    paths::to_absolute(special)
+}
 
-    PathBuf::from(special_string.replace("{{HOME}}", home_to_substitute.to_str().unwrap()))
+/// Gets where a special path lives inside of a directory of a
+/// repository, such as `packed/git/files`.
+/// {{HOME}}/.zshrc -> <dir>/{{HOME}}/.zshrc
+pub fn special_in_dir(dir: &PathBuf, special: &PathBuf) -> PathBuf {
+    let mut ret = dir.clone();
+    ret.extend(
+        special
+            .components()
+            .filter(|c| !matches!(c, Component::RootDir | Component::Prefix(_))),
+    );
+
+    ret
 }
 
 /// Supplied a relative path, this function returns that
@@ -183,23 +170,65 @@ pub fn relative_to_script(state: &State, rel: &PathBuf) -> PathBuf {
     ret
 }
 
-/// Given a origin file, create a backup file with a unique name
-pub fn backup_file(original: &PathBuf) -> Result<(), CommonErrors> {
-    let mut backup = original.clone();
-    backup.set_extension(
-        backup
-            .extension()
+/// Replace the contents of a file atomically. The data is written to
+/// a temporary file next to the target, flushed to disk, and renamed
+/// over the target.
+pub fn write_atomically(file: &PathBuf, data: &[u8]) -> Result<(), std::io::Error> {
+    let mut tmp = file.clone();
+    tmp.set_extension(
+        file.extension()
             .unwrap_or_default()
             .to_str()
             .unwrap()
             .to_string()
-            + ".bak",
+            + ".tmp",
     );
 
-    std::fs::copy(&original, &backup).context(FileCopyError {
-        src: &original,
-        dest: backup,
-    })?;
+    let write = || -> Result<(), std::io::Error> {
+        let mut f = std::fs::File::create(&tmp)?;
+        f.write_all(data)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, &file)?;
+
+        // Make sure the rename itself hits the disk
+        if let Some(parent) = file.parent() {
+            std::fs::File::open(parent)?.sync_all()?;
+        }
+
+        Ok(())
+    };
+
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        e
+    })
+}
+
+/// Gets the SHA-256 of the contents of a file, as hex.
+pub fn hash_file(file: &PathBuf) -> Result<String, CommonErrors> {
+    let mut f = std::fs::File::open(file).context(FileOpenError { file })?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 8192];
+
+    loop {
+        let read = f.read(&mut buf).context(FileReadError { file })?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Gets the contents of a file as text, unless it's binary. Like git,
+/// only the start of it is checked for NUL bytes.
+pub fn as_text(contents: &[u8]) -> Option<&str> {
+    let sniff = &contents[..contents.len().min(8000)];
+    if sniff.contains(&0) {
+        return None;
+    }
 
-    Ok(())
+    std::str::from_utf8(contents).ok()
 }